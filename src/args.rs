@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+
+/// Command line arguments accepted by the server.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Arguments {
+    /// File to write the server's own operational log to.
+    pub log_file: Option<PathBuf>,
+    /// Process id of the editor, used if the client doesn't provide one when initializing.
+    pub client_process_id: Option<u32>,
+    /// Sparse index to look up every crate in instead of crates.io.
+    pub registry: Option<String>,
+}
+
+impl Arguments {
+    /// Parse arguments, excluding the program name. Unknown arguments are ignored, since
+    /// editors sometimes pass flags such as `--stdio` to every language server.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut arguments = Arguments::default();
+
+        while let Some(arg) = args.next() {
+            // Support both `--flag value` and `--flag=value`.
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+
+            if flag == "--log-file" {
+                arguments.log_file = value.or_else(|| args.next()).map(PathBuf::from);
+            } else if flag == "--client-process-id" || flag == "--clientProcessId" {
+                arguments.client_process_id = value
+                    .or_else(|| args.next())
+                    .and_then(|pid| pid.parse().ok());
+            } else if flag == "--registry" {
+                arguments.registry = value.or_else(|| args.next());
+            }
+        }
+
+        arguments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Arguments;
+
+    fn parse(args: &[&str]) -> Arguments {
+        Arguments::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_log_file() {
+        assert_eq!(parse(&[]), Arguments::default());
+        assert_eq!(
+            parse(&["--stdio", "--log-file", "crates-lsp.log"]).log_file,
+            Some(PathBuf::from("crates-lsp.log"))
+        );
+        assert_eq!(
+            parse(&["--log-file=crates-lsp.log"]).log_file,
+            Some(PathBuf::from("crates-lsp.log"))
+        );
+    }
+
+    #[test]
+    fn parse_client_process_id() {
+        assert_eq!(
+            parse(&["--client-process-id", "1234"]).client_process_id,
+            Some(1234)
+        );
+        assert_eq!(
+            parse(&["--stdio", "--clientProcessId=1234"]).client_process_id,
+            Some(1234)
+        );
+        assert_eq!(parse(&["--client-process-id=abc"]).client_process_id, None);
+    }
+
+    #[test]
+    fn parse_registry() {
+        assert_eq!(
+            parse(&["--registry", "sparse+https://registry.example.com/index/"]).registry,
+            Some("sparse+https://registry.example.com/index/".to_string())
+        );
+        assert_eq!(
+            parse(&["--stdio", "--registry=https://registry.example.com/index/"]).registry,
+            Some("https://registry.example.com/index/".to_string())
+        );
+    }
+}
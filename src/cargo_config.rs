@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+use crate::parse;
+
+/// Names of the cargo configuration files within a `.cargo` directory, in the order
+/// cargo prefers them.
+pub const CONFIG_FILES: [&str; 2] = ["config.toml", "config"];
+
+/// Determine whether cargo has been configured to work offline for the given
+/// manifest, either through the `CARGO_NET_OFFLINE` environment variable or the
+/// `[net] offline` setting of a `.cargo/config.toml` file.
+///
+/// Like cargo, configuration files are searched for in the manifest's directory
+/// and all of its ancestors, followed by `$CARGO_HOME`, with the closest taking
+/// precedence. Manifests which aren't local files, such as `untitled:` documents,
+/// only take the environment and `$CARGO_HOME` into account.
+pub fn offline(manifest: Option<&Path>) -> Option<bool> {
+    if let Ok(value) = std::env::var("CARGO_NET_OFFLINE") {
+        return parse_bool(&value);
+    }
+
+    config_directories(manifest)
+        .into_iter()
+        .find_map(|directory| {
+            CONFIG_FILES
+                .into_iter()
+                .find_map(|file| std::fs::read_to_string(directory.join(file)).ok())
+                .and_then(|config| net_offline(&config))
+        })
+}
+
+/// Whether the given path is that of a cargo configuration file, such as
+/// `.cargo/config.toml`.
+pub fn is_config_file(path: &Path) -> bool {
+    path.parent()
+        .is_some_and(|directory| directory.ends_with(".cargo"))
+        && path
+            .file_name()
+            .is_some_and(|file| CONFIG_FILES.iter().any(|config| file == *config))
+}
+
+/// Find the manifest of the workspace the given manifest belongs to, returning its
+/// source. The manifest itself may be the workspace root.
+pub fn workspace_root(manifest: &Path) -> Option<String> {
+    manifest
+        .ancestors()
+        .skip(1)
+        .map(|directory| directory.join("Cargo.toml"))
+        .find_map(|manifest| {
+            let source = std::fs::read_to_string(manifest).ok()?;
+            parse::is_workspace_root(&source).then_some(source)
+        })
+}
+
+/// Find the lockfile of the workspace the given manifest belongs to, which cargo keeps
+/// next to the workspace root's manifest, returning its source.
+pub fn lockfile(manifest: &Path) -> Option<String> {
+    manifest
+        .ancestors()
+        .skip(1)
+        .find_map(|directory| std::fs::read_to_string(directory.join("Cargo.lock")).ok())
+}
+
+/// Directories which may contain cargo configuration for the manifest, closest first.
+fn config_directories(manifest: Option<&Path>) -> Vec<PathBuf> {
+    let mut directories: Vec<PathBuf> = manifest
+        .into_iter()
+        .flat_map(|manifest| manifest.ancestors().skip(1))
+        .map(|directory| directory.join(".cargo"))
+        .collect();
+    directories.extend(cargo_home());
+    directories
+}
+
+fn cargo_home() -> Option<PathBuf> {
+    std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Find the value of `net.offline` within a cargo configuration file, written
+/// either as `offline` within a `[net]` table, or as a dotted `net.offline` key.
+///
+/// Only the `net` table is looked at, so that unrelated settings, such as target
+/// specific flags or profiles, are ignored whatever their shape.
+fn net_offline(config: &str) -> Option<bool> {
+    let config: toml::Table = match toml::from_str(config) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::debug!(%err, "ignoring invalid cargo configuration");
+            return None;
+        }
+    };
+
+    config.get("net")?.get("offline")?.as_bool()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use indoc::indoc;
+
+    use super::{cargo_home, config_directories, is_config_file, net_offline};
+
+    #[test]
+    fn net_offline_setting() {
+        assert_eq!(net_offline("[net]\noffline = true\n"), Some(true));
+        assert_eq!(
+            net_offline("[net]\noffline = false # online\n"),
+            Some(false)
+        );
+        assert_eq!(net_offline("net.offline = true\n"), Some(true));
+        assert_eq!(net_offline("[build]\noffline = true\n"), None);
+        assert_eq!(net_offline("[net]\nretry = 3\n"), None);
+        assert_eq!(net_offline("[net]\noffline = \"yes\"\n"), None);
+        assert_eq!(net_offline("[net\noffline = true\n"), None);
+    }
+
+    #[test]
+    fn net_offline_among_unrelated_settings() {
+        let config = indoc! {r#"
+            [build]
+            target = ["x86_64-unknown-linux-gnu", "wasm32-unknown-unknown"]
+            rustflags = [
+                "-C", "link-arg=-fuse-ld=lld",
+                # [net]
+                "--cfg", "offline = false",
+            ]
+
+            [unstable]
+            build-std = ["core", "alloc"]
+
+            [target.'cfg(all(target_arch = "arm", target_os = "none"))']
+            runner = "probe-rs run --chip STM32F411CEUx"
+            rustflags = ["-C", "link-arg=-Tlink.x"]
+
+            [target.x86_64-pc-windows-msvc]
+            linker = "rust-lld.exe"
+
+            [profile.release.package."*"]
+            opt-level = 3
+
+            [[profile.dev.build-override]]
+
+            [alias]
+            xtask = "run --package xtask --"
+
+            [env]
+            OPENSSL_DIR = { value = "vendor/openssl", relative = true }
+
+            [registries.internal]
+            index = "sparse+https://registry.example.com/index/"
+
+            [source.crates-io]
+            replace-with = "internal"
+
+            [net]
+            git-fetch-with-cli = true
+            offline = true
+        "#};
+
+        assert_eq!(net_offline(config), Some(true));
+    }
+
+    #[test]
+    fn config_directories_without_local_manifest() {
+        let home: Vec<_> = cargo_home().into_iter().collect();
+        assert_eq!(config_directories(None), home);
+
+        let local = config_directories(Some(Path::new("/project/member/Cargo.toml")));
+        assert_eq!(local[0], Path::new("/project/member/.cargo"));
+        assert_eq!(local[1], Path::new("/project/.cargo"));
+        assert_eq!(local[2], Path::new("/.cargo"));
+        assert_eq!(local[3..], home);
+    }
+
+    #[test]
+    fn config_file_paths() {
+        assert!(is_config_file(Path::new("/project/.cargo/config.toml")));
+        assert!(is_config_file(Path::new("/home/user/.cargo/config")));
+        assert!(!is_config_file(Path::new("/project/config.toml")));
+        assert!(!is_config_file(Path::new(
+            "/project/.cargo/credentials.toml"
+        )));
+    }
+}
@@ -0,0 +1,67 @@
+use crate::parse::DependencyWithVersion;
+
+/// Pairs of crates which usually shouldn't both be direct dependencies. This is
+/// kept intentionally small, since it is only a heuristic.
+pub const BUILTIN_CONFLICTS: &[(&str, &str)] = &[("async-std", "tokio"), ("openssl", "rustls")];
+
+/// Find dependencies which conflict with another dependency in the manifest,
+/// returning each offending dependency along with the name of the crate it
+/// conflicts with. Only the latter of the two dependencies is reported.
+pub fn find_conflicts<'a>(
+    dependencies: &[&'a DependencyWithVersion],
+    pairs: &[(String, String)],
+) -> Vec<(&'a DependencyWithVersion, String)> {
+    let mut conflicts = Vec::new();
+
+    for (index, dependency) in dependencies.iter().enumerate() {
+        for other in &dependencies[..index] {
+            let conflicting = pairs.iter().any(|(a, b)| {
+                (*a == dependency.name && *b == other.name)
+                    || (*b == dependency.name && *a == other.name)
+            });
+
+            if conflicting {
+                conflicts.push((*dependency, other.name.clone()));
+            }
+        }
+    }
+
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::VersionReq;
+    use tower_lsp::lsp_types::Range;
+
+    use crate::parse::{DependencyVersion, DependencyWithVersion};
+
+    use super::find_conflicts;
+
+    fn dependency(name: &str) -> DependencyWithVersion {
+        DependencyWithVersion {
+            name: name.to_string(),
+            name_range: None,
+            version: DependencyVersion::Complete {
+                range: Range::default(),
+                version: VersionReq::parse("1").unwrap(),
+            },
+            default_features: true,
+            features: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn finds_conflicting_pairs() {
+        let pairs = vec![("tokio".to_string(), "async-std".to_string())];
+
+        let tokio = dependency("tokio");
+        let serde = dependency("serde");
+        let async_std = dependency("async-std");
+
+        let conflicts = find_conflicts(&[&tokio, &serde, &async_std], &pairs);
+        assert_eq!(conflicts, vec![(&async_std, "tokio".to_string())]);
+
+        assert!(find_conflicts(&[&tokio, &serde], &pairs).is_empty());
+    }
+}
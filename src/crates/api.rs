@@ -1,25 +1,48 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder, Url};
 use semver::Version;
 use serde::Deserialize;
+use time::OffsetDateTime;
 
-use super::{default_client, CrateError, CrateLookup};
+use super::{
+    selectable_features, CrateError, CrateLookup, Lookup, PublishedVersion, Release, SharedClient,
+    DEFAULT_API_BASE_URL,
+};
 
 #[derive(Debug, Clone)]
 pub struct CrateApi {
-    client: Client,
+    client: SharedClient,
+    /// Shared between clones, so it can be configured once the settings are known.
+    base_url: Arc<RwLock<String>>,
 }
 
 #[async_trait]
 impl CrateLookup for CrateApi {
-    fn client(&self) -> &Client {
-        &self.client
+    fn client(&self) -> Client {
+        self.client.get()
+    }
+
+    fn request(&self, url: &str) -> Result<RequestBuilder, CrateError> {
+        self.client.request(url)
+    }
+
+    fn api_base_url(&self) -> String {
+        self.base_url
+            .read()
+            .expect("base url lock poisoned")
+            .clone()
     }
 
-    async fn get_latest_version(self, crate_name: String) -> Result<Version, CrateError> {
+    async fn get_latest_version(
+        self,
+        crate_name: String,
+        _etag: Option<String>,
+    ) -> Result<Lookup, CrateError> {
         let response = self
-            .client
-            .get(&format!("https://crates.io/api/v1/crates/{crate_name}"))
+            .request(&self.endpoint(&format!("crates/{crate_name}")))?
             .send()
             .await
             .map_err(CrateError::transport)?;
@@ -29,28 +52,154 @@ impl CrateLookup for CrateApi {
             pub max_stable_version: Version,
         }
 
+        #[derive(Deserialize)]
+        struct CrateVersion {
+            pub num: Version,
+            #[serde(with = "time::serde::rfc3339")]
+            pub created_at: OffsetDateTime,
+            #[serde(default)]
+            pub yanked: bool,
+        }
+
         #[derive(Deserialize)]
         struct Crate {
             #[serde(rename = "crate")]
             pub inner: CrateInner,
+            #[serde(default)]
+            pub versions: Vec<CrateVersion>,
         }
         let details: Crate = response.json().await?;
 
-        Ok(details.inner.max_stable_version)
+        let published_at = details
+            .versions
+            .iter()
+            .find(|version| version.num == details.inner.max_stable_version)
+            .map(|version| version.created_at);
+
+        let yanked = details
+            .versions
+            .iter()
+            .filter(|version| version.yanked)
+            .map(|version| version.num.clone())
+            .collect();
+
+        Ok(Lookup::Fetched {
+            release: Release {
+                version: details.inner.max_stable_version,
+                published_at,
+                yanked,
+            },
+            etag: None,
+        })
+    }
+
+    async fn versions(&self, crate_name: &str) -> Result<Vec<PublishedVersion>, CrateError> {
+        let response = self
+            .request(&self.endpoint(&format!("crates/{crate_name}/versions")))?
+            .send()
+            .await
+            .map_err(CrateError::transport)?;
+
+        #[derive(Deserialize)]
+        struct CrateVersion {
+            pub num: Version,
+            pub yanked: bool,
+            #[serde(with = "time::serde::rfc3339")]
+            pub created_at: OffsetDateTime,
+            #[serde(default)]
+            pub rust_version: Option<String>,
+            #[serde(default)]
+            pub features: HashMap<String, Vec<String>>,
+        }
+
+        #[derive(Deserialize)]
+        struct Versions {
+            pub versions: Vec<CrateVersion>,
+        }
+        let details: Versions = response.json().await?;
+
+        Ok(details
+            .versions
+            .into_iter()
+            .map(|version| PublishedVersion {
+                version: version.num,
+                yanked: version.yanked,
+                published_at: Some(version.created_at),
+                rust_version: version.rust_version,
+                // Optional dependencies aren't listed, so any implicit features are missing.
+                features: selectable_features(&version.features, []),
+            })
+            .collect())
     }
 }
 
-impl Default for CrateApi {
-    fn default() -> Self {
+impl CrateApi {
+    pub fn new(client: SharedClient) -> Self {
         CrateApi {
-            client: default_client(),
+            client,
+            base_url: Arc::new(RwLock::new(DEFAULT_API_BASE_URL.to_string())),
         }
     }
+
+    /// Use the crates.io compatible API at the given base URL, such as a proxy or a
+    /// local mock, rather than crates.io itself.
+    pub fn set_base_url(&self, base_url: &str) {
+        *self.base_url.write().expect("base url lock poisoned") =
+            base_url.trim().trim_end_matches('/').to_string();
+    }
+
+    /// URL of the given path within version 1 of the API.
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/api/v1/{path}", self.api_base_url())
+    }
+
+    /// Look up the repository URL declared by the crate, if any. The repository is
+    /// chosen by the crate's author, so only `http` and `https` URLs are returned.
+    pub async fn repository(&self, crate_name: &str) -> Result<Option<Url>, CrateError> {
+        let response = self
+            .request(&self.endpoint(&format!("crates/{crate_name}")))?
+            .send()
+            .await
+            .map_err(CrateError::transport)?;
+
+        #[derive(Deserialize)]
+        struct CrateInner {
+            #[serde(default)]
+            pub repository: Option<String>,
+        }
+
+        #[derive(Deserialize)]
+        struct Crate {
+            #[serde(rename = "crate")]
+            pub inner: CrateInner,
+        }
+        let details: Crate = response.json().await?;
+
+        Ok(details.inner.repository.as_deref().and_then(repository_url))
+    }
+}
+
+/// Parse a crate's declared repository, refusing anything but `http` and `https`
+/// URLs, as those such as `file:` or `javascript:` could do more than open a page.
+fn repository_url(repository: &str) -> Option<Url> {
+    Url::parse(repository.trim())
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+impl Default for CrateApi {
+    fn default() -> Self {
+        CrateApi::new(SharedClient::default())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::crates::{api::CrateApi, cache::CrateCache, CrateLookup};
+    use crate::crates::{
+        api::{repository_url, CrateApi},
+        cache::CrateCache,
+        CrateError, CrateLookup,
+    };
 
     #[tokio::test]
     async fn get_common_crates() {
@@ -59,9 +208,60 @@ mod tests {
         let cache = CrateCache::default();
 
         let versions = api
-            .fetch_versions(cache, &["serde", "log", "tracing", "crate-does-not-exist"])
+            .fetch_versions(
+                cache,
+                &["serde", "log", "tracing", "crate-does-not-exist"],
+                None,
+            )
             .await;
 
         println!("{versions:#?}");
     }
+
+    #[test]
+    fn base_url_trailing_slash() {
+        let api = CrateApi::default();
+        assert_eq!(
+            api.endpoint("crates/serde"),
+            "https://crates.io/api/v1/crates/serde"
+        );
+
+        for base_url in ["http://localhost:8080", "http://localhost:8080/"] {
+            api.set_base_url(base_url);
+            assert_eq!(
+                api.endpoint("crates/serde"),
+                "http://localhost:8080/api/v1/crates/serde"
+            );
+            assert_eq!(api.clone().api_base_url(), "http://localhost:8080");
+        }
+    }
+
+    #[tokio::test]
+    async fn disallowed_host_is_refused() {
+        let api = CrateApi::default();
+        api.set_base_url("https://crates.example.com");
+
+        assert!(matches!(
+            api.clone().get_latest_version("serde".to_string(), None).await,
+            Err(CrateError::HostNotAllowed(host)) if host == "crates.example.com"
+        ));
+        assert!(matches!(
+            api.search_crates(&"serde".to_string(), 5).await,
+            Err(CrateError::HostNotAllowed(_))
+        ));
+    }
+
+    #[test]
+    fn repository_url_schemes() {
+        assert_eq!(
+            repository_url("https://github.com/serde-rs/serde").map(String::from),
+            Some("https://github.com/serde-rs/serde".to_string())
+        );
+        assert!(repository_url("http://example.com/repo").is_some());
+
+        assert_eq!(repository_url("file:///etc/passwd"), None);
+        assert_eq!(repository_url("javascript:alert(1)"), None);
+        assert_eq!(repository_url("vscode://settings"), None);
+        assert_eq!(repository_url(""), None);
+    }
 }
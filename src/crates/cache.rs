@@ -1,41 +1,330 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use tokio::sync::RwLock;
+use tokio::sync::{watch, RwLock};
 
-const CRATE_CACHE_DIR: &str = "./.lapce/plugins/crates-lsp/crates.io";
+use super::clock::SharedClock;
+use super::{sparse::DEFAULT_INDEX_URL, Crate, PublishedVersion, Release, DEFAULT_API_BASE_URL};
+
+const CRATE_CACHE_DIR: &str = "./.lapce/plugins/crates-lsp";
+
+/// Namespace of crates.io within the cache, also used when no registry is given.
+pub const CRATES_IO_NAMESPACE: &str = "crates.io";
+
+/// Number of crates kept in memory, unless configured otherwise.
+pub const DEFAULT_MAX_CACHE_ENTRIES: usize = 10_000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Fetch {
     pub version: Option<Version>,
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub published_at: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub yanked: Vec<Version>,
+    /// ETag of the registry response, used for revalidating the result once expired.
+    #[serde(default)]
+    pub etag: Option<String>,
     #[serde(with = "time::serde::iso8601")]
     pub expires_at: OffsetDateTime,
 }
 
+impl Fetch {
+    pub fn release(&self) -> Option<Release> {
+        self.version.clone().map(|version| Release {
+            version,
+            published_at: self.published_at,
+            yanked: self.yanked.clone(),
+        })
+    }
+}
+
+/// In-memory tier of the [`CrateCache`], evicting the least recently used crate
+/// once full. Evicted crates remain available from the file cache.
+#[derive(Debug)]
+struct Memory {
+    entries: HashMap<String, (Fetch, u64)>,
+    capacity: usize,
+    /// Monotonic counter recording when each entry was last used.
+    clock: u64,
+}
+
+/// Namespace of the registry at the given index or API base URL within the cache, so
+/// that crates of different registries never share entries. crates.io is the same
+/// registry whether reached through its API or its sparse index.
+pub fn namespace(registry_url: &str) -> String {
+    let registry_url = registry_url.trim();
+    let registry_url = registry_url
+        .strip_prefix("sparse+")
+        .unwrap_or(registry_url)
+        .trim_end_matches('/');
+
+    if registry_url == DEFAULT_INDEX_URL || registry_url == DEFAULT_API_BASE_URL {
+        return CRATES_IO_NAMESPACE.to_string();
+    }
+
+    let registry_url = registry_url
+        .strip_prefix("https://")
+        .or_else(|| registry_url.strip_prefix("http://"))
+        .unwrap_or(registry_url);
+    let namespace: String = registry_url
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+
+    // The namespace is a directory within the cache, which `.` and `..` would escape.
+    if namespace.chars().all(|c| c == '.') {
+        return "_".repeat(namespace.len().max(1));
+    }
+    namespace
+}
+
+impl Memory {
+    fn get(&mut self, key: &str) -> Option<Fetch> {
+        self.clock += 1;
+        let (fetch, last_used) = self.entries.get_mut(key)?;
+        *last_used = self.clock;
+        Some(fetch.clone())
+    }
+
+    /// Insert the fetch, returning how many entries were evicted to make room for it.
+    fn insert(&mut self, key: String, fetch: Fetch) -> u64 {
+        self.clock += 1;
+        self.entries.insert(key, (fetch, self.clock));
+        self.shrink()
+    }
+
+    fn shrink(&mut self) -> u64 {
+        let mut evicted = 0;
+        while self.entries.len() > self.capacity.max(1) {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(crate_name, _)| crate_name.clone())
+            else {
+                break;
+            };
+
+            self.entries.remove(&oldest);
+            evicted += 1;
+        }
+        evicted
+    }
+}
+
+/// Counters describing the effectiveness of the [`CrateCache`].
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// Snapshot of the [`CrateCache`] counters, as reported by the `crates-lsp.cacheStats` command.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Fraction of lookups answered by the cache, if any lookups have been made.
+    pub hit_rate: Option<f64>,
+}
+
+/// Outcome of a lookup which may still be in progress, `None` until it completes.
+type Pending = watch::Receiver<Option<Option<Release>>>;
+
+/// Registry lookups currently in progress, keyed by crate name.
+type InFlightLookups = Arc<Mutex<HashMap<String, Pending>>>;
+
+/// Whether a registry lookup of a crate is already in progress, as determined
+/// by [`CrateCache::begin_lookup`].
+pub enum InFlight {
+    /// No lookup was in progress, so the caller must perform it and report the result.
+    Leader(LookupGuard),
+    /// Another lookup of the crate is in progress, whose result can be awaited.
+    Follower(PendingLookup),
+}
+
+/// Registers a lookup as being in progress until it is finished or dropped.
+pub struct LookupGuard {
+    crate_name: String,
+    sender: watch::Sender<Option<Option<Release>>>,
+    in_flight: InFlightLookups,
+}
+
+impl LookupGuard {
+    /// Share the result of the lookup with everyone waiting for it.
+    pub fn finish(self, release: Option<Release>) {
+        self.sender.send_replace(Some(release));
+    }
+}
+
+impl Drop for LookupGuard {
+    fn drop(&mut self) {
+        self.in_flight
+            .lock()
+            .expect("in-flight lookups lock poisoned")
+            .remove(&self.crate_name);
+    }
+}
+
+/// A lookup of a crate performed by someone else.
+pub struct PendingLookup(Pending);
+
+impl PendingLookup {
+    /// Wait for the lookup to complete, returning `None` if it was abandoned, such as
+    /// when the request which started it was cancelled.
+    pub async fn wait(mut self) -> Option<Option<Release>> {
+        self.0
+            .wait_for(Option::is_some)
+            .await
+            .ok()
+            .and_then(|release| release.clone())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CrateCache {
-    crates: Arc<RwLock<HashMap<String, Fetch>>>,
+    crates: Arc<RwLock<Memory>>,
+    counters: Arc<Counters>,
+    clock: SharedClock,
+    in_flight: InFlightLookups,
+    /// Directory of the file cache, or `None` if crates are only cached in memory.
+    directory: Option<Arc<Path>>,
+    /// Registry whose crates are cached, see [`CrateCache::for_registry`].
+    namespace: Arc<str>,
 }
 
 impl Default for CrateCache {
     fn default() -> Self {
-        std::fs::create_dir_all(CRATE_CACHE_DIR)
-            .expect("Failed to create cargo crate version cache dir.");
+        CrateCache::with_clock(SharedClock::default())
+    }
+}
 
-        std::fs::write(Path::new(CRATE_CACHE_DIR).join(".gitignore"), "*")
-            .expect("failed to create crates-lsp .gitignore file.");
+impl CrateCache {
+    /// Create a cache which determines whether lookups have expired using the given clock.
+    pub fn with_clock(clock: SharedClock) -> Self {
+        let candidates = [
+            PathBuf::from(CRATE_CACHE_DIR),
+            std::env::temp_dir().join("crates-lsp"),
+        ];
+
+        CrateCache::in_directory(cache_directory(&candidates), clock)
+    }
+
+    /// Create a cache whose file cache is kept in the given directory, or only in
+    /// memory if there is none.
+    fn in_directory(directory: Option<PathBuf>, clock: SharedClock) -> Self {
+        CrateCache {
+            crates: Arc::new(RwLock::new(Memory {
+                entries: HashMap::default(),
+                capacity: DEFAULT_MAX_CACHE_ENTRIES,
+                clock: 0,
+            })),
+            counters: Arc::default(),
+            clock,
+            in_flight: Arc::default(),
+            directory: directory.map(Arc::from),
+            namespace: Arc::from(CRATES_IO_NAMESPACE),
+        }
+    }
 
+    /// The cache of crates looked up in the registry at the given index or API base URL.
+    /// Its entries are kept apart from those of other registries, both in memory and on
+    /// disk, while still sharing the capacity and statistics of this cache.
+    pub fn for_registry(&self, registry_url: &str) -> CrateCache {
         CrateCache {
-            crates: Arc::new(RwLock::new(HashMap::default())),
+            namespace: Arc::from(namespace(registry_url)),
+            ..self.clone()
         }
     }
+
+    /// Key of the crate within the cache, which is also its path within the file cache.
+    /// Crate names are lowercase in the registry, so differently capitalized names of
+    /// the same crate share a single entry.
+    fn key(&self, crate_name: &str) -> String {
+        format!("{}/{}", self.namespace, crate_name.to_lowercase())
+    }
+
+    /// Location of the entry with the given key within the file cache, if there is one.
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.directory.as_ref().map(|directory| directory.join(key))
+    }
+
+    fn persist(&self, key: &str, fetch: &Fetch) -> std::io::Result<()> {
+        let Some(path) = self.path(key) else {
+            return Ok(());
+        };
+
+        let content = serde_json::to_string(fetch);
+        let content = content.as_deref().unwrap_or("{}");
+        match std::fs::write(&path, content) {
+            // The directory of a registry is only created once it has crates to cache.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&path, content)
+            }
+            result => result,
+        }
+    }
+
+    /// Register a registry lookup of the crate, unless one is already in progress,
+    /// so that concurrent fetches of the same crate share a single request.
+    pub fn begin_lookup(&self, crate_name: &str) -> InFlight {
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .expect("in-flight lookups lock poisoned");
+
+        let crate_name = self.key(crate_name);
+        if let Some(pending) = in_flight.get(&crate_name) {
+            return InFlight::Follower(PendingLookup(pending.clone()));
+        }
+
+        let (sender, receiver) = watch::channel(None);
+        in_flight.insert(crate_name.clone(), receiver);
+
+        InFlight::Leader(LookupGuard {
+            crate_name,
+            sender,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Number of registry lookups currently in progress.
+    pub fn lookups_in_flight(&self) -> usize {
+        self.in_flight
+            .lock()
+            .expect("in-flight lookups lock poisoned")
+            .len()
+    }
+
+    /// The current time according to the cache's clock, for calculating expiry times.
+    pub fn now(&self) -> OffsetDateTime {
+        self.clock.now()
+    }
 }
 
 pub enum CachedVersion {
     /// Crate was found, and a latest stable version was determined.
-    Known(Version),
+    Known(Release),
 
     /// The crate name is unknown, and does not exist in cache, nor
     /// do we know if the crate might be present in an upstream registry.
@@ -43,12 +332,18 @@ pub enum CachedVersion {
 
     /// Crate was looked up in upstream registries, and was not found.
     DoesNotExist,
+
+    /// A previous lookup has expired, but can be revalidated using its ETag.
+    Expired {
+        release: Option<Release>,
+        etag: String,
+    },
 }
 
-impl From<Option<Version>> for CachedVersion {
-    fn from(value: Option<Version>) -> Self {
+impl From<Option<Release>> for CachedVersion {
+    fn from(value: Option<Release>) -> Self {
         match value {
-            Some(version) => CachedVersion::Known(version),
+            Some(release) => CachedVersion::Known(release),
             None => CachedVersion::DoesNotExist,
         }
     }
@@ -56,51 +351,764 @@ impl From<Option<Version>> for CachedVersion {
 
 impl CrateCache {
     pub async fn get(&self, crate_name: &str) -> CachedVersion {
+        let cached = self.lookup(crate_name).await;
+
+        let counter = match cached {
+            CachedVersion::Known(_) | CachedVersion::DoesNotExist => &self.counters.hits,
+            CachedVersion::Unknown | CachedVersion::Expired { .. } => &self.counters.misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        cached
+    }
+
+    async fn lookup(&self, crate_name: &str) -> CachedVersion {
+        let key = self.key(crate_name);
+
         // Check the in-memory cache first.
-        if let Some(cached) = self.crates.read().await.get(crate_name).cloned() {
+        let memory = self.crates.write().await.get(&key);
+        if let Some(cached) = &memory {
             // Only return the cached result if it is still valid.
-            if OffsetDateTime::now_utc() < cached.expires_at {
-                return cached.version.into();
+            if self.now() < cached.expires_at {
+                return cached.release().into();
             }
         };
 
         // Attempt to load crate informtion from file cache.
-        if let Ok(content) =
-            std::fs::read_to_string(std::path::Path::new(CRATE_CACHE_DIR).join(crate_name))
-        {
+        if let Some(Ok(content)) = self.path(&key).map(std::fs::read_to_string) {
             if let Ok(fetch) = serde_json::from_str::<Fetch>(&content) {
-                if OffsetDateTime::now_utc() < fetch.expires_at {
-                    self.put(crate_name, fetch.version.clone(), fetch.expires_at)
-                        .await;
+                if self.now() < fetch.expires_at {
+                    self.put(
+                        crate_name,
+                        fetch.release(),
+                        fetch.etag.clone(),
+                        fetch.expires_at,
+                    )
+                    .await;
+
+                    return fetch.release().into();
+                }
 
-                    return fetch.version.into();
+                if let Some(etag) = fetch.etag.clone() {
+                    return CachedVersion::Expired {
+                        release: fetch.release(),
+                        etag,
+                    };
                 }
             }
         }
 
+        // Without a file cache, expired entries are revalidated from memory, so that
+        // both tiers treat expiry the same way.
+        if let Some(Fetch {
+            etag: Some(etag), ..
+        }) = &memory
+        {
+            return CachedVersion::Expired {
+                release: memory.as_ref().and_then(Fetch::release),
+                etag: etag.clone(),
+            };
+        }
+
         CachedVersion::Unknown
     }
 
+    /// Get the latest known release of a crate regardless of whether the lookup has
+    /// expired, for use when the registry can't be reached. Crates which have never
+    /// been looked up are omitted.
+    pub async fn cached_versions(&self, crate_names: &[&str]) -> HashMap<String, Option<Release>> {
+        let mut versions = HashMap::new();
+
+        for crate_name in crate_names {
+            let key = self.key(crate_name);
+            let memory = self.crates.write().await.get(&key);
+            let fetch = memory.or_else(|| {
+                let content = std::fs::read_to_string(self.path(&key)?).ok()?;
+                serde_json::from_str::<Fetch>(&content).ok()
+            });
+
+            if let Some(fetch) = fetch {
+                versions.insert(crate_name.to_string(), fetch.release());
+            }
+        }
+
+        versions
+    }
+
+    /// Evict a crate from both the in-memory and file cache.
+    pub async fn remove(&self, crate_name: &str) {
+        let key = self.key(crate_name);
+        self.crates.write().await.entries.remove(&key);
+
+        if let Some(path) = self.path(&key) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
     pub async fn put(
         &self,
         crate_name: &str,
-        version: Option<Version>,
+        release: Option<Release>,
+        etag: Option<String>,
         expires_at: OffsetDateTime,
     ) {
         let fetch = Fetch {
-            version,
+            published_at: release.as_ref().and_then(|release| release.published_at),
+            yanked: release
+                .as_ref()
+                .map(|release| release.yanked.clone())
+                .unwrap_or_default(),
+            version: release.map(|release| release.version),
+            etag,
             expires_at,
         };
 
-        std::fs::write(
-            Path::new(CRATE_CACHE_DIR).join(crate_name),
-            serde_json::to_string(&fetch).as_deref().unwrap_or("{}"),
-        )
-        .unwrap();
+        // The in-memory cache still works if the file can't be written.
+        let key = self.key(crate_name);
+        if let Err(err) = self.persist(&key, &fetch) {
+            tracing::warn!(crate_name, %err, "failed to write crate to file cache");
+        }
+
+        let evicted = self.crates.write().await.insert(key, fetch);
+        self.counters
+            .evictions
+            .fetch_add(evicted, Ordering::Relaxed);
+    }
+
+    /// Write every crate kept in memory to the file cache, so that it is available
+    /// to the next session. Since [`CrateCache::put`] already writes through to disk,
+    /// this only ensures that nothing is lost when the server shuts down.
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let crates = self.crates.read().await;
+        for (key, (fetch, _)) in &crates.entries {
+            self.persist(key, fetch)?;
+        }
+        Ok(())
+    }
+
+    /// Limit the number of crates kept in memory, evicting the least recently used
+    /// ones if there are currently more.
+    pub async fn set_capacity(&self, capacity: usize) {
+        let mut crates = self.crates.write().await;
+        crates.capacity = capacity;
+        let evicted = crates.shrink();
+        self.counters
+            .evictions
+            .fetch_add(evicted, Ordering::Relaxed);
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let crates = self.crates.read().await;
+        let hits = self.counters.hits.load(Ordering::Relaxed);
+        let misses = self.counters.misses.load(Ordering::Relaxed);
 
+        CacheStats {
+            entries: crates.entries.len(),
+            capacity: crates.capacity,
+            hits,
+            misses,
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            hit_rate: (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64),
+        }
+    }
+}
+
+/// Find the first of the candidate directories which can be used for the file cache,
+/// creating it if necessary. If none can, such as when the file system is read-only,
+/// crates are only cached in memory rather than failing to start.
+fn cache_directory(candidates: &[PathBuf]) -> Option<PathBuf> {
+    let prepare = |directory: &Path| {
+        std::fs::create_dir_all(directory)?;
+        std::fs::write(directory.join(".gitignore"), "*")
+    };
+
+    let directory = candidates
+        .iter()
+        .find(|directory| match prepare(directory) {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::warn!(directory = %directory.display(), %err, "unable to use cache directory");
+                false
+            }
+        });
+
+    if directory.is_none() {
+        tracing::warn!("no usable cache directory, only caching crates in memory");
+    }
+
+    directory.cloned()
+}
+
+/// How long crate search results remain valid.
+const SEARCH_TTL: time::Duration = time::Duration::seconds(60);
+
+#[derive(Debug, Clone)]
+struct Search {
+    pub crates: Vec<Crate>,
+    /// Whether the search returned fewer results than requested, in which case
+    /// it holds every crate matching the query, rather than only the most relevant.
+    pub complete: bool,
+    pub expires_at: OffsetDateTime,
+}
+
+/// Short-lived in-memory cache of crate name searches, used for completions.
+#[derive(Debug, Clone, Default)]
+pub struct SearchCache {
+    searches: Arc<RwLock<HashMap<String, Search>>>,
+    clock: SharedClock,
+}
+
+impl SearchCache {
+    /// Get the search results for the given query, if known. If the exact query
+    /// hasn't been searched for, results for a shorter prefix of it are filtered
+    /// and reused instead, so that typing a crate name doesn't trigger a new search
+    /// on every keystroke. Only searches which weren't cut off by the result limit
+    /// are reused this way, as the crates matching a longer query might otherwise
+    /// have been left out.
+    pub async fn get(&self, query: &str) -> Option<Vec<Crate>> {
+        let now = self.clock.now();
+        let searches = self.searches.read().await;
+
+        if let Some(search) = searches.get(query) {
+            if now < search.expires_at {
+                return Some(search.crates.clone());
+            }
+        }
+
+        searches
+            .iter()
+            .filter(|(prefix, search)| {
+                query.starts_with(prefix.as_str()) && search.complete && now < search.expires_at
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, search)| {
+                search
+                    .crates
+                    .iter()
+                    .filter(|krate| krate.name.starts_with(query))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .filter(|crates| !crates.is_empty())
+    }
+
+    /// Store the results of searching for the given query, having asked for
+    /// at most `limit` crates.
+    pub async fn put(&self, query: &str, crates: Vec<Crate>, limit: u8) {
+        let now = self.clock.now();
+        let mut searches = self.searches.write().await;
+
+        // Evict expired searches, so the cache doesn't grow indefinitely.
+        searches.retain(|_, search| now < search.expires_at);
+        searches.insert(
+            query.to_string(),
+            Search {
+                complete: crates.len() < usize::from(limit),
+                crates,
+                expires_at: now + SEARCH_TTL,
+            },
+        );
+    }
+}
+
+/// How long the lists of every published version of a crate remain valid.
+const VERSIONS_TTL: time::Duration = time::Duration::minutes(10);
+
+#[derive(Debug, Clone)]
+struct Versions {
+    pub versions: Vec<PublishedVersion>,
+    pub expires_at: OffsetDateTime,
+}
+
+/// Short-lived in-memory cache of every published version of crates, used for
+/// pre-release diagnostics, feature completions and hovers. Like the [`CrateCache`],
+/// crates are kept apart for each registry.
+#[derive(Debug, Clone)]
+pub struct VersionsCache {
+    crates: Arc<RwLock<HashMap<String, Versions>>>,
+    clock: SharedClock,
+}
+
+impl Default for VersionsCache {
+    fn default() -> Self {
+        VersionsCache::with_clock(SharedClock::default())
+    }
+}
+
+impl VersionsCache {
+    /// Create a cache which determines whether lists have expired using the given clock.
+    pub fn with_clock(clock: SharedClock) -> Self {
+        VersionsCache {
+            crates: Arc::default(),
+            clock,
+        }
+    }
+
+    /// Get every published version of the crate in the registry at the given index or
+    /// API base URL, if listed recently.
+    pub async fn get(&self, registry_url: &str, crate_name: &str) -> Option<Vec<PublishedVersion>> {
+        let now = self.clock.now();
+        let crates = self.crates.read().await;
+
+        crates
+            .get(&versions_key(registry_url, crate_name))
+            .filter(|versions| now < versions.expires_at)
+            .map(|versions| versions.versions.clone())
+    }
+
+    pub async fn put(&self, registry_url: &str, crate_name: &str, versions: Vec<PublishedVersion>) {
+        let now = self.clock.now();
+        let mut crates = self.crates.write().await;
+
+        // Evict expired lists, so the cache doesn't grow indefinitely.
+        crates.retain(|_, versions| now < versions.expires_at);
+        crates.insert(
+            versions_key(registry_url, crate_name),
+            Versions {
+                versions,
+                expires_at: now + VERSIONS_TTL,
+            },
+        );
+    }
+
+    pub async fn remove(&self, registry_url: &str, crate_name: &str) {
         self.crates
             .write()
             .await
-            .insert(crate_name.to_string(), fetch);
+            .remove(&versions_key(registry_url, crate_name));
+    }
+}
+
+fn versions_key(registry_url: &str, crate_name: &str) -> String {
+    format!("{}/{}", namespace(registry_url), crate_name.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+    use time::{Duration, OffsetDateTime};
+
+    use crate::crates::{Crate, PublishedVersion, Release, DEFAULT_API_BASE_URL};
+
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::crates::clock::{ManualClock, SharedClock};
+
+    use super::{
+        cache_directory, namespace, CachedVersion, CrateCache, SearchCache, VersionsCache,
+    };
+
+    fn release(version: &str) -> Release {
+        Release {
+            version: Version::parse(version).unwrap(),
+            published_at: None,
+            yanked: Vec::new(),
+        }
+    }
+
+    /// A cache whose file cache is in a directory of its own within the temporary
+    /// directory, rather than the user's cache directory.
+    fn temp_cache(test: &str, clock: SharedClock) -> (CrateCache, PathBuf) {
+        let directory = std::env::temp_dir().join(format!("crates-lsp-test-{test}"));
+        let _ = std::fs::remove_dir_all(&directory);
+        let cache =
+            CrateCache::in_directory(cache_directory(std::slice::from_ref(&directory)), clock);
+        (cache, directory)
+    }
+
+    #[tokio::test]
+    async fn expired_fetch_with_etag() {
+        let (cache, directory) = temp_cache("etag", SharedClock::default());
+        let release = release("1.2.3");
+        let expired = OffsetDateTime::now_utc() - Duration::hours(1);
+
+        cache
+            .put("crates-lsp-test-etag", Some(release.clone()), None, expired)
+            .await;
+        assert!(matches!(
+            cache.get("crates-lsp-test-etag").await,
+            CachedVersion::Unknown
+        ));
+
+        cache
+            .put(
+                "crates-lsp-test-etag",
+                Some(release.clone()),
+                Some("\"abc\"".to_string()),
+                expired,
+            )
+            .await;
+        assert!(matches!(
+            cache.get("crates-lsp-test-etag").await,
+            CachedVersion::Expired { release: Some(cached), etag } if cached == release && etag == "\"abc\""
+        ));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() {
+        let (cache, directory) = temp_cache("lru", SharedClock::default());
+        cache.set_capacity(2).await;
+
+        let release = release("1.2.3");
+        let expires_at = OffsetDateTime::now_utc() + Duration::hours(1);
+
+        for crate_name in ["crates-lsp-test-lru-a", "crates-lsp-test-lru-b"] {
+            cache
+                .put(crate_name, Some(release.clone()), None, expires_at)
+                .await;
+        }
+
+        // Using a makes b the least recently used.
+        assert!(matches!(
+            cache.get("crates-lsp-test-lru-a").await,
+            CachedVersion::Known(_)
+        ));
+        cache
+            .put("crates-lsp-test-lru-c", Some(release), None, expires_at)
+            .await;
+
+        {
+            let memory = cache.crates.read().await;
+            assert!(memory
+                .entries
+                .contains_key("crates.io/crates-lsp-test-lru-a"));
+            assert!(!memory
+                .entries
+                .contains_key("crates.io/crates-lsp-test-lru-b"));
+            assert!(memory
+                .entries
+                .contains_key("crates.io/crates-lsp-test-lru-c"));
+        }
+
+        // Evicted crates are still served from the file cache.
+        assert!(matches!(
+            cache.get("crates-lsp-test-lru-b").await,
+            CachedVersion::Known(_)
+        ));
+        assert!(matches!(
+            cache.get("crates-lsp-test-lru-missing").await,
+            CachedVersion::Unknown
+        ));
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.capacity, 2);
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!(stats.evictions, 2);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn expires_at_boundary() {
+        let clock = Arc::new(ManualClock::new(OffsetDateTime::UNIX_EPOCH));
+        let (cache, directory) = temp_cache("boundary", SharedClock::new(clock.clone()));
+        let release = release("1.2.3");
+
+        cache
+            .put(
+                "crates-lsp-test-boundary",
+                Some(release),
+                None,
+                cache.now() + Duration::hours(1),
+            )
+            .await;
+
+        clock.advance(Duration::hours(1) - Duration::seconds(1));
+        assert!(matches!(
+            cache.get("crates-lsp-test-boundary").await,
+            CachedVersion::Known(_)
+        ));
+
+        clock.advance(Duration::seconds(1));
+        assert!(matches!(
+            cache.get("crates-lsp-test-boundary").await,
+            CachedVersion::Unknown
+        ));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_fresh_entries_are_reused() {
+        let clock = Arc::new(ManualClock::new(OffsetDateTime::UNIX_EPOCH));
+        let (cache, directory) = temp_cache("fresh", SharedClock::new(clock.clone()));
+        cache.set_capacity(1).await;
+        let release = release("1.2.3");
+        let expires_at = cache.now() + Duration::hours(24);
+
+        for crate_name in ["crates-lsp-test-fresh-a", "crates-lsp-test-fresh-b"] {
+            cache
+                .put(crate_name, Some(release.clone()), None, expires_at)
+                .await;
+        }
+
+        // a has been evicted from memory, so is read from the file cache.
+        clock.advance(Duration::hours(23));
+        assert!(matches!(
+            cache.get("crates-lsp-test-fresh-a").await,
+            CachedVersion::Known(_)
+        ));
+
+        // A day later, neither tier reuses the entries.
+        clock.advance(Duration::hours(1));
+        for crate_name in ["crates-lsp-test-fresh-a", "crates-lsp-test-fresh-b"] {
+            assert!(matches!(
+                cache.get(crate_name).await,
+                CachedVersion::Unknown
+            ));
+        }
+        std::fs::remove_dir_all(directory).unwrap();
+
+        // Without a file cache, expired entries can still be revalidated.
+        let memory_only = CrateCache::in_directory(None, SharedClock::new(clock.clone()));
+        memory_only
+            .put(
+                "crates-lsp-test-fresh-c",
+                Some(release.clone()),
+                Some("\"abc\"".to_string()),
+                memory_only.now() + Duration::hours(24),
+            )
+            .await;
+        clock.advance(Duration::hours(24));
+        assert!(matches!(
+            memory_only.get("crates-lsp-test-fresh-c").await,
+            CachedVersion::Expired { release: Some(cached), etag } if cached == release && etag == "\"abc\""
+        ));
+    }
+
+    #[tokio::test]
+    async fn case_insensitive_names() {
+        let (cache, directory) = temp_cache("case", SharedClock::default());
+        let release = release("1.2.3");
+        let expires_at = OffsetDateTime::now_utc() + Duration::hours(1);
+
+        cache
+            .put("Crates-LSP-Test-Case", Some(release), None, expires_at)
+            .await;
+
+        assert!(directory.join("crates.io/crates-lsp-test-case").exists());
+        assert!(matches!(
+            cache.get("crates-lsp-test-case").await,
+            CachedVersion::Known(_)
+        ));
+        assert!(cache
+            .cached_versions(&["CRATES-LSP-TEST-CASE"])
+            .await
+            .contains_key("CRATES-LSP-TEST-CASE"));
+
+        cache.remove("crates-lsp-test-CASE").await;
+        assert!(matches!(
+            cache.get("crates-lsp-test-case").await,
+            CachedVersion::Unknown
+        ));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn flush_persists_memory() {
+        let (cache, directory) = temp_cache("flush", SharedClock::default());
+        let release = release("1.2.3");
+        let expires_at = OffsetDateTime::now_utc() + Duration::hours(1);
+
+        cache
+            .put("crates-lsp-test-flush", Some(release), None, expires_at)
+            .await;
+
+        // Simulate a write which hasn't reached the disk yet.
+        let path = directory.join("crates.io/crates-lsp-test-flush");
+        std::fs::remove_file(&path).unwrap();
+
+        cache.flush().await.unwrap();
+        assert!(path.exists());
+
+        // A new session starts out warm.
+        assert!(matches!(
+            CrateCache::in_directory(Some(directory.clone()), SharedClock::default())
+                .get("crates-lsp-test-flush")
+                .await,
+            CachedVersion::Known(release) if release.version == Version::new(1, 2, 3)
+        ));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn registries_are_cached_separately() {
+        assert_eq!(namespace("https://index.crates.io/"), "crates.io");
+        assert_eq!(namespace("sparse+https://index.crates.io"), "crates.io");
+        assert_eq!(namespace("https://crates.io"), "crates.io");
+        assert_eq!(
+            namespace("sparse+https://registry.example.com/index/"),
+            "registry.example.com_index"
+        );
+        assert_eq!(namespace("http://localhost:8080"), "localhost_8080");
+        assert_eq!(namespace("https://.."), "__");
+        assert_eq!(namespace("sparse+..."), "___");
+        assert_eq!(namespace(""), "_");
+
+        let (cache, directory) = temp_cache("registry", SharedClock::default());
+        let private = cache.for_registry("https://registry.example.com/index");
+        let release = release("1.2.3");
+        let expires_at = OffsetDateTime::now_utc() + Duration::hours(1);
+
+        private
+            .put(
+                "crates-lsp-test-registry",
+                Some(release.clone()),
+                None,
+                expires_at,
+            )
+            .await;
+        assert!(directory
+            .join("registry.example.com_index/crates-lsp-test-registry")
+            .exists());
+
+        // Neither tier serves the private crate as a crates.io one, nor the other way round.
+        assert!(matches!(
+            cache.get("crates-lsp-test-registry").await,
+            CachedVersion::Unknown
+        ));
+        assert!(cache
+            .cached_versions(&["crates-lsp-test-registry"])
+            .await
+            .is_empty());
+        assert!(matches!(
+            private.get("crates-lsp-test-registry").await,
+            CachedVersion::Known(cached) if cached == release
+        ));
+        assert!(matches!(
+            cache
+                .for_registry("https://crates.io/")
+                .get("crates-lsp-test-registry")
+                .await,
+            CachedVersion::Unknown
+        ));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn cache_directory_fallback() {
+        // Directories can't be created within a file.
+        let file = std::env::temp_dir().join("crates-lsp-test-cache-directory");
+        std::fs::write(&file, "").unwrap();
+        let unusable = file.join("crates.io");
+        let usable = std::env::temp_dir().join("crates-lsp-test-cache-fallback");
+
+        assert_eq!(
+            cache_directory(&[unusable.clone(), usable.clone()]),
+            Some(usable.clone())
+        );
+        assert!(usable.join(".gitignore").exists());
+        assert_eq!(cache_directory(&[unusable]), None);
+
+        std::fs::remove_file(file).unwrap();
+        std::fs::remove_dir_all(usable).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cached_versions_include_expired() {
+        let (cache, directory) = temp_cache("offline", SharedClock::default());
+        let release = release("1.2.3");
+        let expired = OffsetDateTime::now_utc() - Duration::hours(1);
+
+        cache
+            .put(
+                "crates-lsp-test-offline",
+                Some(release.clone()),
+                None,
+                expired,
+            )
+            .await;
+
+        let versions = cache
+            .cached_versions(&["crates-lsp-test-offline", "crates-lsp-test-never-fetched"])
+            .await;
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions["crates-lsp-test-offline"], Some(release));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn crates(names: &[&str]) -> Vec<Crate> {
+        names
+            .iter()
+            .map(|name| Crate {
+                name: name.to_string(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn search_cache_reuses_prefix() {
+        let cache = SearchCache::default();
+        assert_eq!(cache.get("ser").await, None);
+
+        cache
+            .put("ser", crates(&["serde", "serde_json", "serial"]), 5)
+            .await;
+
+        assert_eq!(
+            cache.get("ser").await,
+            Some(crates(&["serde", "serde_json", "serial"]))
+        );
+        assert_eq!(
+            cache.get("serd").await,
+            Some(crates(&["serde", "serde_json"]))
+        );
+
+        // No cached results match, so a new search is needed.
+        assert_eq!(cache.get("servo").await, None);
+        assert_eq!(cache.get("tokio").await, None);
+    }
+
+    #[tokio::test]
+    async fn search_cache_skips_truncated_prefix() {
+        let cache = SearchCache::default();
+
+        // Only the most relevant crates were returned, so other crates starting
+        // with `serde_j` may exist beyond the limit.
+        cache.put("se", crates(&["serde", "serde_json"]), 2).await;
+
+        assert_eq!(
+            cache.get("se").await,
+            Some(crates(&["serde", "serde_json"]))
+        );
+        assert_eq!(cache.get("serde_j").await, None);
+    }
+
+    #[tokio::test]
+    async fn versions_cache_per_registry() {
+        let clock = Arc::new(ManualClock::new(OffsetDateTime::UNIX_EPOCH));
+        let cache = VersionsCache::with_clock(SharedClock::new(clock.clone()));
+        let versions = vec![PublishedVersion {
+            version: Version::new(1, 0, 0),
+            yanked: false,
+            published_at: None,
+            rust_version: None,
+            features: vec!["derive".to_string()],
+        }];
+        let private = "sparse+https://registry.example.com/index/";
+
+        cache
+            .put(DEFAULT_API_BASE_URL, "Serde", versions.clone())
+            .await;
+        assert_eq!(
+            cache.get(DEFAULT_API_BASE_URL, "serde").await,
+            Some(versions.clone())
+        );
+        assert_eq!(cache.get(private, "serde").await, None);
+
+        clock.advance(Duration::minutes(10));
+        assert_eq!(cache.get(DEFAULT_API_BASE_URL, "serde").await, None);
+
+        cache.put(private, "serde", versions).await;
+        cache.remove(private, "serde").await;
+        assert_eq!(cache.get(private, "serde").await, None);
     }
 }
@@ -0,0 +1,63 @@
+use std::{fmt::Debug, sync::Arc};
+
+use time::OffsetDateTime;
+
+/// Source of the current time used for expiring cached lookups, so that tests can
+/// control the passing of time.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> OffsetDateTime;
+}
+
+/// The actual system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// Clock which only moves when told to.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct ManualClock(std::sync::Mutex<OffsetDateTime>);
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(now: OffsetDateTime) -> Self {
+        ManualClock(std::sync::Mutex::new(now))
+    }
+
+    pub fn advance(&self, duration: time::Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> OffsetDateTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Cheaply cloneable handle to a [`Clock`], defaulting to the [`SystemClock`].
+#[derive(Debug, Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    #[cfg(test)]
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        SharedClock(clock)
+    }
+
+    pub fn now(&self) -> OffsetDateTime {
+        self.0.now()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock(Arc::new(SystemClock))
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use semver::Version;
+use time::OffsetDateTime;
+use tokio::{process::Command, sync::RwLock, task::JoinSet};
+
+use super::{clock::SharedClock, AllowedHosts};
+
+/// How long the tags of a repository are cached for.
+const TAGS_TTL: time::Duration = time::Duration::hours(1);
+
+/// How long to wait for a remote to list its tags.
+const LS_REMOTE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+struct Tags {
+    pub latest: Option<(String, Version)>,
+    pub expires_at: OffsetDateTime,
+}
+
+/// Looks up the latest semver tags of git repositories using `git ls-remote`.
+#[derive(Debug, Clone, Default)]
+pub struct GitTags {
+    repositories: Arc<RwLock<HashMap<String, Tags>>>,
+    clock: SharedClock,
+    /// Repositories currently being looked up by [`GitTags::look_up`].
+    in_flight: Arc<Mutex<HashSet<String>>>,
+    /// Hosts which may be contacted, the same as for registry lookups.
+    allowed_hosts: AllowedHosts,
+}
+
+impl GitTags {
+    /// Look up tags only on the given hosts, which should be those allowed for registry
+    /// lookups, so that `allowedHosts` covers every host the server contacts.
+    pub fn new(allowed_hosts: AllowedHosts) -> Self {
+        GitTags {
+            allowed_hosts,
+            ..GitTags::default()
+        }
+    }
+
+    /// Get the latest semver tag of the repository if it was looked up recently, or
+    /// `None` if it has to be looked up first.
+    pub async fn cached(&self, url: &str) -> Option<Option<(String, Version)>> {
+        let repositories = self.repositories.read().await;
+        let tags = repositories.get(url)?;
+        (self.clock.now() < tags.expires_at).then(|| tags.latest.clone())
+    }
+
+    /// Get the latest semver tag of the repository, along with its parsed version.
+    ///
+    /// Failures, such as private repositories requiring authentication or the git
+    /// executable being unavailable, are treated as the repository having no tags,
+    /// as are URLs which aren't accepted by [`is_remote_url`] or whose host isn't allowed.
+    pub async fn latest(&self, url: &str) -> Option<(String, Version)> {
+        if let Some(latest) = self.cached(url).await {
+            return latest;
+        }
+
+        let host = super::host(url).unwrap_or_default();
+        let latest = if !is_remote_url(url) {
+            tracing::warn!(url, "refusing to list tags of git dependency");
+            None
+        } else if !self.allowed_hosts.allows(&host) {
+            tracing::warn!(
+                url,
+                host,
+                "refusing to list tags of host not in allowedHosts"
+            );
+            None
+        } else {
+            match ls_remote_tags(url).await {
+                Some(tags) => latest_tag(&tags),
+                None => {
+                    tracing::debug!(url, "failed to list remote tags");
+                    None
+                }
+            }
+        };
+
+        self.repositories.write().await.insert(
+            url.to_string(),
+            Tags {
+                latest: latest.clone(),
+                expires_at: self.clock.now() + TAGS_TTL,
+            },
+        );
+
+        latest
+    }
+
+    /// Look up the latest tags of the given repositories concurrently, so that they are
+    /// available through [`GitTags::cached`]. Repositories which are already being looked
+    /// up aren't looked up again.
+    pub async fn look_up(&self, urls: Vec<String>) {
+        let mut lookups = JoinSet::new();
+        for url in urls {
+            let newly_in_flight = self
+                .in_flight
+                .lock()
+                .expect("in-flight repositories lock poisoned")
+                .insert(url.clone());
+            if !newly_in_flight {
+                continue;
+            }
+
+            let tags = self.clone();
+            lookups.spawn(async move {
+                tags.latest(&url).await;
+                tags.in_flight
+                    .lock()
+                    .expect("in-flight repositories lock poisoned")
+                    .remove(&url);
+            });
+        }
+
+        while lookups.join_next().await.is_some() {}
+    }
+}
+
+/// Check whether the URL of a git dependency may be passed to `git ls-remote`. Only
+/// `https`, `ssh` and `git` URLs are, so that values such as `--upload-pack=...` can
+/// never be mistaken for options, and local paths or other transports are never used.
+pub fn is_remote_url(url: &str) -> bool {
+    if url.starts_with('-') {
+        return false;
+    }
+
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+
+    // Hosts and users starting with a dash could be passed on to ssh as options.
+    matches!(url.scheme(), "https" | "ssh" | "git")
+        && url.host_str().is_some_and(|host| !host.starts_with('-'))
+        && !url.username().starts_with('-')
+}
+
+async fn ls_remote_tags(url: &str) -> Option<String> {
+    let output = Command::new("git")
+        // The URL must never be interpreted as an option.
+        .args(["ls-remote", "--tags", "--refs", "--", url])
+        // Never prompt for credentials, just fail.
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+
+    let output = tokio::time::timeout(LS_REMOTE_TIMEOUT, output)
+        .await
+        .ok()?
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8(output.stdout).ok()
+}
+
+/// Parse a tag name as a version, allowing for the common `v` prefix.
+pub fn parse_tag(tag: &str) -> Option<Version> {
+    Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+/// Find the latest semver tag in the output of `git ls-remote --tags`, preferring
+/// stable releases over pre-releases.
+fn latest_tag(ls_remote: &str) -> Option<(String, Version)> {
+    let tags: Vec<_> = ls_remote
+        .lines()
+        .filter_map(|line| line.split_once("refs/tags/"))
+        .filter_map(|(_, tag)| Some((tag.to_string(), parse_tag(tag)?)))
+        .collect();
+
+    tags.iter()
+        .filter(|(_, version)| version.pre.is_empty())
+        .max_by(|(_, a), (_, b)| a.cmp(b))
+        .or(tags.iter().max_by(|(_, a), (_, b)| a.cmp(b)))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::Version;
+
+    use super::{is_remote_url, latest_tag, GitTags};
+
+    #[test]
+    fn only_remote_urls() {
+        for url in [
+            "https://github.com/MathiasPius/crates-lsp",
+            "ssh://git@github.com/MathiasPius/crates-lsp.git",
+            "git://example.com/repository.git",
+        ] {
+            assert!(is_remote_url(url), "{url}");
+        }
+
+        for url in [
+            "--upload-pack=touch PWNED",
+            "-c",
+            "file:///tmp/repository",
+            "ext::sh -c touch% PWNED",
+            "http://example.com/repository.git",
+            "ssh://-oProxyCommand=touch%20PWNED/repository",
+            "ssh://-oProxyCommand=touch@example.com/repository",
+            "../repository",
+            "",
+        ] {
+            assert!(!is_remote_url(url), "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn options_are_never_run() {
+        let marker = std::env::temp_dir().join("crates-lsp-test-upload-pack");
+        let _ = std::fs::remove_file(&marker);
+
+        let tags = GitTags::default();
+        let url = format!("--upload-pack=touch {}", marker.display());
+        assert_eq!(tags.latest(&url).await, None);
+        assert!(!marker.exists());
+
+        // Refused URLs are remembered like any other failure.
+        assert_eq!(tags.cached(&url).await, Some(None));
+    }
+
+    #[test]
+    fn latest_tag_prefers_stable() {
+        let ls_remote = [
+            "a1\trefs/tags/v1.0.0",
+            "a2\trefs/tags/v1.2.0",
+            "a3\trefs/tags/1.1.0",
+            "a4\trefs/tags/v2.0.0-rc.1",
+            "a5\trefs/tags/nightly",
+        ]
+        .join("\n");
+
+        assert_eq!(
+            latest_tag(&ls_remote),
+            Some(("v1.2.0".to_string(), Version::new(1, 2, 0)))
+        );
+        assert_eq!(latest_tag("a1\trefs/tags/nightly"), None);
+    }
+}
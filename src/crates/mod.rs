@@ -1,17 +1,27 @@
 pub mod api;
 pub mod cache;
+pub mod clock;
+pub mod git;
 pub mod sparse;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
 
 use async_trait::async_trait;
-use reqwest::{Client, Error};
+use reqwest::{Client, Error, RequestBuilder};
 use semver::Version;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use tokio::sync::mpsc;
+use tracing::{field, Instrument};
 
-use self::cache::{CachedVersion, CrateCache};
+use self::cache::{CachedVersion, CrateCache, InFlight};
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -21,12 +31,33 @@ pub enum CrateError {
     Transport(Box<dyn std::error::Error + Send>),
     Deserialization(serde_json::Error),
     Reqwest(Error),
+    /// The request was refused, since its host isn't listed in `allowedHosts`.
+    HostNotAllowed(String),
 }
 
 impl CrateError {
     pub fn transport(error: impl std::error::Error + Send + 'static) -> Self {
         CrateError::Transport(Box::new(error))
     }
+
+    /// Whether the registry couldn't be reached at all, as opposed to answering that
+    /// the crate doesn't exist or with a response which couldn't be understood.
+    ///
+    /// Requests refused by `allowedHosts` don't count, since the registry was never
+    /// contacted, and asking another one instead is exactly what the setting prevents.
+    pub fn is_unreachable(&self) -> bool {
+        match self {
+            // Redirects are refused if they lead to hosts which aren't allowed.
+            CrateError::Transport(err) => {
+                !err.downcast_ref::<Error>().is_some_and(Error::is_redirect)
+            }
+            CrateError::Reqwest(err) => err.is_connect() || err.is_timeout(),
+            CrateError::NoVersionsFound
+            | CrateError::InvalidCrateName(_)
+            | CrateError::Deserialization(_)
+            | CrateError::HostNotAllowed(_) => false,
+        }
+    }
 }
 
 impl From<Error> for CrateError {
@@ -35,7 +66,113 @@ impl From<Error> for CrateError {
     }
 }
 
-#[derive(Deserialize)]
+/// The newest release of a crate, as determined by a [`CrateLookup`] backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Release {
+    pub version: Version,
+    /// When the release was published, if reported by the registry.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub published_at: Option<OffsetDateTime>,
+    /// Versions of the crate which have been yanked, if reported by the registry.
+    #[serde(default)]
+    pub yanked: Vec<Version>,
+}
+
+/// A single published version of a crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedVersion {
+    pub version: Version,
+    pub yanked: bool,
+    pub published_at: Option<OffsetDateTime>,
+    /// Minimum supported Rust version declared by the release, if any.
+    pub rust_version: Option<String>,
+    /// Features which dependents can enable, sorted by name.
+    pub features: Vec<String>,
+}
+
+/// Collect the features of a release which dependents can enable, given its feature
+/// table and the names of its optional dependencies.
+///
+/// Optional dependencies are features of their own, unless the feature table refers
+/// to them using the `dep:` syntax, in which case they are only enabled internally.
+pub fn selectable_features<'a>(
+    features: impl IntoIterator<Item = (&'a String, &'a Vec<String>)>,
+    optional_dependencies: impl IntoIterator<Item = &'a str>,
+) -> Vec<String> {
+    let features: Vec<_> = features.into_iter().collect();
+
+    let hidden: HashSet<&str> = features
+        .iter()
+        .flat_map(|(_, enables)| enables.iter())
+        .filter_map(|enabled| enabled.strip_prefix("dep:"))
+        .collect();
+
+    let mut selectable: Vec<String> = features
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| !name.starts_with("dep:"))
+        .chain(
+            optional_dependencies
+                .into_iter()
+                .filter(|dependency| !hidden.contains(dependency)),
+        )
+        .map(str::to_string)
+        .collect();
+
+    selectable.sort();
+    selectable.dedup();
+    selectable
+}
+
+/// Find the newest prerelease which hasn't been yanked and is newer than the given
+/// version, if any.
+pub fn newest_prerelease<'a>(
+    versions: &'a [PublishedVersion],
+    newest: &Version,
+) -> Option<&'a Version> {
+    versions
+        .iter()
+        .filter(|published| !published.yanked && !published.version.pre.is_empty())
+        .map(|published| &published.version)
+        .filter(|version| *version > newest)
+        .max()
+}
+
+/// Find the yanked versions newer than the given version, newest first. Pre-releases
+/// are left out unless asked for.
+pub fn newer_yanked<'a>(
+    versions: &'a [PublishedVersion],
+    newest: &Version,
+    include_prereleases: bool,
+) -> Vec<&'a Version> {
+    let mut yanked: Vec<_> = versions
+        .iter()
+        .filter(|published| published.yanked)
+        .map(|published| &published.version)
+        .filter(|version| *version > newest && (include_prereleases || version.pre.is_empty()))
+        .collect();
+
+    yanked.sort_unstable_by(|a, b| b.cmp(a));
+    yanked
+}
+
+/// Outcome of looking up the latest version of a crate in a registry.
+#[derive(Debug)]
+pub enum Lookup {
+    /// The crate was fetched, along with the ETag of the response, if any.
+    Fetched {
+        release: Release,
+        etag: Option<String>,
+    },
+    /// The registry confirmed that the previously fetched release is still current.
+    NotModified,
+}
+
+/// Receives the number of crates fetched so far, along with the total number of
+/// crates being fetched from the registry.
+pub type Progress = mpsc::UnboundedSender<(usize, usize)>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct Crate {
     pub name: String,
 }
@@ -45,16 +182,37 @@ struct Crates {
     pub crates: Vec<Crate>,
 }
 
+/// Base URL of the crates.io API, unless configured otherwise through `apiBaseUrl`.
+pub const DEFAULT_API_BASE_URL: &str = "https://crates.io";
+
 #[async_trait]
 pub trait CrateLookup: Clone + Send + 'static {
-    fn client(&self) -> &Client;
-    async fn search_crates(&self, crate_name: &String) -> Result<Vec<Crate>, CrateError> {
+    fn client(&self) -> Client;
+
+    /// Start a GET request to the given URL. Backends sharing a [`SharedClient`] refuse
+    /// requests to hosts which aren't allowed.
+    fn request(&self, url: &str) -> Result<RequestBuilder, CrateError> {
+        Ok(self.client().get(url))
+    }
+
+    /// Base URL of the crates.io compatible API used for searching crates, without
+    /// a trailing slash.
+    fn api_base_url(&self) -> String {
+        DEFAULT_API_BASE_URL.to_string()
+    }
+
+    async fn search_crates(
+        &self,
+        crate_name: &String,
+        limit: u8,
+    ) -> Result<Vec<Crate>, CrateError> {
         let response = self
-            .client()
-            .get(&format!(
-                "https://crates.io/api/v1/crates?q={}&per_page=5",
-                crate_name
-            ))
+            .request(&format!(
+                "{}/api/v1/crates?q={}&per_page={}",
+                self.api_base_url(),
+                crate_name,
+                limit
+            ))?
             .send()
             .await
             .map_err(CrateError::transport)?;
@@ -63,83 +221,1002 @@ pub trait CrateLookup: Clone + Send + 'static {
         Ok(details.crates)
     }
 
-    async fn get_latest_version(self, crate_name: String) -> Result<Version, CrateError>;
+    /// Look up the latest version of a crate. If the ETag of a previous lookup is
+    /// given, backends supporting conditional requests may answer [`Lookup::NotModified`].
+    async fn get_latest_version(
+        self,
+        crate_name: String,
+        etag: Option<String>,
+    ) -> Result<Lookup, CrateError>;
+
+    /// List every published version of a crate, including yanked ones.
+    async fn versions(&self, crate_name: &str) -> Result<Vec<PublishedVersion>, CrateError>;
 
     // How long to cache a result for.
-    fn time_to_live(_version: &Option<Version>) -> time::Duration {
+    fn time_to_live(_release: &Option<Release>) -> time::Duration {
         time::Duration::days(1)
     }
 
+    /// How long to wait for all lookups of a single [`CrateLookup::fetch_versions`] call.
+    /// Lookups which take longer are left out of the result, but are still cached once
+    /// they complete.
+    fn fetch_deadline(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(15)
+    }
+
+    /// Fetch the latest versions of the given crates, reporting each completed
+    /// registry lookup to `progress`, if given.
     async fn fetch_versions(
         &self,
         cache: CrateCache,
         crate_names: &[&str],
-    ) -> HashMap<String, Option<Version>> {
-        let crate_names: Vec<_> = crate_names.iter().map(|name| name.to_string()).collect();
+        progress: Option<Progress>,
+    ) -> HashMap<String, Option<Release>> {
+        let mut stream = self
+            .fetch_versions_stream(cache, crate_names, progress)
+            .await;
 
         let mut versions = HashMap::new();
+        let collect = async {
+            while let Some((name, release)) = stream.recv().await {
+                versions.insert(name, release);
+            }
+        };
+
+        if tokio::time::timeout(self.fetch_deadline(), collect)
+            .await
+            .is_err()
+        {
+            tracing::warn!("deadline exceeded while fetching crates, returning partial results");
+            finish_in_background(stream);
+        }
 
-        let mut dispatched_tasks = 0;
-        let (tx, mut rx) = mpsc::channel(crate_names.len());
+        versions
+    }
+
+    /// Fetch the latest versions of the given crates, yielding each one as soon as it
+    /// is known, starting with those already cached. Each completed registry lookup is
+    /// reported to `progress`, if given.
+    ///
+    /// Lookups are aborted if the stream is dropped, such as when the request which
+    /// needed them is cancelled by the client. Use [`finish_in_background`] to let them
+    /// complete and be cached regardless.
+    async fn fetch_versions_stream(
+        &self,
+        cache: CrateCache,
+        crate_names: &[&str],
+        progress: Option<Progress>,
+    ) -> VersionStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let span = tracing::info_span!(
+            target: METRICS_TARGET,
+            "cache_lookup",
+            crates = crate_names.len(),
+            hits = field::Empty,
+            expired = field::Empty,
+            misses = field::Empty,
+        );
+        let (mut hits, mut expired) = (0, 0);
+
+        let mut dispatched = Vec::new();
         for crate_name in crate_names {
-            let tx = tx.clone();
+            let crate_name = crate_name.to_string();
 
-            match cache.get(&crate_name).await {
-                CachedVersion::Known(version) => {
-                    versions.insert(crate_name, Some(version));
+            let (previous, etag) = match cache.get(&crate_name).await {
+                CachedVersion::Known(release) => {
+                    tracing::debug!(crate_name, version = %release.version, "cache hit");
+                    let _ = tx.send((crate_name, Some(release)));
+                    hits += 1;
+                    continue;
                 }
                 CachedVersion::DoesNotExist => {
-                    versions.insert(crate_name, None);
+                    tracing::debug!(crate_name, "cache hit for nonexistent crate");
+                    let _ = tx.send((crate_name, None));
+                    hits += 1;
+                    continue;
+                }
+                CachedVersion::Expired { release, etag } => {
+                    tracing::debug!(crate_name, etag, "cache expired, revalidating");
+                    expired += 1;
+                    (release, Some(etag))
                 }
                 CachedVersion::Unknown => {
-                    dispatched_tasks += 1;
-                    let cloned_self = self.clone();
-
-                    tokio::spawn(async move {
-                        match cloned_self.get_latest_version(crate_name.clone()).await {
-                            Ok(version) => tx.send((crate_name, Some(version))).await,
-                            Err(err) => {
-                                println!("{:?}", err);
-                                tx.send((crate_name, None)).await
-                            }
-                        }
-                    });
+                    tracing::debug!(crate_name, "cache miss, fetching from registry");
+                    (None, None)
                 }
             };
+
+            dispatched.push((crate_name, previous, etag));
+        }
+
+        span.record("hits", hits);
+        span.record("expired", expired);
+        span.record("misses", dispatched.len() - expired);
+        drop(span);
+
+        let dispatched_tasks = dispatched.len();
+        if let Some(progress) = &progress {
+            let _ = progress.send((0, dispatched_tasks));
         }
 
-        for _ in 0..dispatched_tasks {
-            let Some((name, version)) = rx.recv().await else {
-                // If the receiver is broken, just ignore the rest of the dispatched tasks
-                // and return whatever we have already.
-                break;
+        let completed = Arc::new(AtomicUsize::new(0));
+        for (crate_name, previous, etag) in dispatched {
+            let tx = tx.clone();
+            let progress = progress.clone();
+            let completed = completed.clone();
+            let cloned_self = self.clone();
+            let cache = cache.clone();
+            let in_flight = cache.begin_lookup(&crate_name);
+
+            let lookup = async move {
+                match in_flight {
+                    InFlight::Leader(guard) => {
+                        let release =
+                            lookup_and_cache(cloned_self, &cache, &crate_name, previous, etag)
+                                .await;
+                        guard.finish(release.clone());
+                        (crate_name, release)
+                    }
+                    // Share the result of the lookup already in progress, unless it is
+                    // abandoned before completing.
+                    InFlight::Follower(pending) => {
+                        tracing::debug!(crate_name, "joining lookup in progress");
+                        let release = match pending.wait().await {
+                            Some(release) => release,
+                            None => {
+                                lookup_and_cache(cloned_self, &cache, &crate_name, previous, etag)
+                                    .await
+                            }
+                        };
+                        (crate_name, release)
+                    }
+                }
             };
 
-            // Set 24h expiration regardless of whether a package was found or not.
-            let expires_at = OffsetDateTime::now_utc().saturating_add(Self::time_to_live(&version));
+            tokio::spawn(async move {
+                let result = tokio::select! {
+                    result = lookup => result,
+                    () = tx.closed() => return,
+                };
+
+                let _ = tx.send(result);
+                if let Some(progress) = &progress {
+                    let completed = completed.fetch_add(1, Ordering::Relaxed) + 1;
+                    let _ = progress.send((completed, dispatched_tasks));
+                }
+            });
+        }
+
+        rx
+    }
+}
+
+/// Looks up crates using the `primary` backend, trying the `secondary` one, if any, for
+/// crates whose lookup failed because the primary registry couldn't be reached, such
+/// as in networks where only one of crates.io and its sparse index is accessible.
+#[derive(Debug, Clone)]
+pub struct WithFallback<P, S> {
+    primary: P,
+    secondary: Option<S>,
+}
+
+impl<P, S> WithFallback<P, S> {
+    pub fn new(primary: P, secondary: Option<S>) -> Self {
+        WithFallback { primary, secondary }
+    }
+}
+
+#[async_trait]
+impl<P: CrateLookup + Sync, S: CrateLookup + Sync> CrateLookup for WithFallback<P, S> {
+    fn client(&self) -> Client {
+        self.primary.client()
+    }
+
+    fn request(&self, url: &str) -> Result<RequestBuilder, CrateError> {
+        self.primary.request(url)
+    }
+
+    fn api_base_url(&self) -> String {
+        self.primary.api_base_url()
+    }
+
+    fn fetch_deadline(&self) -> std::time::Duration {
+        self.primary.fetch_deadline()
+    }
 
-            // Store the result in the cache.
-            cache.put(&name, version.clone(), expires_at).await;
+    async fn get_latest_version(
+        self,
+        crate_name: String,
+        etag: Option<String>,
+    ) -> Result<Lookup, CrateError> {
+        let result = self
+            .primary
+            .get_latest_version(crate_name.clone(), etag.clone())
+            .await;
 
-            versions.insert(name, version);
+        match (result, self.secondary) {
+            (Err(err), Some(secondary)) if err.is_unreachable() => {
+                tracing::debug!(crate_name, ?err, "registry unreachable, falling back");
+                secondary.get_latest_version(crate_name, etag).await
+            }
+            (result, _) => result,
         }
+    }
 
-        versions
+    async fn versions(&self, crate_name: &str) -> Result<Vec<PublishedVersion>, CrateError> {
+        match (self.primary.versions(crate_name).await, &self.secondary) {
+            (Err(err), Some(secondary)) if err.is_unreachable() => {
+                tracing::debug!(crate_name, ?err, "registry unreachable, falling back");
+                secondary.versions(crate_name).await
+            }
+            (result, _) => result,
+        }
     }
 }
 
-pub fn default_client() -> Client {
-    _default_client().unwrap_or_default()
+/// Versions of crates in the order they become known, see
+/// [`CrateLookup::fetch_versions_stream`].
+pub type VersionStream = mpsc::UnboundedReceiver<(String, Option<Release>)>;
+
+/// Let the remaining lookups of the stream complete in the background, so their
+/// results are cached for next time.
+pub fn finish_in_background(mut stream: VersionStream) {
+    tokio::spawn(async move { while stream.recv().await.is_some() {} });
 }
-fn _default_client() -> reqwest::Result<Client> {
-    let builder = Client::builder()
+
+/// Target of the spans meant for collecting metrics, which are documented in the
+/// README and shouldn't change lightly, since downstream tooling may rely on them.
+pub const METRICS_TARGET: &str = "crates_lsp::metrics";
+
+/// How long to keep using an expired release whose revalidation failed, before
+/// trying to revalidate it again.
+const REVALIDATION_RETRY: time::Duration = time::Duration::minutes(5);
+
+/// Look up the latest version of a crate in the registry, storing the result in the cache.
+/// If the lookup fails while revalidating a `previous` release, that release is kept
+/// along with its ETag for a short while, rather than forgetting about the crate.
+async fn lookup_and_cache<L: CrateLookup>(
+    registry: L,
+    cache: &CrateCache,
+    crate_name: &str,
+    previous: Option<Release>,
+    etag: Option<String>,
+) -> Option<Release> {
+    let span = tracing::info_span!(
+        target: METRICS_TARGET,
+        "fetch",
+        crate_name,
+        outcome = field::Empty,
+        elapsed_ms = field::Empty,
+    );
+
+    let started = std::time::Instant::now();
+    let lookup = registry
+        .get_latest_version(crate_name.to_string(), etag.clone())
+        .instrument(span.clone())
+        .await;
+    span.record("elapsed_ms", started.elapsed().as_millis() as u64);
+
+    let (release, etag, retry) = match lookup {
+        Ok(Lookup::Fetched { release, etag }) => {
+            tracing::debug!(crate_name, version = %release.version, "fetched");
+            span.record("outcome", "fetched");
+            (Some(release), etag, false)
+        }
+        Ok(Lookup::NotModified) => {
+            tracing::debug!(crate_name, "not modified");
+            span.record("outcome", "not_modified");
+            (previous, etag, false)
+        }
+        Err(err) if previous.is_some() => {
+            tracing::warn!(
+                crate_name,
+                ?err,
+                "failed to revalidate crate, keeping expired release"
+            );
+            span.record("outcome", "error");
+            (previous, etag, true)
+        }
+        Err(err) => {
+            tracing::warn!(crate_name, ?err, "failed to fetch crate");
+            span.record("outcome", "error");
+            (None, None, false)
+        }
+    };
+    drop(span);
+
+    // Set 24h expiration regardless of whether a package was found or not, unless
+    // the expired release is only kept until it can be revalidated.
+    let time_to_live = if retry {
+        REVALIDATION_RETRY
+    } else {
+        L::time_to_live(&release)
+    };
+    let expires_at = cache.now().saturating_add(time_to_live);
+
+    // Store the result in the cache, even if the deadline has passed, so
+    // that it is available next time.
+    cache
+        .put(crate_name, release.clone(), etag, expires_at)
+        .await;
+
+    release
+}
+
+/// Host of the given URL, if it is a valid URL with a host.
+pub fn host(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+}
+
+/// Hosts which may be contacted, unless configured otherwise through `allowedHosts`.
+pub const DEFAULT_ALLOWED_HOSTS: &[&str] = &["crates.io", "index.crates.io"];
+
+/// Hosts which may be contacted, as configured through `allowedHosts`. Shared between
+/// clones, so that it can be configured once the settings are known.
+#[derive(Debug, Clone)]
+pub struct AllowedHosts(Arc<RwLock<Vec<String>>>);
+
+impl AllowedHosts {
+    /// Whether the given host may be contacted.
+    pub fn allows(&self, host: &str) -> bool {
+        self.0
+            .read()
+            .expect("allowed hosts lock poisoned")
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+
+    /// Only allow the given hosts from now on.
+    pub fn set(&self, hosts: Vec<String>) {
+        *self.0.write().expect("allowed hosts lock poisoned") = hosts;
+    }
+}
+
+impl Default for AllowedHosts {
+    fn default() -> Self {
+        AllowedHosts(Arc::new(RwLock::new(
+            DEFAULT_ALLOWED_HOSTS
+                .iter()
+                .map(|host| host.to_string())
+                .collect(),
+        )))
+    }
+}
+
+/// HTTP client shared between the registry backends, which can be replaced
+/// once the settings provided by the LSP client are known.
+#[derive(Debug, Clone)]
+pub struct SharedClient {
+    client: Arc<RwLock<Client>>,
+    allowed_hosts: AllowedHosts,
+}
+
+impl SharedClient {
+    pub fn get(&self) -> Client {
+        self.client
+            .read()
+            .expect("http client lock poisoned")
+            .clone()
+    }
+
+    /// Replace the client with one built using the given options, which still only
+    /// follows redirects to allowed hosts.
+    pub fn configure(&self, options: &ClientOptions) -> Result<(), ClientError> {
+        let client = build_client(options, &self.allowed_hosts)?;
+        *self.client.write().expect("http client lock poisoned") = client;
+        Ok(())
+    }
+
+    /// The hosts which may be contacted, which other clients such as `git` should
+    /// respect as well.
+    pub fn allowed_hosts(&self) -> AllowedHosts {
+        self.allowed_hosts.clone()
+    }
+
+    /// Only allow requests to the given hosts from now on.
+    pub fn set_allowed_hosts(&self, hosts: Vec<String>) {
+        self.allowed_hosts.set(hosts);
+    }
+
+    /// Start a GET request to the given URL, unless its host isn't allowed, in which
+    /// case the request is refused without contacting the host at all.
+    pub fn request(&self, url: &str) -> Result<RequestBuilder, CrateError> {
+        let host = host(url).unwrap_or_default();
+
+        if !self.allowed_hosts.allows(&host) {
+            tracing::warn!(url, host, "refusing request to host not in allowedHosts");
+            return Err(CrateError::HostNotAllowed(host));
+        }
+
+        Ok(self.get().get(url))
+    }
+}
+
+impl Default for SharedClient {
+    fn default() -> Self {
+        let allowed_hosts = AllowedHosts::default();
+        SharedClient {
+            client: Arc::new(RwLock::new(default_client(&allowed_hosts))),
+            allowed_hosts,
+        }
+    }
+}
+
+/// Options affecting how HTTP clients are constructed.
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// Disable certificate verification entirely. Only ever enabled by explicit opt-in.
+    pub accept_invalid_certs: bool,
+    /// Additional PEM-encoded root certificate to trust, e.g. for internal mirrors.
+    pub extra_ca_cert: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(std::io::Error),
+    Reqwest(Error),
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Io(err) => write!(f, "{err}"),
+            ClientError::Reqwest(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+pub fn default_client(allowed_hosts: &AllowedHosts) -> Client {
+    build_client(&ClientOptions::default(), allowed_hosts).unwrap_or_default()
+}
+
+/// Maximum number of redirects followed for a single request.
+const MAX_REDIRECTS: usize = 10;
+
+/// Follow redirects only to allowed hosts, so that an allowed host can't redirect
+/// requests anywhere else.
+fn redirect_policy(allowed_hosts: AllowedHosts) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        let host = attempt.url().host_str().unwrap_or_default().to_string();
+        if !allowed_hosts.allows(&host) {
+            tracing::warn!(url = %attempt.url(), host, "refusing redirect to host not in allowedHosts");
+            attempt.error(format!("redirect to {host} refused by allowedHosts"))
+        } else if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+pub fn build_client(
+    options: &ClientOptions,
+    allowed_hosts: &AllowedHosts,
+) -> Result<Client, ClientError> {
+    let mut builder = Client::builder()
         .timeout(std::time::Duration::from_secs(10))
+        .redirect(redirect_policy(allowed_hosts.clone()))
         .user_agent("crates-lsp (github.com/MathiasPius/crates-lsp)");
 
+    if let Some(path) = &options.extra_ca_cert {
+        let pem = std::fs::read(path).map_err(ClientError::Io)?;
+        let certificate = reqwest::Certificate::from_pem(&pem).map_err(ClientError::Reqwest)?;
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    if options.accept_invalid_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
     if let Ok(proxy) = std::env::var("https_proxy") {
         if let Ok(proxy) = reqwest::Proxy::all(proxy) {
-            return builder.proxy(proxy).build();
+            builder = builder.proxy(proxy);
         }
     };
-    builder.build()
+    builder.build().map_err(ClientError::Reqwest)
+}
+
+/// The newest releases of a few well-known crates, as if looked up in the registry,
+/// for tests of checks comparing dependencies against them. `unknown-crate` doesn't
+/// exist in the registry.
+#[cfg(test)]
+pub fn newest_packages() -> HashMap<String, Option<Release>> {
+    [
+        ("serde", Some("1.0.200")),
+        ("tokio", Some("1.40.0")),
+        ("anyhow", Some("1.0.80")),
+        ("rand", Some("0.8.5")),
+        ("unknown-crate", None),
+    ]
+    .into_iter()
+    .map(|(name, version)| {
+        let release = version.map(|version| Release {
+            version: Version::parse(version).unwrap(),
+            published_at: None,
+            yanked: Vec::new(),
+        });
+        (name.to_string(), release)
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use reqwest::Client;
+    use semver::Version;
+
+    use super::{
+        cache::{CachedVersion, CrateCache},
+        clock::{ManualClock, SharedClock},
+        newer_yanked, newest_prerelease, CrateError, CrateLookup, Lookup, PublishedVersion,
+        Release, SharedClient, WithFallback,
+    };
+
+    /// Registry which answers immediately, except for crates named "slow".
+    #[derive(Clone, Default)]
+    struct SlowRegistry {
+        /// Set if a slow lookup is dropped before completing.
+        cancelled: Arc<AtomicBool>,
+        /// Number of lookups performed.
+        lookups: Arc<AtomicUsize>,
+    }
+
+    struct SetOnDrop(Arc<AtomicBool>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[async_trait]
+    impl CrateLookup for SlowRegistry {
+        fn client(&self) -> Client {
+            Client::new()
+        }
+
+        async fn get_latest_version(
+            self,
+            crate_name: String,
+            _etag: Option<String>,
+        ) -> Result<Lookup, CrateError> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+
+            if crate_name.ends_with("slow") {
+                let guard = SetOnDrop(self.cancelled.clone());
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                std::mem::forget(guard);
+            }
+
+            Ok(Lookup::Fetched {
+                release: Release {
+                    version: Version::new(1, 0, 0),
+                    published_at: None,
+                    yanked: Vec::new(),
+                },
+                etag: None,
+            })
+        }
+
+        async fn versions(&self, _crate_name: &str) -> Result<Vec<PublishedVersion>, CrateError> {
+            Ok(Vec::new())
+        }
+
+        fn fetch_deadline(&self) -> Duration {
+            Duration::from_millis(200)
+        }
+    }
+
+    #[test]
+    fn newest_prereleases() {
+        let published = |version: &str, yanked: bool| PublishedVersion {
+            version: Version::parse(version).unwrap(),
+            yanked,
+            published_at: None,
+            rust_version: None,
+            features: Vec::new(),
+        };
+
+        let versions = [
+            published("1.0.0", false),
+            published("1.1.0-rc.1", false),
+            published("2.0.0-alpha.1", false),
+            published("2.0.0-alpha.2", true),
+            published("0.9.0-beta.1", false),
+        ];
+
+        assert_eq!(
+            newest_prerelease(&versions, &Version::new(1, 0, 0)),
+            Some(&Version::parse("2.0.0-alpha.1").unwrap())
+        );
+        assert_eq!(newest_prerelease(&versions, &Version::new(2, 0, 0)), None);
+    }
+
+    #[test]
+    fn newer_yanked_versions() {
+        let published = |version: &str, yanked: bool| PublishedVersion {
+            version: Version::parse(version).unwrap(),
+            yanked,
+            published_at: None,
+            rust_version: None,
+            features: Vec::new(),
+        };
+
+        let versions = [
+            published("0.9.0", true),
+            published("1.0.0", false),
+            published("1.0.1", true),
+            published("1.1.0", true),
+            published("2.0.0-alpha.1", true),
+        ];
+
+        let newest = Version::new(1, 0, 0);
+        assert_eq!(
+            newer_yanked(&versions, &newest, false),
+            [&Version::new(1, 1, 0), &Version::new(1, 0, 1)]
+        );
+        assert_eq!(
+            newer_yanked(&versions, &newest, true),
+            [
+                &Version::parse("2.0.0-alpha.1").unwrap(),
+                &Version::new(1, 1, 0),
+                &Version::new(1, 0, 1)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_versions_deadline() {
+        let cache = CrateCache::default();
+        let versions = SlowRegistry::default()
+            .fetch_versions(
+                cache.clone(),
+                &["crates-lsp-test-fast", "crates-lsp-test-slow"],
+                None,
+            )
+            .await;
+
+        assert_eq!(versions.len(), 1);
+        assert!(versions["crates-lsp-test-fast"].is_some());
+
+        cache.remove("crates-lsp-test-fast").await;
+    }
+
+    #[tokio::test]
+    async fn fetch_versions_refetches_expired() {
+        let clock = Arc::new(ManualClock::new(time::OffsetDateTime::UNIX_EPOCH));
+        let cache = CrateCache::with_clock(SharedClock::new(clock.clone()));
+        let registry = SlowRegistry::default();
+
+        for _ in 0..2 {
+            registry
+                .fetch_versions(cache.clone(), &["crates-lsp-test-refetch"], None)
+                .await;
+        }
+        assert_eq!(registry.lookups.load(Ordering::SeqCst), 1);
+
+        // Lookups are cached for a day.
+        clock.advance(time::Duration::days(1) - time::Duration::seconds(1));
+        registry
+            .fetch_versions(cache.clone(), &["crates-lsp-test-refetch"], None)
+            .await;
+        assert_eq!(registry.lookups.load(Ordering::SeqCst), 1);
+
+        clock.advance(time::Duration::seconds(1));
+        registry
+            .fetch_versions(cache.clone(), &["crates-lsp-test-refetch"], None)
+            .await;
+        assert_eq!(registry.lookups.load(Ordering::SeqCst), 2);
+
+        cache.remove("crates-lsp-test-refetch").await;
+    }
+
+    #[tokio::test]
+    async fn fetch_versions_shares_lookups_in_progress() {
+        let cache = CrateCache::default();
+        let registry = SlowRegistry::default();
+
+        let (first, second) = tokio::join!(
+            registry.fetch_versions(cache.clone(), &["crates-lsp-test-shared-slow"], None),
+            registry.fetch_versions(cache.clone(), &["crates-lsp-test-shared-slow"], None),
+        );
+
+        // Neither lookup completes before the deadline, but only one was made.
+        assert!(first.is_empty() && second.is_empty());
+        assert_eq!(registry.lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn fetch_versions_stream_yields_as_completed() {
+        let cache = CrateCache::default();
+        let registry = SlowRegistry::default();
+
+        let release = Release {
+            version: Version::new(2, 0, 0),
+            published_at: None,
+            yanked: Vec::new(),
+        };
+        let expires_at = cache.now() + time::Duration::days(1);
+        cache
+            .put(
+                "crates-lsp-test-stream-cached",
+                Some(release.clone()),
+                None,
+                expires_at,
+            )
+            .await;
+
+        let mut stream = registry
+            .fetch_versions_stream(
+                cache.clone(),
+                &[
+                    "crates-lsp-test-stream-slow",
+                    "crates-lsp-test-stream-fast",
+                    "crates-lsp-test-stream-cached",
+                ],
+                None,
+            )
+            .await;
+
+        // Cached versions come first, followed by lookups in the order they complete.
+        assert_eq!(
+            stream.recv().await,
+            Some(("crates-lsp-test-stream-cached".to_string(), Some(release)))
+        );
+        let (name, _) = stream.recv().await.unwrap();
+        assert_eq!(name, "crates-lsp-test-stream-fast");
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), stream.recv())
+                .await
+                .is_err()
+        );
+
+        // Dropping the stream aborts the remaining lookups.
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(registry.cancelled.load(Ordering::SeqCst));
+
+        cache.remove("crates-lsp-test-stream-cached").await;
+        cache.remove("crates-lsp-test-stream-fast").await;
+    }
+
+    #[tokio::test]
+    async fn fetch_versions_cancelled() {
+        let registry = SlowRegistry::default();
+        let fetch = registry.fetch_versions(
+            CrateCache::default(),
+            &["crates-lsp-test-cancelled-slow"],
+            None,
+        );
+
+        // Dropping the future, as happens when a request is cancelled, aborts the lookups.
+        assert!(tokio::time::timeout(Duration::from_millis(50), fetch)
+            .await
+            .is_err());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        assert!(registry.cancelled.load(Ordering::SeqCst));
+    }
+
+    /// Registry answering every lookup the same way, counting the lookups.
+    #[derive(Clone)]
+    struct FixedRegistry {
+        /// The version of every crate, or `None` if the registry is unreachable.
+        version: Option<Option<&'static str>>,
+        /// Host refusing every lookup, as if it weren't in `allowedHosts`.
+        refused: Option<&'static str>,
+        lookups: Arc<AtomicUsize>,
+    }
+
+    impl FixedRegistry {
+        fn new(version: Option<Option<&'static str>>) -> Self {
+            FixedRegistry {
+                version,
+                refused: None,
+                lookups: Arc::default(),
+            }
+        }
+
+        fn refused(host: &'static str) -> Self {
+            FixedRegistry {
+                refused: Some(host),
+                ..FixedRegistry::new(None)
+            }
+        }
+    }
+
+    #[async_trait]
+    impl CrateLookup for FixedRegistry {
+        fn client(&self) -> Client {
+            Client::new()
+        }
+
+        async fn get_latest_version(
+            self,
+            _crate_name: String,
+            _etag: Option<String>,
+        ) -> Result<Lookup, CrateError> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+
+            if let Some(host) = self.refused {
+                return Err(CrateError::HostNotAllowed(host.to_string()));
+            }
+
+            match self.version {
+                Some(Some(version)) => Ok(Lookup::Fetched {
+                    release: Release {
+                        version: Version::parse(version).unwrap(),
+                        published_at: None,
+                        yanked: Vec::new(),
+                    },
+                    etag: None,
+                }),
+                Some(None) => Err(CrateError::NoVersionsFound),
+                None => Err(CrateError::transport(std::io::Error::from(
+                    std::io::ErrorKind::ConnectionRefused,
+                ))),
+            }
+        }
+
+        async fn versions(&self, _crate_name: &str) -> Result<Vec<PublishedVersion>, CrateError> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_revalidation_keeps_expired_release() {
+        let clock = Arc::new(ManualClock::new(time::OffsetDateTime::UNIX_EPOCH));
+        let cache = CrateCache::with_clock(SharedClock::new(clock.clone()));
+        let release = Release {
+            version: Version::new(1, 2, 3),
+            published_at: None,
+            yanked: Vec::new(),
+        };
+        cache
+            .put(
+                "crates-lsp-test-revalidate",
+                Some(release.clone()),
+                Some("\"abc\"".to_string()),
+                cache.now() + time::Duration::days(1),
+            )
+            .await;
+        clock.advance(time::Duration::days(1));
+
+        // The registry can't be reached, so the expired release is still used.
+        let registry = FixedRegistry::new(None);
+        let versions = registry
+            .fetch_versions(cache.clone(), &["crates-lsp-test-revalidate"], None)
+            .await;
+        assert_eq!(
+            versions["crates-lsp-test-revalidate"],
+            Some(release.clone())
+        );
+        assert!(matches!(
+            cache.get("crates-lsp-test-revalidate").await,
+            CachedVersion::Known(known) if known == release
+        ));
+
+        // It is revalidated again shortly after, using its ETag.
+        clock.advance(time::Duration::minutes(5));
+        assert!(matches!(
+            cache.get("crates-lsp-test-revalidate").await,
+            CachedVersion::Expired { release: Some(expired), etag }
+                if expired == release && etag == "\"abc\""
+        ));
+
+        cache.remove("crates-lsp-test-revalidate").await;
+    }
+
+    #[tokio::test]
+    async fn fallback_on_unreachable_registry() {
+        let cache = CrateCache::default();
+        let secondary = FixedRegistry::new(Some(Some("1.2.3")));
+        let registry = WithFallback::new(FixedRegistry::new(None), Some(secondary.clone()));
+
+        let versions = registry
+            .fetch_versions(cache.clone(), &["crates-lsp-test-fallback"], None)
+            .await;
+        assert_eq!(
+            versions["crates-lsp-test-fallback"]
+                .as_ref()
+                .unwrap()
+                .version,
+            Version::new(1, 2, 3)
+        );
+        assert_eq!(secondary.lookups.load(Ordering::SeqCst), 1);
+        cache.remove("crates-lsp-test-fallback").await;
+
+        // Crates which the registry doesn't know aren't looked up again.
+        let secondary = FixedRegistry::new(Some(Some("1.2.3")));
+        let registry = WithFallback::new(FixedRegistry::new(Some(None)), Some(secondary.clone()));
+        assert!(matches!(
+            registry
+                .get_latest_version("crates-lsp-test-fallback".to_string(), None)
+                .await,
+            Err(CrateError::NoVersionsFound)
+        ));
+        assert_eq!(secondary.lookups.load(Ordering::SeqCst), 0);
+
+        // Refused requests never reached the registry, so aren't sent elsewhere either.
+        let secondary = FixedRegistry::new(Some(Some("1.2.3")));
+        let registry = WithFallback::new(
+            FixedRegistry::refused("crates.example.com"),
+            Some(secondary.clone()),
+        );
+        assert!(matches!(
+            registry
+                .get_latest_version("crates-lsp-test-fallback".to_string(), None)
+                .await,
+            Err(CrateError::HostNotAllowed(_))
+        ));
+        assert_eq!(secondary.lookups.load(Ordering::SeqCst), 0);
+
+        // Without a fallback, the lookup fails.
+        let registry = WithFallback::<_, FixedRegistry>::new(FixedRegistry::new(None), None);
+        assert!(registry
+            .get_latest_version("crates-lsp-test-fallback".to_string(), None)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn only_allowed_hosts_are_contacted() {
+        let client = SharedClient::default();
+        assert!(client
+            .request("https://index.crates.io/se/rd/serde")
+            .is_ok());
+        assert!(client
+            .request("https://crates.io/api/v1/crates/serde")
+            .is_ok());
+        assert!(matches!(
+            client.request("https://crates.example.com/api/v1/crates/serde"),
+            Err(CrateError::HostNotAllowed(host)) if host == "crates.example.com"
+        ));
+        assert!(matches!(
+            client.request("not a url"),
+            Err(CrateError::HostNotAllowed(_))
+        ));
+
+        client.set_allowed_hosts(vec!["Localhost".to_string()]);
+        assert!(client
+            .request("http://localhost:8080/api/v1/crates")
+            .is_ok());
+        assert!(client.request("https://crates.io/api/v1/crates").is_err());
+    }
+
+    #[tokio::test]
+    async fn redirects_to_other_hosts_are_refused() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let _ = stream.read(&mut [0; 1024]);
+            stream
+                .write_all(b"HTTP/1.1 302 Found\r\nLocation: http://crates.example.com/api/v1/crates/serde\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+        });
+
+        let client = SharedClient::default();
+        client.set_allowed_hosts(vec!["127.0.0.1".to_string()]);
+        let err = client
+            .request(&format!("http://127.0.0.1:{port}/api/v1/crates/serde"))
+            .unwrap()
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.is_redirect());
+
+        // The registry was reached, so other backends aren't asked instead.
+        assert!(!CrateError::transport(err).is_unreachable());
+    }
 }
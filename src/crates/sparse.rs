@@ -1,82 +1,323 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder};
 use semver::Version;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
+use time::OffsetDateTime;
+
+use super::{
+    selectable_features, CrateError, CrateLookup, Lookup, PublishedVersion, Release, SharedClient,
+};
 
-use super::{default_client, CrateError, CrateLookup};
+/// Root of the crates.io sparse index, unless overridden with `--registry`.
+pub const DEFAULT_INDEX_URL: &str = "https://index.crates.io";
 
 #[derive(Debug, Clone)]
 pub struct CrateIndex {
-    client: Client,
+    client: SharedClient,
+    /// Shared between clones, so it can be configured once the arguments are known.
+    index_url: Arc<RwLock<String>>,
 }
 
 #[async_trait]
 impl CrateLookup for CrateIndex {
-    fn client(&self) -> &Client {
-        &self.client
+    fn client(&self) -> Client {
+        self.client.get()
     }
 
-    async fn get_latest_version(self, crate_name: String) -> Result<Version, CrateError> {
-        let crate_index_path = match crate_name.len() {
-            0 => return Err(CrateError::InvalidCrateName(crate_name)),
-            1 => format!("1/{crate_name}"),
-            2 => format!("2/{crate_name}"),
-            3 => format!("3/{}/{crate_name}", &crate_name[0..1]),
-            _ => format!("{}/{}/{crate_name}", &crate_name[0..2], &crate_name[2..4]),
-        };
+    fn request(&self, url: &str) -> Result<RequestBuilder, CrateError> {
+        self.client.request(url)
+    }
+
+    async fn get_latest_version(
+        self,
+        crate_name: String,
+        etag: Option<String>,
+    ) -> Result<Lookup, CrateError> {
+        let mut request = self.request(&self.index_url(&crate_name)?)?;
+
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+
+        let response = request.send().await.map_err(CrateError::transport)?;
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(Lookup::NotModified);
+        }
+
+        let etag = response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+
+        let stringified = response.text().await?;
 
+        Ok(Lookup::Fetched {
+            release: latest_version(&stringified)?,
+            etag,
+        })
+    }
+
+    async fn versions(&self, crate_name: &str) -> Result<Vec<PublishedVersion>, CrateError> {
         let response = self
-            .client
-            .get(&format!("https://index.crates.io/{crate_index_path}"))
+            .request(&self.index_url(crate_name)?)?
             .send()
             .await
             .map_err(CrateError::transport)?;
 
         let stringified = response.text().await?;
 
-        let mut all_releases = Vec::new();
-        for line in stringified.lines() {
-            #[derive(Deserialize)]
-            struct CrateVersion {
-                pub vers: Version,
-                pub yanked: bool,
+        index_versions(&stringified)
+    }
+}
+
+/// Location of the crate's file relative to the root of a sparse index. The index
+/// only contains lowercase names, so the name is lowercased first.
+///
+/// Names are sliced by byte below, which is only sound because registry names are
+/// ASCII, so anything else is rejected rather than risking a split character.
+fn index_path(crate_name: &str) -> Result<String, CrateError> {
+    if crate_name.is_empty() || !crate_name.is_ascii() {
+        return Err(CrateError::InvalidCrateName(crate_name.to_string()));
+    }
+
+    let crate_name = crate_name.to_ascii_lowercase();
+    let crate_index_path = match crate_name.len() {
+        1 => format!("1/{crate_name}"),
+        2 => format!("2/{crate_name}"),
+        3 => format!("3/{}/{crate_name}", &crate_name[0..1]),
+        _ => format!("{}/{}/{crate_name}", &crate_name[0..2], &crate_name[2..4]),
+    };
+
+    Ok(crate_index_path)
+}
+
+#[derive(Deserialize)]
+struct IndexVersion {
+    pub vers: Version,
+    pub yanked: bool,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub pubtime: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub rust_version: Option<String>,
+    #[serde(default)]
+    pub features: HashMap<String, Vec<String>>,
+    /// Features using the `dep:` or `?` syntax, which older versions of cargo
+    /// don't understand, are listed separately.
+    #[serde(default)]
+    pub features2: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub deps: Vec<IndexDependency>,
+}
+
+/// Just the parts of a release needed to find the latest version. Lines of crates
+/// with many features are large, so skipping the features and dependencies is
+/// considerably cheaper than deserializing a whole [`IndexVersion`].
+#[derive(Deserialize)]
+struct IndexRelease {
+    pub vers: Version,
+    pub yanked: bool,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub pubtime: Option<OffsetDateTime>,
+}
+
+#[derive(Deserialize)]
+struct IndexDependency {
+    pub name: String,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// The releases listed within the contents of a sparse index file, one per line.
+///
+/// Malformed lines, such as those of a partial download, are logged and skipped so
+/// they don't hide the remaining releases. The file as a whole is only considered
+/// malformed if none of its lines could be parsed, see [`IndexReleases::finish`].
+struct IndexReleases<'a, T> {
+    lines: std::str::Lines<'a>,
+    parsed: usize,
+    error: Option<serde_json::Error>,
+    release: PhantomData<T>,
+}
+
+impl<'a, T> IndexReleases<'a, T> {
+    fn new(index: &'a str) -> Self {
+        IndexReleases {
+            lines: index.lines(),
+            parsed: 0,
+            error: None,
+            release: PhantomData,
+        }
+    }
+
+    /// Fail if every line of the file was malformed.
+    fn finish(self) -> Result<(), CrateError> {
+        match self.error {
+            Some(error) if self.parsed == 0 => Err(CrateError::Deserialization(error)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for IndexReleases<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = self.lines.next()?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str(line) {
+                Ok(release) => {
+                    self.parsed += 1;
+                    return Some(release);
+                }
+                Err(error) => {
+                    tracing::warn!(%error, line, "skipping malformed sparse index line");
+                    self.error = Some(error);
+                }
+            }
+        }
+    }
+}
+
+/// Parse every version listed within the contents of a sparse index file.
+fn index_versions(index: &str) -> Result<Vec<PublishedVersion>, CrateError> {
+    let mut releases = IndexReleases::<IndexVersion>::new(index);
+    let versions = releases
+        .by_ref()
+        .map(|release| {
+            let optional_dependencies = release
+                .deps
+                .iter()
+                .filter(|dependency| dependency.optional)
+                .map(|dependency| dependency.name.as_str());
+            let features = selectable_features(
+                release.features.iter().chain(&release.features2),
+                optional_dependencies,
+            );
+
+            PublishedVersion {
+                version: release.vers,
+                yanked: release.yanked,
+                published_at: release.pubtime,
+                rust_version: release.rust_version,
+                features,
             }
+        })
+        .collect();
+
+    releases.finish()?;
+    Ok(versions)
+}
+
+/// Find the latest unyanked version within the contents of a sparse index file.
+fn latest_version(index: &str) -> Result<Release, CrateError> {
+    // Index files for crates with many releases can be very large, so rather than
+    // collecting every release, keep track of the running maximum as we go.
+    let mut latest_stable: Option<Release> = None;
+    let mut latest_prerelease: Option<Release> = None;
+    let mut yanked = Vec::new();
+    let mut releases = IndexReleases::<IndexRelease>::new(index);
+    for release in releases.by_ref() {
+        if release.yanked {
+            yanked.push(release.vers);
+            continue;
+        }
 
-            let version: CrateVersion =
-                serde_json::from_str(line).map_err(CrateError::Deserialization)?;
+        let latest = if release.vers.pre.is_empty() {
+            &mut latest_stable
+        } else {
+            &mut latest_prerelease
+        };
 
-            all_releases.push(version);
+        if latest
+            .as_ref()
+            .is_none_or(|latest| release.vers > latest.version)
+        {
+            *latest = Some(Release {
+                version: release.vers,
+                published_at: release.pubtime,
+                yanked: Vec::new(),
+            });
         }
+    }
+
+    releases.finish()?;
 
-        let unyanked_versions: Vec<_> = all_releases
-            .into_iter()
-            .filter(|release| !release.yanked)
-            .map(|release| release.vers)
-            .collect();
-
-        // Try to find the latest non-prerelease version first, falling back to whichever
-        // latest pre-release version is available.
-        unyanked_versions
-            .iter()
-            .filter(|version| version.pre.is_empty())
-            .max()
-            .or(unyanked_versions.iter().max())
-            .cloned()
-            .ok_or(CrateError::NoVersionsFound)
+    // Try to find the latest non-prerelease version first, falling back to whichever
+    // latest pre-release version is available.
+    latest_stable
+        .or(latest_prerelease)
+        .map(|latest| Release { yanked, ..latest })
+        .ok_or(CrateError::NoVersionsFound)
+}
+
+impl CrateIndex {
+    pub fn new(client: SharedClient) -> Self {
+        CrateIndex {
+            client,
+            index_url: Arc::new(RwLock::new(DEFAULT_INDEX_URL.to_string())),
+        }
+    }
+
+    /// Look up every crate in the sparse index at the given root rather than in the
+    /// crates.io index, accepting cargo's `sparse+https://...` form as well.
+    pub fn set_index_url(&self, index_url: &str) {
+        let index_url = index_url.trim();
+        let index_url = index_url.strip_prefix("sparse+").unwrap_or(index_url);
+        *self.index_url.write().expect("index url lock poisoned") =
+            index_url.trim_end_matches('/').to_string();
+    }
+
+    /// Root of the sparse index, without a trailing slash.
+    pub fn root(&self) -> String {
+        self.index_url
+            .read()
+            .expect("index url lock poisoned")
+            .clone()
+    }
+
+    /// Whether crates are looked up in an index other than the crates.io one.
+    pub fn is_custom(&self) -> bool {
+        self.root() != DEFAULT_INDEX_URL
+    }
+
+    /// Host of the sparse index, if its root is a valid URL.
+    pub fn host(&self) -> Option<String> {
+        super::host(&self.root())
+    }
+
+    /// Location of the crate's file within the sparse index.
+    fn index_url(&self, crate_name: &str) -> Result<String, CrateError> {
+        let root = self.index_url.read().expect("index url lock poisoned");
+        Ok(format!("{root}/{}", index_path(crate_name)?))
     }
 }
 
 impl Default for CrateIndex {
     fn default() -> Self {
-        CrateIndex {
-            client: default_client(),
-        }
+        CrateIndex::new(SharedClient::default())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::crates::{cache::CrateCache, sparse::CrateIndex, CrateLookup};
+    use proptest::proptest;
+    use semver::Version;
+
+    use crate::crates::{
+        cache::CrateCache,
+        sparse::{index_path, index_versions, latest_version, CrateIndex},
+        CrateError, CrateLookup,
+    };
 
     #[tokio::test]
     async fn get_common_crates() {
@@ -85,9 +326,180 @@ mod tests {
         let cache = CrateCache::default();
 
         let versions = api
-            .fetch_versions(cache, &["serde", "log", "tracing", "crate-does-not-exist"])
+            .fetch_versions(
+                cache,
+                &["serde", "log", "tracing", "crate-does-not-exist"],
+                None,
+            )
             .await;
 
         println!("{versions:#?}");
     }
+
+    #[test]
+    fn index_url_lowercases_names() {
+        let index = CrateIndex::default();
+        assert_eq!(
+            index.index_url("MyCrate").unwrap(),
+            "https://index.crates.io/my/cr/mycrate"
+        );
+        assert_eq!(
+            index.index_url("Foo").unwrap(),
+            "https://index.crates.io/3/f/foo"
+        );
+    }
+
+    #[test]
+    fn index_path_of_short_and_non_ascii_names() {
+        assert_eq!(index_path("a").unwrap(), "1/a");
+        assert_eq!(index_path("ab").unwrap(), "2/ab");
+        assert!(matches!(
+            index_path(""),
+            Err(CrateError::InvalidCrateName(_))
+        ));
+        for name in ["é", "aé", "aéb", "éab", "abcé", "ⓢerde"] {
+            assert!(
+                matches!(index_path(name), Err(CrateError::InvalidCrateName(_))),
+                "{name}"
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn index_path_of_arbitrary_name(name in ".{0,8}") {
+            if let Ok(path) = index_path(&name) {
+                assert!(path.ends_with(&name.to_lowercase()));
+            }
+        }
+    }
+
+    #[test]
+    fn custom_index_url() {
+        let index = CrateIndex::default();
+        for index_url in [
+            "https://registry.example.com/index",
+            "https://registry.example.com/index/",
+            "sparse+https://registry.example.com/index/",
+        ] {
+            index.set_index_url(index_url);
+            assert_eq!(
+                index.clone().index_url("serde").unwrap(),
+                "https://registry.example.com/index/se/rd/serde"
+            );
+            assert!(index.is_custom());
+            assert_eq!(index.host().as_deref(), Some("registry.example.com"));
+        }
+
+        index.set_index_url("sparse+https://index.crates.io/");
+        assert!(!index.is_custom());
+    }
+
+    #[test]
+    fn latest_version_skips_yanked_and_prereleases() {
+        let index = [
+            r#"{"name":"foo","vers":"1.0.0","yanked":false}"#,
+            r#"{"name":"foo","vers":"1.2.0","yanked":false}"#,
+            r#"{"name":"foo","vers":"1.1.0","yanked":false}"#,
+            r#"{"name":"foo","vers":"1.3.0","yanked":true}"#,
+            r#"{"name":"foo","vers":"2.0.0-alpha.1","yanked":false}"#,
+        ]
+        .join("\n");
+
+        let latest = latest_version(&index).unwrap();
+        assert_eq!(latest.version, Version::parse("1.2.0").unwrap());
+        assert_eq!(latest.yanked, vec![Version::new(1, 3, 0)]);
+    }
+
+    #[test]
+    fn index_versions_include_yanked() {
+        let index = [
+            r#"{"name":"foo","vers":"1.0.0","yanked":false,"pubtime":"2024-01-02T03:04:05Z"}"#,
+            r#"{"name":"foo","vers":"1.1.0","yanked":true,"rust_version":"1.70"}"#,
+        ]
+        .join("\n");
+
+        let versions = index_versions(&index).unwrap();
+        assert_eq!(versions.len(), 2);
+        assert!(!versions[0].yanked);
+        assert!(versions[0].published_at.is_some());
+        assert_eq!(versions[1].version, Version::new(1, 1, 0));
+        assert!(versions[1].yanked);
+        assert_eq!(versions[0].rust_version, None);
+        assert_eq!(versions[1].rust_version.as_deref(), Some("1.70"));
+    }
+
+    #[test]
+    fn index_versions_hide_internal_features() {
+        let index = concat!(
+            r#"{"name":"foo","vers":"1.0.0","yanked":false,"#,
+            r#""deps":["#,
+            r#"{"name":"serde","req":"^1","optional":true,"kind":"normal"},"#,
+            r#"{"name":"rayon","req":"^1","optional":true,"kind":"normal"},"#,
+            r#"{"name":"log","req":"^0.4","optional":true},"#,
+            r#"{"name":"libc","req":"^0.2","optional":false,"kind":"normal"}"#,
+            r#"],"#,
+            r#""features":{"default":["std"],"std":[]},"#,
+            r#""features2":{"serde":["dep:serde","log?/std"],"parallel":["dep:rayon"]}}"#,
+        );
+
+        let versions = index_versions(index).unwrap();
+        assert_eq!(
+            versions[0].features,
+            ["default", "log", "parallel", "serde", "std"]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let index = [
+            r#"{"name":"foo","vers":"1.0.0","yanked":false}"#,
+            r#"{"name":"foo","vers":"1.1.0","yan"#,
+            r#"{"name":"foo","vers":"not a version","yanked":false}"#,
+            "",
+            r#"{"name":"foo","vers":"1.2.0","yanked":false}"#,
+        ]
+        .join("\n");
+
+        assert_eq!(
+            latest_version(&index).unwrap().version,
+            Version::new(1, 2, 0)
+        );
+        assert_eq!(
+            index_versions(&index)
+                .unwrap()
+                .into_iter()
+                .map(|published| published.version)
+                .collect::<Vec<_>>(),
+            [Version::new(1, 0, 0), Version::new(1, 2, 0)]
+        );
+
+        let malformed = r#"{"name":"foo","vers":"1.1.0","yan"#;
+        assert!(matches!(
+            latest_version(malformed),
+            Err(CrateError::Deserialization(_))
+        ));
+        assert!(matches!(
+            index_versions(malformed),
+            Err(CrateError::Deserialization(_))
+        ));
+        assert!(matches!(
+            latest_version(""),
+            Err(CrateError::NoVersionsFound)
+        ));
+    }
+
+    #[test]
+    fn latest_version_falls_back_to_prerelease() {
+        let index = [
+            r#"{"name":"foo","vers":"0.1.0-alpha.1","yanked":false}"#,
+            r#"{"name":"foo","vers":"0.1.0-alpha.2","yanked":false}"#,
+        ]
+        .join("\n");
+
+        assert_eq!(
+            latest_version(&index).unwrap().version,
+            Version::parse("0.1.0-alpha.2").unwrap()
+        );
+    }
 }
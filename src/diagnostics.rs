@@ -0,0 +1,1784 @@
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range, TextEdit,
+};
+
+use semver::Version;
+use time::OffsetDateTime;
+
+use crate::crates::Release;
+use crate::diagnostic_codes;
+use crate::encoding::PositionEncoding;
+use crate::lockfile::Lockfile;
+use crate::parse::{self, DependencyVersion, DependencyWithVersion};
+use crate::requirement::{self, PrereleaseStatus, Status};
+use crate::settings::{DiagnosticOrder, RequirementStyle};
+
+/// Severities of the version diagnostics, as configured by the client.
+#[derive(Debug, Clone, Copy)]
+pub struct Severities {
+    pub needs_update: DiagnosticSeverity,
+    pub up_to_date: DiagnosticSeverity,
+    pub unknown_dep: DiagnosticSeverity,
+}
+
+/// Compare each dependency against the newest release found in the registry,
+/// producing a diagnostic stating whether it is up to date, outdated, pinned to a
+/// yanked version or unknown.
+/// Dependencies missing from `newest_packages` entirely, such as crates which
+/// haven't been cached while offline, are skipped.
+///
+/// Outdated dependencies mention the version locked in the `lockfile`, if given.
+/// The ranges of the dependencies are expected in the given `encoding`.
+pub fn version_diagnostics(
+    content: &str,
+    dependencies: &[&DependencyWithVersion],
+    newest_packages: &HashMap<String, Option<Release>>,
+    severities: &Severities,
+    lockfile: Option<&Lockfile>,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    dependencies
+        .iter()
+        .filter_map(|dependency| {
+            // Invalid requirements are reported regardless of what the registry says.
+            if let DependencyVersion::Invalid { range, error, .. } = &dependency.version {
+                return Some(Diagnostic::new(
+                    *range,
+                    Some(DiagnosticSeverity::ERROR),
+                    Some(NumberOrString::Number(diagnostic_codes::INVALID_REQ)),
+                    None,
+                    format!("{}: invalid version requirement: {error}", &dependency.name),
+                    None,
+                    None,
+                ));
+            }
+
+            let newest = newest_packages.get(&dependency.name)?;
+
+            Some(if let Some(release) = newest {
+                let newest_version = &release.version;
+                match &dependency.version {
+                    DependencyVersion::Complete { range, version } => {
+                        let requirement =
+                            parse::text_at(content, encoding.decode(content, *range)).unwrap_or("");
+                        let (code, message, replacement, breaking) =
+                            match requirement::status(requirement, version, release) {
+                                Status::UpToDate => {
+                                    let range = Range {
+                                        start: Position::new(range.start.line, 0),
+                                        end: Position::new(range.start.line, 0),
+                                    };
+                                    return Some(Diagnostic::new(
+                                        range,
+                                        Some(severities.up_to_date),
+                                        Some(NumberOrString::Number(diagnostic_codes::UP_TO_DATE)),
+                                        None,
+                                        "✓".to_string(),
+                                        None,
+                                        None,
+                                    ));
+                                }
+                                Status::Outdated {
+                                    replacement,
+                                    breaking,
+                                } => {
+                                    let locked = lockfile.and_then(|lockfile| {
+                                        lockfile.locked(&dependency.name, version)
+                                    });
+                                    let message = match locked {
+                                        Some(locked) => format!(
+                                            "{}: {newest_version} ({locked} locked)",
+                                            &dependency.name
+                                        ),
+                                        None => format!("{}: {newest_version}", &dependency.name),
+                                    };
+                                    (
+                                        diagnostic_codes::NEEDS_UPDATE,
+                                        message,
+                                        replacement,
+                                        breaking,
+                                    )
+                                }
+                                Status::Yanked {
+                                    pinned,
+                                    replacement,
+                                    breaking,
+                                } => (
+                                    diagnostic_codes::YANKED,
+                                    format!(
+                                        "{}: {pinned} has been yanked, {newest_version}",
+                                        &dependency.name
+                                    ),
+                                    replacement,
+                                    breaking,
+                                ),
+                            };
+
+                        Diagnostic {
+                            range: *range,
+                            severity: Some(severities.needs_update),
+                            code: Some(NumberOrString::Number(code)),
+                            code_description: None,
+                            source: None,
+                            message: if breaking {
+                                format!("{message} (breaking)")
+                            } else {
+                                message
+                            },
+                            related_information: None,
+                            tags: None,
+                            data: Some(serde_json::json!({
+                                "newest_version": newest_version,
+                                "replacement": replacement,
+                                "breaking": breaking,
+                            })),
+                        }
+                    }
+                    DependencyVersion::Partial { range, version } => {
+                        // Requirements typed without quotes, as in `serde = 1`, are
+                        // quoted by the update, so that it leaves valid TOML behind.
+                        let mut replacement =
+                            requirement::update_requirement(version, newest_version);
+                        if !parse::is_quoted(content, encoding.decode(content, *range)) {
+                            replacement = format!("\"{replacement}\"");
+                        }
+
+                        Diagnostic {
+                            range: *range,
+                            severity: Some(severities.needs_update),
+                            code: Some(NumberOrString::Number(diagnostic_codes::NEEDS_UPDATE)),
+                            code_description: None,
+                            source: None,
+                            message: format!("{}: {newest_version}", &dependency.name),
+                            related_information: None,
+                            tags: None,
+                            data: Some(serde_json::json!({
+                                "newest_version": newest_version,
+                                "replacement": replacement,
+                            })),
+                        }
+                    }
+                    DependencyVersion::Invalid { .. } => return None,
+                }
+            } else {
+                Diagnostic {
+                    range: dependency.version.range(),
+                    severity: Some(severities.unknown_dep),
+                    code: Some(NumberOrString::Number(diagnostic_codes::UNKNOWN_DEP)),
+                    code_description: None,
+                    source: None,
+                    message: format!("{}: Unknown crate", &dependency.name),
+                    related_information: None,
+                    tags: None,
+                    data: None,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Point out dependencies whose newest release was published at least `threshold`
+/// years before `now`. Releases dated after `now`, as happens when clocks disagree,
+/// are considered brand new.
+pub fn stale_diagnostics(
+    dependencies: &[&DependencyWithVersion],
+    newest_packages: &HashMap<String, Option<Release>>,
+    threshold: u32,
+    severity: DiagnosticSeverity,
+    now: OffsetDateTime,
+) -> Vec<Diagnostic> {
+    dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let Some(Some(Release {
+                published_at: Some(published_at),
+                ..
+            })) = newest_packages.get(&dependency.name)
+            else {
+                return None;
+            };
+
+            let days = (now - *published_at).whole_days().max(0);
+            let age = u32::try_from(days / 365).unwrap_or(u32::MAX);
+            if age < threshold {
+                return None;
+            }
+
+            Some(Diagnostic::new(
+                dependency.version.range(),
+                Some(severity),
+                Some(NumberOrString::Number(diagnostic_codes::STALE_CRATE)),
+                None,
+                format!(
+                    "{}'s latest release is {age} year{} old",
+                    dependency.name,
+                    if age == 1 { "" } else { "s" }
+                ),
+                None,
+                None,
+            ))
+        })
+        .collect()
+}
+
+/// Update suggested by the diagnostic of an outdated dependency, or one pinned to a
+/// yanked version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    pub newest_version: String,
+    /// Requirement to replace the current one with, preserving its operator.
+    pub replacement: String,
+    pub breaking: bool,
+}
+
+/// Read the update suggested by a diagnostic produced by [`version_diagnostics`], if any.
+pub fn suggested_update(diagnostic: &Diagnostic) -> Option<Update> {
+    let Some(NumberOrString::Number(diagnostic_codes::NEEDS_UPDATE | diagnostic_codes::YANKED)) =
+        diagnostic.code
+    else {
+        return None;
+    };
+
+    let serde_json::Value::Object(data) = diagnostic.data.as_ref()? else {
+        return None;
+    };
+
+    let serde_json::Value::String(newest_version) = data.get("newest_version")? else {
+        return None;
+    };
+
+    // Prefer the replacement which preserves the requirement operator, if present.
+    let replacement = match data.get("replacement") {
+        Some(serde_json::Value::String(replacement)) => replacement.clone(),
+        _ => newest_version.clone(),
+    };
+
+    Some(Update {
+        newest_version: newest_version.clone(),
+        replacement,
+        breaking: matches!(data.get("breaking"), Some(serde_json::Value::Bool(true))),
+    })
+}
+
+/// Point out dependencies whose requirement excludes every version of the crate in
+/// the lockfile, meaning the lockfile hasn't been updated since the requirement was
+/// changed. Crates missing from the lockfile entirely are ignored, since the lockfile
+/// may belong to an unrelated workspace further up the directory tree.
+pub fn lockfile_diagnostics(
+    dependencies: &[&DependencyWithVersion],
+    lockfile: &Lockfile,
+) -> Vec<Diagnostic> {
+    dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let DependencyVersion::Complete { range, version } = &dependency.version else {
+                return None;
+            };
+
+            let locked = lockfile.versions(&dependency.name);
+            if locked.is_empty() || locked.iter().any(|locked| version.matches(locked)) {
+                return None;
+            }
+
+            let locked: Vec<_> = locked.iter().map(Version::to_string).collect();
+            Some(Diagnostic::new(
+                *range,
+                Some(DiagnosticSeverity::WARNING),
+                Some(NumberOrString::Number(diagnostic_codes::LOCKFILE_MISMATCH)),
+                None,
+                format!(
+                    "{}: {version} excludes locked version {}, run `cargo update`",
+                    &dependency.name,
+                    locked.join(", ")
+                ),
+                None,
+                None,
+            ))
+        })
+        .collect()
+}
+
+/// Report requirements on pre-releases according to their [`PrereleaseStatus`], which
+/// takes the place of the diagnostics produced by [`version_diagnostics`] for them.
+///
+/// The ranges of the dependencies are expected in the given `encoding`.
+pub fn prerelease_diagnostics(
+    content: &str,
+    dependencies: &[&DependencyWithVersion],
+    statuses: &HashMap<String, PrereleaseStatus>,
+    severities: &Severities,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let DependencyVersion::Complete { range, version } = &dependency.version else {
+                return None;
+            };
+
+            let (newest_version, message) = match statuses.get(&dependency.name)? {
+                PrereleaseStatus::UpToDate => {
+                    let line = range.start.line;
+                    return Some(Diagnostic::new(
+                        Range::new(Position::new(line, 0), Position::new(line, 0)),
+                        Some(severities.up_to_date),
+                        Some(NumberOrString::Number(diagnostic_codes::UP_TO_DATE)),
+                        None,
+                        "✓".to_string(),
+                        None,
+                        None,
+                    ));
+                }
+                PrereleaseStatus::Stable(newest) => (newest, format!("stable {newest} available")),
+                PrereleaseStatus::Prerelease(newest) => {
+                    (newest, format!("newer pre-release {newest} available"))
+                }
+            };
+
+            let requirement =
+                parse::text_at(content, encoding.decode(content, *range)).unwrap_or("");
+            let breaking = requirement::is_breaking_update(version, newest_version);
+            let message = format!("{}: {message}", &dependency.name);
+
+            Some(Diagnostic {
+                range: *range,
+                severity: Some(severities.needs_update),
+                code: Some(NumberOrString::Number(diagnostic_codes::NEEDS_UPDATE)),
+                code_description: None,
+                source: None,
+                message: if breaking {
+                    format!("{message} (breaking)")
+                } else {
+                    message
+                },
+                related_information: None,
+                tags: None,
+                data: Some(serde_json::json!({
+                    "newest_version": newest_version,
+                    "replacement": requirement::update_requirement(requirement, newest_version),
+                    "breaking": breaking,
+                })),
+            })
+        })
+        .collect()
+}
+
+/// Report dependencies which disable their default features without enabling any
+/// others, leaving the crate with no features at all.
+pub fn empty_features_diagnostics(dependencies: &[&DependencyWithVersion]) -> Vec<Diagnostic> {
+    dependencies
+        .iter()
+        .filter(|dependency| !dependency.default_features && dependency.features.is_empty())
+        .map(|dependency| {
+            Diagnostic::new(
+                dependency.version.range(),
+                Some(DiagnosticSeverity::INFORMATION),
+                Some(NumberOrString::Number(diagnostic_codes::EMPTY_FEATURES)),
+                None,
+                format!(
+                    "{}: default features are disabled and no features are enabled",
+                    &dependency.name
+                ),
+                None,
+                None,
+            )
+        })
+        .collect()
+}
+
+/// Report requirements without an upper bound, which accept any future breaking
+/// release of the crate. If the newest release is known, the diagnostic suggests a
+/// caret requirement on it instead.
+pub fn unbounded_diagnostics(
+    dependencies: &[&DependencyWithVersion],
+    newest_packages: &HashMap<String, Option<Release>>,
+) -> Vec<Diagnostic> {
+    dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let DependencyVersion::Complete { range, version } = &dependency.version else {
+                return None;
+            };
+
+            if !requirement::is_unbounded(version) {
+                return None;
+            }
+
+            let replacement = match newest_packages.get(&dependency.name) {
+                Some(Some(release)) => Some(requirement::with_style(
+                    &release.version,
+                    RequirementStyle::Caret,
+                )),
+                _ => None,
+            };
+
+            Some(Diagnostic {
+                range: *range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::Number(diagnostic_codes::UNBOUNDED_REQ)),
+                code_description: None,
+                source: None,
+                message: format!(
+                    "{}: `{version}` has no upper bound and accepts breaking releases",
+                    &dependency.name
+                ),
+                related_information: None,
+                tags: None,
+                data: replacement.map(|replacement| {
+                    serde_json::json!({
+                        "replacement": replacement,
+                    })
+                }),
+            })
+        })
+        .collect()
+}
+
+/// Report dependencies whose requirement allows major versions below the minimum
+/// configured for the crate. Crates without a configured minimum aren't checked.
+/// If the newest release meets the minimum, the diagnostic suggests updating to it.
+///
+/// The ranges of the dependencies are expected in the given `encoding`.
+pub fn minimum_major_diagnostics(
+    content: &str,
+    dependencies: &[&DependencyWithVersion],
+    minimums: &HashMap<String, u64>,
+    newest_packages: &HashMap<String, Option<Release>>,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let minimum = *minimums.get(&dependency.name)?;
+            let DependencyVersion::Complete { range, version } = &dependency.version else {
+                return None;
+            };
+
+            // Requirements without a lower bound, such as `*` or `<2`, allow every
+            // major version down to 0.
+            let major = requirement::lower_bound(version).map_or(0, |lower| lower.major);
+            if major >= minimum {
+                return None;
+            }
+
+            let replacement = match newest_packages.get(&dependency.name) {
+                Some(Some(release)) if release.version.major >= minimum => {
+                    let requirement =
+                        parse::text_at(content, encoding.decode(content, *range)).unwrap_or("");
+                    Some(requirement::update_requirement(
+                        requirement,
+                        &release.version,
+                    ))
+                }
+                _ => None,
+            };
+
+            Some(Diagnostic {
+                range: *range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::Number(
+                    diagnostic_codes::BELOW_MINIMUM_MAJOR,
+                )),
+                code_description: None,
+                source: None,
+                message: format!(
+                    "{}: major version {minimum} or newer is required",
+                    &dependency.name
+                ),
+                related_information: None,
+                tags: None,
+                data: replacement.map(|replacement| {
+                    serde_json::json!({
+                        "replacement": replacement,
+                    })
+                }),
+            })
+        })
+        .collect()
+}
+
+/// Point out `[patch.crates-io]` entries whose version has since been published in
+/// the given registry, at the same or a newer version, as the patch may no longer be
+/// needed. Patches whose newest release hasn't been looked up aren't reported.
+///
+/// Patches renamed through `package = "..."` are looked up under that name. The
+/// ranges of the patches are expected as byte offsets.
+pub fn redundant_patch_diagnostics(
+    content: &str,
+    patches: &[DependencyWithVersion],
+    newest_packages: &HashMap<String, Option<Release>>,
+    registry: &str,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    patches
+        .iter()
+        .filter_map(|patch| {
+            let DependencyVersion::Complete { range, version } = &patch.version else {
+                return None;
+            };
+            let package = parse::dependency_value(content, patch, "package");
+            let Some(Some(release)) = newest_packages.get(package.unwrap_or(&patch.name)) else {
+                return None;
+            };
+
+            if release.version < requirement::lower_bound(version)? {
+                return None;
+            }
+
+            Some(Diagnostic::new(
+                encoding.encode(content, *range),
+                Some(DiagnosticSeverity::INFORMATION),
+                Some(NumberOrString::Number(diagnostic_codes::REDUNDANT_PATCH)),
+                None,
+                format!(
+                    "{}: {}{} has been published on {registry}, this patch may no longer be needed",
+                    &patch.name,
+                    package
+                        .map(|package| format!("{package} "))
+                        .unwrap_or_default(),
+                    release.version
+                ),
+                None,
+                None,
+            ))
+        })
+        .collect()
+}
+
+/// Report requirements which don't use the operator required by the style policy,
+/// suggesting the requirement rewritten in that style. The rewritten requirement
+/// targets the version locked in the `lockfile`, if any, or otherwise the lowest
+/// version the requirement allows.
+///
+/// The ranges of the dependencies are expected in the given `encoding`.
+pub fn style_diagnostics(
+    content: &str,
+    dependencies: &[&DependencyWithVersion],
+    style: RequirementStyle,
+    lockfile: Option<&Lockfile>,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    if style == RequirementStyle::Any {
+        return Vec::new();
+    }
+
+    dependencies
+        .iter()
+        .filter_map(|dependency| {
+            let DependencyVersion::Complete { range, version } = &dependency.version else {
+                return None;
+            };
+
+            let requirement =
+                parse::text_at(content, encoding.decode(content, *range)).unwrap_or("");
+            if requirement::conforms_to_style(requirement, version, style) {
+                return None;
+            }
+
+            let resolved = lockfile
+                .and_then(|lockfile| lockfile.locked(&dependency.name, version))
+                .cloned()
+                .or_else(|| requirement::lower_bound(version));
+            let replacement = resolved.map(|version| requirement::with_style(&version, style));
+
+            Some(Diagnostic {
+                range: *range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::Number(diagnostic_codes::REQUIREMENT_STYLE)),
+                code_description: None,
+                source: None,
+                message: format!(
+                    "{}: requirement must use the {} style",
+                    &dependency.name,
+                    style_name(style)
+                ),
+                related_information: None,
+                tags: None,
+                data: replacement.map(|replacement| {
+                    serde_json::json!({
+                        "replacement": replacement,
+                    })
+                }),
+            })
+        })
+        .collect()
+}
+
+fn style_name(style: RequirementStyle) -> &'static str {
+    match style {
+        RequirementStyle::Caret => "caret (`^1.2.3`)",
+        RequirementStyle::Tilde => "tilde (`~1.2.3`)",
+        RequirementStyle::Exact => "exact (`=1.2.3`)",
+        RequirementStyle::Any => "any",
+    }
+}
+
+/// Read the requirement suggested by a diagnostic produced by [`style_diagnostics`],
+/// [`unbounded_diagnostics`] or [`minimum_major_diagnostics`], if any.
+pub fn style_fix(diagnostic: &Diagnostic) -> Option<String> {
+    let Some(NumberOrString::Number(
+        diagnostic_codes::REQUIREMENT_STYLE
+        | diagnostic_codes::UNBOUNDED_REQ
+        | diagnostic_codes::BELOW_MINIMUM_MAJOR,
+    )) = diagnostic.code
+    else {
+        return None;
+    };
+
+    match diagnostic.data.as_ref()?.get("replacement")? {
+        serde_json::Value::String(replacement) => Some(replacement.clone()),
+        _ => None,
+    }
+}
+
+/// Report dependencies which declare a version of their own, even though the crate
+/// is also declared in the `workspace` dependencies they could inherit instead.
+/// Each diagnostic carries an edit converting the declaration to `workspace = true`.
+///
+/// The ranges of the dependencies are expected in the given `encoding`.
+pub fn workspace_diagnostics(
+    content: &str,
+    dependencies: &[&DependencyWithVersion],
+    workspace: &[DependencyWithVersion],
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    dependencies
+        .iter()
+        .filter(|dependency| {
+            workspace
+                .iter()
+                .any(|inheritable| inheritable.name == dependency.name)
+        })
+        // Cargo rejects these keys next to `workspace = true`, and they may well be
+        // meant to differ from the workspace's declaration.
+        .filter(|dependency| {
+            ["path", "git", "registry", "package"]
+                .iter()
+                .all(|key| parse::dependency_value(content, dependency, key).is_none())
+        })
+        .map(|dependency| {
+            let range = dependency.version.range();
+            let edit = inherit_edit(content, dependency, encoding.decode(content, range)).map(
+                |mut edit| {
+                    edit.range = encoding.encode(content, edit.range);
+                    edit
+                },
+            );
+
+            Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::Number(
+                    diagnostic_codes::SHOULD_USE_WORKSPACE,
+                )),
+                code_description: None,
+                source: None,
+                message: format!(
+                    "{}: declared in the workspace, use `{}.workspace = true` instead",
+                    &dependency.name, &dependency.name
+                ),
+                related_information: None,
+                tags: None,
+                data: edit.map(|edit| {
+                    serde_json::json!({
+                        "edit": edit,
+                    })
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Edit replacing the version requirement at the given byte range with
+/// `workspace = true`, or the entire declaration if it's nothing but the requirement.
+fn inherit_edit(
+    content: &str,
+    dependency: &DependencyWithVersion,
+    range: Range,
+) -> Option<TextEdit> {
+    let line = content.lines().nth(range.start.line as usize)?;
+    let start = range.start.character as usize;
+    let end = range.end.character as usize;
+
+    // Cover the closing quote, leaving unterminated strings alone.
+    let end = end
+        + line
+            .get(end..)?
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')?
+            .len_utf8();
+
+    let key = line
+        .get(..start)?
+        .trim_end()
+        .strip_suffix(['"', '\''])?
+        .trim_end()
+        .strip_suffix('=')?
+        .trim_end();
+
+    let (start, new_text) = match key.strip_suffix("version") {
+        // `serde = { version = "1" }`, `serde.version = "1"` or a `version` key within
+        // a `[dependencies.serde]` table.
+        Some(prefix)
+            if !prefix.ends_with(|c: char| c.is_alphanumeric() || c == '-' || c == '_') =>
+        {
+            (prefix.len(), "workspace = true".to_string())
+        }
+        // `serde = "1"`
+        _ => (
+            line.len() - line.trim_start().len(),
+            format!("{}.workspace = true", dependency.name),
+        ),
+    };
+
+    Some(TextEdit::new(
+        Range::new(
+            Position::new(range.start.line, start as u32),
+            Position::new(range.start.line, end as u32),
+        ),
+        new_text,
+    ))
+}
+
+/// Read the edit suggested by a diagnostic produced by [`workspace_diagnostics`], if any.
+pub fn workspace_fix(diagnostic: &Diagnostic) -> Option<TextEdit> {
+    let Some(NumberOrString::Number(diagnostic_codes::SHOULD_USE_WORKSPACE)) = diagnostic.code
+    else {
+        return None;
+    };
+
+    serde_json::from_value(diagnostic.data.as_ref()?.get("edit")?.clone()).ok()
+}
+
+/// Replace the per-line up to date, outdated and yanked diagnostics with a single
+/// diagnostic spanning the first line of the manifest, e.g. `5 of 40 dependencies are
+/// outdated and 1 is yanked`, keeping every other diagnostic as is. Only dependencies
+/// whose versions are known are counted. The summary carries the edits of every
+/// suggested update, for updating all of them at once.
+pub fn summarize(
+    content: &str,
+    diagnostics: Vec<Diagnostic>,
+    severities: &Severities,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    let (versions, mut summarized): (Vec<_>, Vec<_>) =
+        diagnostics.into_iter().partition(|diagnostic| {
+            matches!(
+                diagnostic.code,
+                Some(NumberOrString::Number(
+                    diagnostic_codes::UP_TO_DATE
+                        | diagnostic_codes::NEEDS_UPDATE
+                        | diagnostic_codes::YANKED
+                ))
+            )
+        });
+
+    if versions.is_empty() {
+        return summarized;
+    }
+
+    let edits: Vec<_> = versions
+        .iter()
+        .filter_map(|diagnostic| {
+            let update = suggested_update(diagnostic)?;
+            Some(TextEdit::new(diagnostic.range, update.replacement))
+        })
+        .collect();
+    let count = |code| {
+        versions
+            .iter()
+            .filter(|diagnostic| diagnostic.code == Some(NumberOrString::Number(code)))
+            .count()
+    };
+    let outdated = count(diagnostic_codes::NEEDS_UPDATE);
+    let yanked = count(diagnostic_codes::YANKED);
+
+    let dependencies = match versions.len() {
+        1 => "1 dependency".to_string(),
+        total => format!("{total} dependencies"),
+    };
+    let verb = |count| if count == 1 { "is" } else { "are" };
+
+    let (severity, message) = match (outdated, yanked) {
+        (0, 0) if versions.len() == 1 => (
+            severities.up_to_date,
+            format!("{dependencies} is up to date"),
+        ),
+        (0, 0) => (
+            severities.up_to_date,
+            format!("All {dependencies} are up to date"),
+        ),
+        (outdated, 0) => (
+            severities.needs_update,
+            format!("{outdated} of {dependencies} {} outdated", verb(outdated)),
+        ),
+        (0, yanked) => (
+            severities.needs_update,
+            format!("{yanked} of {dependencies} {} yanked", verb(yanked)),
+        ),
+        (outdated, yanked) => (
+            severities.needs_update,
+            format!(
+                "{outdated} of {dependencies} {} outdated and {yanked} {} yanked",
+                verb(outdated),
+                verb(yanked)
+            ),
+        ),
+    };
+
+    let line = content.lines().next().unwrap_or_default();
+    summarized.push(Diagnostic {
+        range: Range::new(
+            Position::new(0, 0),
+            Position::new(0, encoding.column(line, line.len())),
+        ),
+        severity: Some(severity),
+        code: Some(NumberOrString::Number(diagnostic_codes::SUMMARY)),
+        code_description: None,
+        source: None,
+        message,
+        related_information: None,
+        tags: None,
+        data: (!edits.is_empty()).then(|| {
+            serde_json::json!({
+                "edits": edits,
+            })
+        }),
+    });
+
+    summarized
+}
+
+/// Read the edits updating every outdated dependency from a diagnostic produced by
+/// [`summarize`], if any.
+pub fn summary_edits(diagnostic: &Diagnostic) -> Option<Vec<TextEdit>> {
+    let Some(NumberOrString::Number(diagnostic_codes::SUMMARY)) = diagnostic.code else {
+        return None;
+    };
+
+    serde_json::from_value(diagnostic.data.as_ref()?.get("edits")?.clone()).ok()
+}
+
+/// Sort the diagnostics according to the configured order. Diagnostics which are
+/// considered equal are kept in document order.
+pub fn sort(diagnostics: &mut [Diagnostic], order: DiagnosticOrder) {
+    let line = |diagnostic: &Diagnostic| diagnostic.range.start.line;
+
+    match order {
+        DiagnosticOrder::Document => (),
+        DiagnosticOrder::Severity => diagnostics.sort_by_key(|diagnostic| {
+            // Lower severities are more severe, with ERROR being 1.
+            (diagnostic.severity, line(diagnostic))
+        }),
+        DiagnosticOrder::Status => diagnostics.sort_by_key(|diagnostic| {
+            let rank = match diagnostic.code {
+                Some(NumberOrString::Number(
+                    diagnostic_codes::NEEDS_UPDATE | diagnostic_codes::YANKED,
+                )) => 0,
+                Some(NumberOrString::Number(diagnostic_codes::UNKNOWN_DEP)) => 1,
+                Some(NumberOrString::Number(diagnostic_codes::UP_TO_DATE)) => 3,
+                _ => 2,
+            };
+            (rank, line(diagnostic))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use indoc::indoc;
+    use semver::Version;
+    use serde_json::{json, Value};
+    use time::{Duration, OffsetDateTime};
+    use tower_lsp::lsp_types::{
+        Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range, Url,
+    };
+
+    use crate::crates::{newest_packages, Release};
+    use crate::diagnostic_codes;
+    use crate::encoding::PositionEncoding;
+    use crate::lockfile::Lockfile;
+    use crate::parse::{self, ManifestTracker};
+    use crate::settings::{DiagnosticOrder, RequirementStyle};
+
+    use crate::requirement::PrereleaseStatus;
+
+    use super::{
+        empty_features_diagnostics, lockfile_diagnostics, minimum_major_diagnostics,
+        prerelease_diagnostics, redundant_patch_diagnostics, sort, stale_diagnostics,
+        style_diagnostics, style_fix, suggested_update, summarize, summary_edits,
+        unbounded_diagnostics, version_diagnostics, workspace_diagnostics, workspace_fix,
+        Severities,
+    };
+
+    const MANIFEST: &str = r#"[package]
+name = "example"
+
+[dependencies]
+serde = "1.0.100"
+tokio = { version = "0.2", features = ["full"] }
+anyhow = "=1.0.50"
+yanked-pin = "=2.0.1"
+unknown-crate = "1"
+log = "0.
+"#;
+
+    /// Stands in for the registry, answering with fixed releases.
+    fn registry() -> HashMap<String, Option<Release>> {
+        let mut registry = newest_packages();
+        registry.insert(
+            "yanked-pin".to_string(),
+            Some(Release {
+                version: Version::new(2, 0, 2),
+                published_at: None,
+                yanked: vec![Version::new(2, 0, 1)],
+            }),
+        );
+        registry.insert(
+            "log".to_string(),
+            Some(Release {
+                version: Version::new(0, 4, 22),
+                published_at: None,
+                yanked: Vec::new(),
+            }),
+        );
+        registry
+    }
+
+    /// Build the expected diagnostic, with the range given as `(line, start, end)`.
+    fn diagnostic(
+        (line, start, end): (u32, u32, u32),
+        severity: DiagnosticSeverity,
+        code: i32,
+        message: &str,
+        data: Option<Value>,
+    ) -> Diagnostic {
+        Diagnostic {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            severity: Some(severity),
+            code: Some(NumberOrString::Number(code)),
+            message: message.to_string(),
+            data,
+            ..Diagnostic::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn representative_manifest() {
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), MANIFEST)
+            .await;
+
+        let dependencies = parse::versioned(&packages);
+
+        let severities = Severities {
+            needs_update: DiagnosticSeverity::HINT,
+            up_to_date: DiagnosticSeverity::INFORMATION,
+            unknown_dep: DiagnosticSeverity::WARNING,
+        };
+
+        let diagnostics = version_diagnostics(
+            MANIFEST,
+            &dependencies,
+            &registry(),
+            &severities,
+            None,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                diagnostic(
+                    (4, 0, 0),
+                    DiagnosticSeverity::INFORMATION,
+                    diagnostic_codes::UP_TO_DATE,
+                    "✓",
+                    None,
+                ),
+                diagnostic(
+                    (5, 21, 24),
+                    DiagnosticSeverity::HINT,
+                    diagnostic_codes::NEEDS_UPDATE,
+                    "tokio: 1.40.0 (breaking)",
+                    Some(json!({
+                        "newest_version": "1.40.0",
+                        "replacement": "1.40.0",
+                        "breaking": true,
+                    })),
+                ),
+                diagnostic(
+                    (6, 10, 17),
+                    DiagnosticSeverity::HINT,
+                    diagnostic_codes::NEEDS_UPDATE,
+                    "anyhow: 1.0.80",
+                    Some(json!({
+                        "newest_version": "1.0.80",
+                        "replacement": "=1.0.80",
+                        "breaking": false,
+                    })),
+                ),
+                diagnostic(
+                    (7, 14, 20),
+                    DiagnosticSeverity::HINT,
+                    diagnostic_codes::YANKED,
+                    "yanked-pin: 2.0.1 has been yanked, 2.0.2",
+                    Some(json!({
+                        "newest_version": "2.0.2",
+                        "replacement": "=2.0.2",
+                        "breaking": false,
+                    })),
+                ),
+                diagnostic(
+                    (8, 17, 18),
+                    DiagnosticSeverity::WARNING,
+                    diagnostic_codes::UNKNOWN_DEP,
+                    "unknown-crate: Unknown crate",
+                    None,
+                ),
+                diagnostic(
+                    (9, 7, 9),
+                    DiagnosticSeverity::HINT,
+                    diagnostic_codes::NEEDS_UPDATE,
+                    "log: 0.4.22",
+                    Some(json!({
+                        "newest_version": "0.4.22",
+                        "replacement": "0.4.22",
+                    })),
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unquoted_requirement_update() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = 1
+            log = "0.
+        "#};
+
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), cargo)
+            .await;
+
+        let severities = Severities {
+            needs_update: DiagnosticSeverity::HINT,
+            up_to_date: DiagnosticSeverity::INFORMATION,
+            unknown_dep: DiagnosticSeverity::WARNING,
+        };
+
+        // The unquoted requirement is quoted by its update, the quoted one isn't.
+        let replacements: Vec<_> = version_diagnostics(
+            cargo,
+            &parse::versioned(&packages),
+            &registry(),
+            &severities,
+            None,
+            PositionEncoding::Utf16,
+        )
+        .iter()
+        .map(|diagnostic| {
+            let update = suggested_update(diagnostic).unwrap();
+            (diagnostic.range.start.line, update.replacement)
+        })
+        .collect();
+
+        assert_eq!(
+            replacements,
+            vec![(1, "\"1.0.200\"".to_string()), (2, "0.4.22".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn locked_versions() {
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), MANIFEST)
+            .await;
+
+        let dependencies = parse::versioned(&packages);
+
+        let severities = Severities {
+            needs_update: DiagnosticSeverity::HINT,
+            up_to_date: DiagnosticSeverity::INFORMATION,
+            unknown_dep: DiagnosticSeverity::WARNING,
+        };
+
+        let lockfile = Lockfile::parse(indoc! {r#"
+            [[package]]
+            name = "tokio"
+            version = "0.2.25"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+        "#})
+        .unwrap();
+
+        let messages: Vec<_> = version_diagnostics(
+            MANIFEST,
+            &dependencies,
+            &registry(),
+            &severities,
+            Some(&lockfile),
+            PositionEncoding::Utf16,
+        )
+        .into_iter()
+        .filter(|diagnostic| {
+            diagnostic.code == Some(NumberOrString::Number(diagnostic_codes::NEEDS_UPDATE))
+        })
+        .map(|diagnostic| diagnostic.message)
+        .collect();
+
+        assert_eq!(
+            messages,
+            vec![
+                "tokio: 1.40.0 (0.2.25 locked) (breaking)",
+                "anyhow: 1.0.80",
+                "log: 0.4.22"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lockfile_mismatches() {
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), MANIFEST)
+            .await;
+
+        let dependencies = parse::versioned(&packages);
+
+        let lockfile = Lockfile::parse(indoc! {r#"
+            [[package]]
+            name = "serde"
+            version = "1.0.100"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "anyhow"
+            version = "1.0.49"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+        "#})
+        .unwrap();
+
+        let diagnostics: Vec<_> = lockfile_diagnostics(&dependencies, &lockfile)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.range.start.line, diagnostic.message))
+            .collect();
+
+        assert_eq!(
+            diagnostics,
+            vec![(
+                6,
+                "anyhow: =1.0.50 excludes locked version 1.0.49, run `cargo update`".to_string()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn prerelease_requirements() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            beta = "1.0.0-beta.1"
+            rc = "=2.0.0-rc.1"
+            alpha = "0.3.0-alpha.2"
+            serde = "1"
+        "#};
+
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), cargo)
+            .await;
+
+        let dependencies = parse::versioned(&packages);
+
+        let statuses = HashMap::from([
+            (
+                "beta".to_string(),
+                PrereleaseStatus::Prerelease(Version::parse("1.0.0-beta.2").unwrap()),
+            ),
+            (
+                "rc".to_string(),
+                PrereleaseStatus::Stable(Version::new(2, 0, 0)),
+            ),
+            ("alpha".to_string(), PrereleaseStatus::UpToDate),
+        ]);
+
+        let severities = Severities {
+            needs_update: DiagnosticSeverity::HINT,
+            up_to_date: DiagnosticSeverity::INFORMATION,
+            unknown_dep: DiagnosticSeverity::WARNING,
+        };
+
+        assert_eq!(
+            prerelease_diagnostics(
+                cargo,
+                &dependencies,
+                &statuses,
+                &severities,
+                PositionEncoding::Utf16
+            ),
+            vec![
+                diagnostic(
+                    (1, 8, 20),
+                    DiagnosticSeverity::HINT,
+                    diagnostic_codes::NEEDS_UPDATE,
+                    "beta: newer pre-release 1.0.0-beta.2 available",
+                    Some(json!({
+                        "newest_version": "1.0.0-beta.2",
+                        "replacement": "1.0.0-beta.2",
+                        "breaking": false,
+                    })),
+                ),
+                diagnostic(
+                    (2, 6, 17),
+                    DiagnosticSeverity::HINT,
+                    diagnostic_codes::NEEDS_UPDATE,
+                    "rc: stable 2.0.0 available",
+                    Some(json!({
+                        "newest_version": "2.0.0",
+                        "replacement": "=2.0.0",
+                        "breaking": false,
+                    })),
+                ),
+                diagnostic(
+                    (3, 0, 0),
+                    DiagnosticSeverity::INFORMATION,
+                    diagnostic_codes::UP_TO_DATE,
+                    "✓",
+                    None,
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn requirement_style_policy() {
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), MANIFEST)
+            .await;
+
+        let dependencies = parse::versioned(&packages);
+
+        let lockfile = Lockfile::parse(indoc! {r#"
+            [[package]]
+            name = "serde"
+            version = "1.0.150"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+        "#})
+        .unwrap();
+
+        let fixes = |style| -> Vec<_> {
+            style_diagnostics(
+                MANIFEST,
+                &dependencies,
+                style,
+                Some(&lockfile),
+                PositionEncoding::Utf16,
+            )
+            .iter()
+            .map(|diagnostic| (diagnostic.range.start.line, style_fix(diagnostic)))
+            .collect()
+        };
+
+        assert_eq!(
+            fixes(RequirementStyle::Exact),
+            vec![
+                (4, Some("=1.0.150".to_string())),
+                (5, Some("=0.2.0".to_string())),
+                (8, Some("=1.0.0".to_string())),
+            ]
+        );
+        assert!(fixes(RequirementStyle::Any).is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_use_workspace() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1.0"
+            tokio = { version = "1", features = ["rt"] }
+            anyhow.version = "1"
+            rand = "0.8"
+            log = { features = ["kv_ø"], version = "0.4" }
+            bytes = { path = "../bytes", version = "1" }
+            ryu = { package = "ryu-fork", version = "1" }
+
+            [dependencies.regex]
+            version = "1"
+
+            [dependencies.itoa]
+            git = "https://github.com/dtolnay/itoa"
+            version = "1"
+
+            [dependencies.memchr]
+            version = "2"
+            path = "../memchr"
+        "#};
+
+        let workspace = crate::parse::workspace_dependencies(indoc! {r#"
+            [workspace]
+            members = ["*"]
+
+            [workspace.dependencies]
+            serde = "1"
+            tokio = "1"
+            anyhow = "1"
+            regex = "1"
+            log = "0.4"
+            bytes = "1"
+            ryu = "1"
+            itoa = "1"
+            memchr = "2"
+        "#});
+
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), cargo)
+            .await;
+        let dependencies = parse::versioned(&packages);
+
+        let fixed: Vec<_> =
+            workspace_diagnostics(cargo, &dependencies, &workspace, PositionEncoding::Utf16)
+                .iter()
+                .map(|diagnostic| {
+                    let edit = workspace_fix(diagnostic).unwrap();
+                    let line = cargo.lines().nth(edit.range.start.line as usize).unwrap();
+                    let encoding = PositionEncoding::Utf16;
+                    let start = encoding.byte_offset(line, edit.range.start.character);
+                    let end = encoding.byte_offset(line, edit.range.end.character);
+                    format!("{}{}{}", &line[..start], edit.new_text, &line[end..])
+                })
+                .collect();
+
+        assert_eq!(
+            fixed,
+            vec![
+                "serde.workspace = true",
+                r#"tokio = { workspace = true, features = ["rt"] }"#,
+                "anyhow.workspace = true",
+                r#"log = { features = ["kv_ø"], workspace = true }"#,
+                "workspace = true",
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_features() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = { version = "1", default-features = false }
+            tokio = { version = "1", default-features = false, features = ["rt"] }
+            anyhow = "1"
+            rand = { version = "0.8", features = [] }
+            log = { version = "0.4", default-features = false, features = [] }
+
+            [dependencies.regex]
+            version = "1"
+            default-features = false
+            features = [
+                "std",
+            ]
+
+            [dependencies.toml]
+            default-features = false
+            version = "0.8"
+        "#};
+
+        let packages = crate::parse::parse_manifest(cargo);
+        let dependencies = parse::versioned(&packages);
+
+        let flagged: Vec<_> = empty_features_diagnostics(&dependencies)
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+
+        assert_eq!(
+            flagged,
+            vec![
+                "serde: default features are disabled and no features are enabled",
+                "log: default features are disabled and no features are enabled",
+                "toml: default features are disabled and no features are enabled",
+            ]
+        );
+    }
+
+    #[test]
+    fn unbounded_requirements() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = ">=0"
+            tokio = { version = ">=1" }
+            anyhow = ">=1, <2"
+            log = "0.4"
+            unknown-crate = ">=0.0.0"
+        "#};
+
+        let packages = crate::parse::parse_manifest(cargo);
+        let dependencies = parse::versioned(&packages);
+
+        let flagged: Vec<_> = unbounded_diagnostics(&dependencies, &registry())
+            .iter()
+            .map(|diagnostic| {
+                (
+                    diagnostic.range.start.line,
+                    diagnostic.message.clone(),
+                    style_fix(diagnostic),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            flagged,
+            vec![
+                (
+                    1,
+                    "serde: `>=0` has no upper bound and accepts breaking releases".to_string(),
+                    Some("^1.0.200".to_string())
+                ),
+                (
+                    2,
+                    "tokio: `>=1` has no upper bound and accepts breaking releases".to_string(),
+                    Some("^1.40.0".to_string())
+                ),
+                (
+                    5,
+                    "unknown-crate: `>=0.0.0` has no upper bound and accepts breaking releases"
+                        .to_string(),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn minimum_major_versions() {
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), MANIFEST)
+            .await;
+
+        let dependencies = parse::versioned(&packages);
+
+        // Below, at, above and below a minimum the newest release doesn't meet.
+        let minimums = HashMap::from([
+            ("tokio".to_string(), 1),
+            ("serde".to_string(), 1),
+            ("anyhow".to_string(), 0),
+            ("yanked-pin".to_string(), 3),
+        ]);
+
+        let flagged: Vec<_> = minimum_major_diagnostics(
+            MANIFEST,
+            &dependencies,
+            &minimums,
+            &registry(),
+            PositionEncoding::Utf16,
+        )
+        .iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.range.start.line,
+                diagnostic.message.clone(),
+                style_fix(diagnostic),
+            )
+        })
+        .collect();
+
+        assert_eq!(
+            flagged,
+            vec![
+                (
+                    5,
+                    "tokio: major version 1 or newer is required".to_string(),
+                    Some("1.40.0".to_string())
+                ),
+                (
+                    7,
+                    "yanked-pin: major version 3 or newer is required".to_string(),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn minimum_major_versions_without_lower_bound() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "*"
+            tokio = "<2"
+            log = ">=2, <3"
+        "#};
+
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), cargo)
+            .await;
+
+        let dependencies = parse::versioned(&packages);
+
+        let minimums = HashMap::from([
+            ("serde".to_string(), 2),
+            ("tokio".to_string(), 2),
+            ("log".to_string(), 2),
+        ]);
+
+        let flagged: Vec<_> = minimum_major_diagnostics(
+            cargo,
+            &dependencies,
+            &minimums,
+            &HashMap::new(),
+            PositionEncoding::Utf16,
+        )
+        .iter()
+        .map(|diagnostic| (diagnostic.range.start.line, diagnostic.message.clone()))
+        .collect();
+
+        assert_eq!(
+            flagged,
+            vec![
+                (1, "serde: major version 2 or newer is required".to_string()),
+                (2, "tokio: major version 2 or newer is required".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn redundant_patches() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+
+            [patch.crates-io]
+            serde = { git = "https://github.com/serde-rs/serde", version = "1.0.200" }
+            tokio = { path = "../tokio", version = "1.41.0" }
+            log = { git = "https://github.com/rust-lang/log" }
+            my-log = { package = "log", path = "../log", version = "0.4.22" }
+
+            [patch.crates-io.anyhow]
+            path = "../anyhow"
+            version = "1.0.80"
+
+            [patch.crates-io.my-tokio]
+            version = "1.40.0"
+            package = "tokio"
+        "#};
+
+        // Patches at the newest release are flagged, unlike newer or unversioned ones.
+        assert_eq!(
+            redundant_patch_diagnostics(
+                cargo,
+                &parse::patched_dependencies(cargo),
+                &registry(),
+                "crates.io",
+                PositionEncoding::Utf16,
+            ),
+            vec![
+                diagnostic(
+                    (4, 64, 71),
+                    DiagnosticSeverity::INFORMATION,
+                    diagnostic_codes::REDUNDANT_PATCH,
+                    "serde: 1.0.200 has been published on crates.io, this patch may no longer be needed",
+                    None,
+                ),
+                diagnostic(
+                    (7, 56, 62),
+                    DiagnosticSeverity::INFORMATION,
+                    diagnostic_codes::REDUNDANT_PATCH,
+                    "my-log: log 0.4.22 has been published on crates.io, this patch may no longer be needed",
+                    None,
+                ),
+                diagnostic(
+                    (11, 11, 17),
+                    DiagnosticSeverity::INFORMATION,
+                    diagnostic_codes::REDUNDANT_PATCH,
+                    "anyhow: 1.0.80 has been published on crates.io, this patch may no longer be needed",
+                    None,
+                ),
+                diagnostic(
+                    (14, 11, 17),
+                    DiagnosticSeverity::INFORMATION,
+                    diagnostic_codes::REDUNDANT_PATCH,
+                    "my-tokio: tokio 1.40.0 has been published on crates.io, this patch may no longer be needed",
+                    None,
+                ),
+            ]
+        );
+
+        // The message names the registry which was actually queried.
+        let private = redundant_patch_diagnostics(
+            cargo,
+            &parse::patched_dependencies(cargo),
+            &registry(),
+            "registry.example.com",
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(
+            private[0].message,
+            "serde: 1.0.200 has been published on registry.example.com, this patch may no longer be needed"
+        );
+    }
+
+    #[tokio::test]
+    async fn summary_diagnostic() {
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), MANIFEST)
+            .await;
+
+        let dependencies = parse::versioned(&packages);
+
+        let severities = Severities {
+            needs_update: DiagnosticSeverity::HINT,
+            up_to_date: DiagnosticSeverity::INFORMATION,
+            unknown_dep: DiagnosticSeverity::WARNING,
+        };
+
+        let diagnostics = version_diagnostics(
+            MANIFEST,
+            &dependencies,
+            &registry(),
+            &severities,
+            None,
+            PositionEncoding::Utf16,
+        );
+        let summarized = summarize(MANIFEST, diagnostics, &severities, PositionEncoding::Utf16);
+
+        let edit = |line, start, end, new_text: &str| {
+            json!({
+                "range": {
+                    "start": { "line": line, "character": start },
+                    "end": { "line": line, "character": end },
+                },
+                "newText": new_text,
+            })
+        };
+
+        assert_eq!(
+            summarized,
+            vec![
+                diagnostic(
+                    (8, 17, 18),
+                    DiagnosticSeverity::WARNING,
+                    diagnostic_codes::UNKNOWN_DEP,
+                    "unknown-crate: Unknown crate",
+                    None,
+                ),
+                diagnostic(
+                    (0, 0, 9),
+                    DiagnosticSeverity::HINT,
+                    diagnostic_codes::SUMMARY,
+                    "3 of 5 dependencies are outdated and 1 is yanked",
+                    Some(json!({
+                        "edits": [
+                            edit(5, 21, 24, "1.40.0"),
+                            edit(6, 10, 17, "=1.0.80"),
+                            edit(7, 14, 20, "=2.0.2"),
+                            edit(9, 7, 9, "0.4.22"),
+                        ],
+                    })),
+                ),
+            ]
+        );
+        assert_eq!(
+            summary_edits(&summarized[1]).map(|edits| edits.len()),
+            Some(4)
+        );
+
+        // Manifests without any outdated dependencies are summarized as such.
+        let up_to_date = version_diagnostics(
+            MANIFEST,
+            &dependencies[..1],
+            &registry(),
+            &severities,
+            None,
+            PositionEncoding::Utf16,
+        );
+        let summarized = summarize(MANIFEST, up_to_date, &severities, PositionEncoding::Utf16);
+        assert_eq!(summarized[0].message, "1 dependency is up to date");
+        assert_eq!(summary_edits(&summarized[0]), None);
+    }
+
+    #[tokio::test]
+    async fn stale_crates() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+            tokio = "1"
+            anyhow = "1"
+        "#};
+
+        let packages = ManifestTracker::default()
+            .update_from_source(Url::parse("file:///Cargo.toml").unwrap(), cargo)
+            .await;
+
+        // Old, recent, and published in the future as far as the local clock knows.
+        let now = OffsetDateTime::UNIX_EPOCH + Duration::days(20 * 365);
+        let newest_packages: HashMap<_, _> = [
+            ("serde", now - Duration::days(3 * 365)),
+            ("tokio", now - Duration::days(30)),
+            ("anyhow", now + Duration::days(3 * 365)),
+        ]
+        .into_iter()
+        .map(|(name, published_at)| {
+            let release = Release {
+                version: Version::new(1, 0, 0),
+                published_at: Some(published_at),
+                yanked: Vec::new(),
+            };
+            (name.to_string(), Some(release))
+        })
+        .collect();
+
+        let flagged: Vec<_> = stale_diagnostics(
+            &parse::versioned(&packages),
+            &newest_packages,
+            2,
+            DiagnosticSeverity::WARNING,
+            now,
+        )
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect();
+
+        assert_eq!(flagged, ["serde's latest release is 3 years old"]);
+    }
+
+    #[test]
+    fn sort_by_status() {
+        let up_to_date = diagnostic(
+            (1, 0, 0),
+            DiagnosticSeverity::INFORMATION,
+            diagnostic_codes::UP_TO_DATE,
+            "✓",
+            None,
+        );
+        let unknown = diagnostic(
+            (2, 0, 1),
+            DiagnosticSeverity::WARNING,
+            diagnostic_codes::UNKNOWN_DEP,
+            "foo: Unknown crate",
+            None,
+        );
+        let outdated = diagnostic(
+            (3, 0, 1),
+            DiagnosticSeverity::HINT,
+            diagnostic_codes::NEEDS_UPDATE,
+            "bar: 1.0.0",
+            None,
+        );
+
+        let mut diagnostics = vec![up_to_date.clone(), unknown.clone(), outdated.clone()];
+        sort(&mut diagnostics, DiagnosticOrder::Document);
+        assert_eq!(
+            diagnostics,
+            [up_to_date.clone(), unknown.clone(), outdated.clone()]
+        );
+
+        sort(&mut diagnostics, DiagnosticOrder::Status);
+        assert_eq!(
+            diagnostics,
+            [outdated.clone(), unknown.clone(), up_to_date.clone()]
+        );
+
+        sort(&mut diagnostics, DiagnosticOrder::Severity);
+        assert_eq!(diagnostics, [unknown, up_to_date, outdated]);
+    }
+}
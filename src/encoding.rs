@@ -0,0 +1,189 @@
+use tower_lsp::lsp_types::{ClientCapabilities, PositionEncodingKind, Range};
+
+/// How the character offsets of positions are counted, as negotiated with the client.
+///
+/// The parser works with byte offsets, which are only correct for UTF-8, so ranges
+/// are converted once a manifest has been parsed, and converted back before slicing
+/// the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    /// The default, and the only encoding every client is required to support.
+    #[default]
+    Utf16,
+}
+
+impl PositionEncoding {
+    /// Prefer UTF-8 if the client supports it, since it matches the parser's byte offsets.
+    pub fn negotiate(capabilities: &ClientCapabilities) -> Self {
+        let supports_utf8 = capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .is_some_and(|encodings| encodings.contains(&PositionEncodingKind::UTF8));
+
+        if supports_utf8 {
+            PositionEncoding::Utf8
+        } else {
+            PositionEncoding::Utf16
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+        }
+    }
+
+    /// Character offset of the given byte offset within the line. Offsets past the
+    /// end of the line, such as those placing hints after it, are kept as they are.
+    pub fn column(self, line: &str, byte: usize) -> u32 {
+        match self {
+            PositionEncoding::Utf8 => byte as u32,
+            PositionEncoding::Utf16 => {
+                let (within, beyond) = match line.get(..byte) {
+                    Some(prefix) => (prefix, 0),
+                    None if byte > line.len() => (line, byte - line.len()),
+                    // Byte offsets produced by the parser always fall on character boundaries.
+                    None => return byte as u32,
+                };
+                (within.encode_utf16().count() + beyond) as u32
+            }
+        }
+    }
+
+    /// Byte offset of the given character offset within the line, the inverse of
+    /// [`PositionEncoding::column`].
+    pub fn byte_offset(self, line: &str, column: u32) -> usize {
+        let column = column as usize;
+        match self {
+            PositionEncoding::Utf8 => column,
+            PositionEncoding::Utf16 => {
+                let mut units = 0;
+                for (byte, c) in line.char_indices() {
+                    if units >= column {
+                        return byte;
+                    }
+                    units += c.len_utf16();
+                }
+                line.len() + column.saturating_sub(units)
+            }
+        }
+    }
+
+    /// Convert a single-line range of byte offsets within the source to this encoding.
+    pub fn encode(self, source: &str, range: Range) -> Range {
+        self.convert(source, range, |line, character| {
+            self.column(line, character as usize)
+        })
+    }
+
+    /// Convert a range of byte offsets on the given line to this encoding. Unlike
+    /// [`PositionEncoding::encode`], this doesn't search the source for the line, for
+    /// converting many ranges of the same source.
+    pub fn encode_line(self, line: &str, range: Range) -> Range {
+        self.convert_line(line, range, |line, character| {
+            self.column(line, character as usize)
+        })
+    }
+
+    /// Convert a single-line range in this encoding to byte offsets within the source.
+    pub fn decode(self, source: &str, range: Range) -> Range {
+        self.convert(source, range, |line, character| {
+            self.byte_offset(line, character) as u32
+        })
+    }
+
+    fn convert(self, source: &str, range: Range, convert: impl Fn(&str, u32) -> u32) -> Range {
+        if self == PositionEncoding::Utf8 {
+            return range;
+        }
+
+        let line = source
+            .lines()
+            .nth(range.start.line as usize)
+            .unwrap_or_default();
+        self.convert_line(line, range, convert)
+    }
+
+    fn convert_line(
+        self,
+        line: &str,
+        mut range: Range,
+        convert: impl Fn(&str, u32) -> u32,
+    ) -> Range {
+        if self == PositionEncoding::Utf8 {
+            return range;
+        }
+
+        range.start.character = convert(line, range.start.character);
+        range.end.character = convert(line, range.end.character);
+        range
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{
+        ClientCapabilities, GeneralClientCapabilities, Position, PositionEncodingKind, Range,
+    };
+
+    use super::PositionEncoding;
+
+    #[test]
+    fn negotiate() {
+        let capabilities = |encodings: Option<Vec<PositionEncodingKind>>| ClientCapabilities {
+            general: Some(GeneralClientCapabilities {
+                position_encodings: encodings,
+                ..GeneralClientCapabilities::default()
+            }),
+            ..ClientCapabilities::default()
+        };
+
+        assert_eq!(
+            PositionEncoding::negotiate(&ClientCapabilities::default()),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(&capabilities(Some(vec![
+                PositionEncodingKind::UTF16,
+                PositionEncodingKind::UTF8
+            ]))),
+            PositionEncoding::Utf8
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(&capabilities(Some(vec![PositionEncodingKind::UTF32]))),
+            PositionEncoding::Utf16
+        );
+    }
+
+    #[test]
+    fn utf16_columns() {
+        // `ø` takes two bytes in UTF-8 but a single code unit in UTF-16, whereas the
+        // emoji takes four bytes and two code units.
+        let line = r#"foo = { path = "../føø🦀", version = "1" }"#;
+        let byte = line.find("1\"").unwrap();
+
+        let utf16 = PositionEncoding::Utf16;
+        assert_eq!(utf16.column(line, byte), byte as u32 - 4);
+        assert_eq!(utf16.byte_offset(line, byte as u32 - 4), byte);
+        assert_eq!(utf16.column(line, line.len() + 1), line.len() as u32 - 3);
+        assert_eq!(
+            utf16.byte_offset(line, line.len() as u32 - 3),
+            line.len() + 1
+        );
+
+        let source = format!("[dependencies]\n{line}");
+        let range = Range::new(
+            Position::new(1, byte as u32),
+            Position::new(1, byte as u32 + 1),
+        );
+        let encoded = utf16.encode(&source, range);
+        assert_eq!(encoded.start.character, byte as u32 - 4);
+        assert_eq!(utf16.encode_line(line, range), encoded);
+        assert_eq!(utf16.decode(&source, encoded), range);
+
+        assert_eq!(PositionEncoding::Utf8.encode(&source, range), range);
+    }
+}
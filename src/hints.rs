@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use time::OffsetDateTime;
+use tower_lsp::lsp_types::{InlayHint, InlayHintLabel, InlayHintTooltip, Position, Range};
+
+use crate::crates::Release;
+use crate::encoding::PositionEncoding;
+use crate::lockfile::Lockfile;
+use crate::parse::{DependencyVersion, DependencyWithVersion};
+use crate::requirement;
+use crate::settings;
+
+/// Where the hint of a dependency is placed on its line.
+#[derive(Debug, Clone, Copy)]
+pub enum Anchor<'a> {
+    /// Right after the version requirement.
+    VersionEnd,
+    /// After the content of the line within the given lines of the source, ignoring
+    /// trailing whitespace, so hints line up regardless of what follows the requirement.
+    LineEnd {
+        lines: &'a [&'a str],
+        encoding: PositionEncoding,
+    },
+}
+
+impl Anchor<'_> {
+    fn position(self, version: Range) -> Position {
+        match self {
+            Anchor::VersionEnd => Position::new(version.end.line, version.end.character + 1),
+            Anchor::LineEnd { lines, encoding } => {
+                let line = lines
+                    .get(version.end.line as usize)
+                    .copied()
+                    .unwrap_or_default()
+                    .trim_end();
+                Position::new(version.end.line, encoding.column(line, line.len()))
+            }
+        }
+    }
+}
+
+/// Build the inlay hints shown after the version requirement of each dependency.
+///
+/// Dependencies are hinted using the `up_to_date` or `needs_update` template depending
+/// on their status, and an empty template disables the hints of that category only.
+/// Dependencies whose newest release hasn't been looked up aren't hinted.
+pub fn version_hints(
+    dependencies: Vec<DependencyWithVersion>,
+    newest_packages: &HashMap<String, Option<Release>>,
+    lockfile: Option<&Lockfile>,
+    up_to_date: &str,
+    needs_update: &str,
+    anchor: Anchor,
+    now: OffsetDateTime,
+) -> Vec<InlayHint> {
+    let mut hints = Vec::new();
+
+    for dep in dependencies {
+        let Some(Some(release)) = newest_packages.get(&dep.name) else {
+            continue;
+        };
+        let newest_version = &release.version;
+        let age = settings::humanize_age(release.published_at, now);
+        let (hint, tip, range) = match dep.version {
+            DependencyVersion::Complete { range, version } => {
+                let status = requirement::status(&version.to_string(), &version, release);
+
+                // The locked version is what's actually built, so it may be outdated
+                // even if the requirement allows the newest version.
+                let locked = lockfile.and_then(|lockfile| lockfile.locked(&dep.name, &version));
+                let is_up_to_date = match locked {
+                    Some(locked) => locked >= newest_version,
+                    None => status == requirement::Status::UpToDate,
+                };
+                let current = match locked {
+                    Some(locked) => locked.to_string(),
+                    None => version.to_string(),
+                };
+
+                if is_up_to_date {
+                    if up_to_date.is_empty() {
+                        continue;
+                    }
+                    let hint = settings::format_hint(
+                        up_to_date,
+                        &current,
+                        &newest_version.to_string(),
+                        &age,
+                    )
+                    .replace("{}", &current);
+                    (hint, "up to date".to_string(), range)
+                } else {
+                    if needs_update.is_empty() {
+                        continue;
+                    }
+                    let latest = newest_version.to_string();
+                    let tip = match locked {
+                        Some(locked) => format!("latest stable version ({locked} locked)"),
+                        None => "latest stable version".to_string(),
+                    };
+                    let hint = settings::format_hint(needs_update, &current, &latest, &age)
+                        .replace("{}", &latest);
+                    (hint, tip, range)
+                }
+            }
+            DependencyVersion::Partial { range, version } => {
+                if needs_update.is_empty() {
+                    continue;
+                }
+                let latest = newest_version.to_string();
+                let hint = settings::format_hint(needs_update, &version, &latest, &age)
+                    .replace("{}", &latest);
+                (hint, "latest stable version".to_string(), range)
+            }
+            DependencyVersion::Invalid { .. } => continue,
+        };
+
+        hints.push(InlayHint {
+            position: anchor.position(range),
+            label: InlayHintLabel::String(hint),
+            kind: None,
+            text_edits: None,
+            tooltip: Some(InlayHintTooltip::String(tip)),
+            padding_left: Some(true),
+            padding_right: None,
+            data: None,
+        });
+    }
+
+    hints
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use time::OffsetDateTime;
+    use tower_lsp::lsp_types::{InlayHintLabel, Position};
+
+    use crate::crates::newest_packages;
+    use crate::encoding::PositionEncoding;
+    use crate::parse::{parse_manifest, versioned};
+
+    use super::{version_hints, Anchor};
+
+    fn hints(up_to_date: &str, needs_update: &str) -> Vec<(u32, String)> {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+            tokio = "0.2"
+            anyhow = "1"
+            rand = "0.
+            uncached = "1"
+        "#};
+
+        anchored_hints(cargo, up_to_date, needs_update, Anchor::VersionEnd)
+            .into_iter()
+            .map(|(position, label)| (position.line, label))
+            .collect()
+    }
+
+    fn anchored_hints(
+        cargo: &str,
+        up_to_date: &str,
+        needs_update: &str,
+        anchor: Anchor,
+    ) -> Vec<(Position, String)> {
+        let dependencies = versioned(&parse_manifest(cargo))
+            .into_iter()
+            .cloned()
+            .collect();
+
+        version_hints(
+            dependencies,
+            &newest_packages(),
+            None,
+            up_to_date,
+            needs_update,
+            anchor,
+            OffsetDateTime::now_utc(),
+        )
+        .into_iter()
+        .map(|hint| {
+            let InlayHintLabel::String(label) = hint.label else {
+                panic!("expected string label");
+            };
+            (hint.position, label)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn only_up_to_date() {
+        assert_eq!(
+            hints("✓", ""),
+            vec![(1, "✓".to_string()), (3, "✓".to_string())]
+        );
+    }
+
+    #[test]
+    fn only_needs_update() {
+        assert_eq!(
+            hints("", "{latest}"),
+            vec![(2, "1.40.0".to_string()), (4, "0.8.5".to_string())]
+        );
+    }
+
+    #[test]
+    fn both_hints() {
+        assert_eq!(
+            hints("✓", "{current} → {latest}"),
+            vec![
+                (1, "✓".to_string()),
+                (2, "^0.2 → 1.40.0".to_string()),
+                (3, "✓".to_string()),
+                (4, "0. → 0.8.5".to_string()),
+            ]
+        );
+        assert!(hints("", "").is_empty());
+    }
+
+    #[test]
+    fn anchors() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"   
+            tokio = { version = "0.2", features = ["full"] }
+            anyhow = { version = "1", path = "../ånyhow" } # vendored
+        "#};
+
+        let positions = |anchor| -> Vec<_> {
+            anchored_hints(cargo, "✓", "{latest}", anchor)
+                .into_iter()
+                .map(|(position, _)| (position.line, position.character))
+                .collect()
+        };
+
+        assert_eq!(
+            positions(Anchor::VersionEnd),
+            vec![(1, 11), (2, 25), (3, 24)]
+        );
+        assert_eq!(
+            positions(Anchor::LineEnd {
+                lines: &cargo.lines().collect::<Vec<_>>(),
+                encoding: PositionEncoding::Utf16,
+            }),
+            vec![(1, 11), (2, 48), (3, 57)]
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use semver::{Version, VersionReq};
+
+use crate::crates::PublishedVersion;
+
+/// Number of recent versions listed when hovering a version requirement.
+pub const RECENT_VERSIONS: usize = 10;
+
+/// Render a Markdown table of the most recent versions of a crate, along with
+/// when they were published and whether they have been yanked. The newest version
+/// matching the requirement and the latest version are highlighted.
+pub fn version_table(
+    crate_name: &str,
+    versions: &[PublishedVersion],
+    requirement: Option<&VersionReq>,
+    limit: usize,
+) -> String {
+    let mut versions: Vec<_> = versions.iter().collect();
+    versions.sort_by(|a, b| b.version.cmp(&a.version));
+
+    let available = || versions.iter().filter(|version| !version.yanked);
+
+    let latest = available()
+        .find(|version| version.version.pre.is_empty())
+        .or_else(|| available().next())
+        .map(|version| &version.version);
+
+    let current = requirement.and_then(|requirement| {
+        available()
+            .find(|version| requirement.matches(&version.version))
+            .map(|version| &version.version)
+    });
+
+    let mut table =
+        format!("**{crate_name}**\n\n| Version | Published | Yanked |\n|---|---|---|\n");
+
+    for version in versions.iter().take(limit) {
+        let mut labels = Vec::new();
+        if Some(&version.version) == current {
+            labels.push("current");
+        }
+        if Some(&version.version) == latest {
+            labels.push("latest");
+        }
+
+        let label = if labels.is_empty() {
+            version.version.to_string()
+        } else {
+            format!("**{}** ({})", version.version, labels.join(", "))
+        };
+
+        let published = version
+            .published_at
+            .map(|published_at| published_at.date().to_string())
+            .unwrap_or_default();
+
+        let yanked = if version.yanked { "yes" } else { "" };
+
+        table.push_str(&format!("| {label} | {published} | {yanked} |\n"));
+    }
+
+    if versions.len() > limit {
+        table.push_str(&format!(
+            "\n{} older versions not shown.",
+            versions.len() - limit
+        ));
+    }
+
+    table
+}
+
+/// Note that the latest version requires a newer version of Rust than the newest
+/// version matching the requirement, if it does.
+pub fn rust_version_note(
+    versions: &[PublishedVersion],
+    requirement: &VersionReq,
+) -> Option<String> {
+    let available = || {
+        versions
+            .iter()
+            .filter(|version| !version.yanked && version.version.pre.is_empty())
+    };
+
+    let latest = available().max_by(|a, b| a.version.cmp(&b.version))?;
+    let current = available()
+        .filter(|version| requirement.matches(&version.version))
+        .max_by(|a, b| a.version.cmp(&b.version))?;
+
+    let required = latest.rust_version.as_deref()?;
+    let needed = current.rust_version.as_deref().unwrap_or("any version");
+
+    let newer = match current.rust_version.as_deref().map(parse_rust_version) {
+        Some(needed) => parse_rust_version(required) > needed,
+        None => parse_rust_version(required).is_some(),
+    };
+
+    newer.then(|| {
+        format!(
+            "{} requires Rust {required} (the current version {} needs {needed})",
+            latest.version, current.version
+        )
+    })
+}
+
+/// Parse a `rust-version` field, which may omit the minor and patch components.
+fn parse_rust_version(rust_version: &str) -> Option<Version> {
+    let mut components = rust_version.trim().split('.').map(str::parse::<u64>);
+
+    Some(Version::new(
+        components.next()?.ok()?,
+        components.next().unwrap_or(Ok(0)).ok()?,
+        components.next().unwrap_or(Ok(0)).ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::{Version, VersionReq};
+    use time::OffsetDateTime;
+
+    use crate::crates::PublishedVersion;
+
+    use super::{rust_version_note, version_table};
+
+    fn published(version: &str, yanked: bool) -> PublishedVersion {
+        PublishedVersion {
+            version: Version::parse(version).unwrap(),
+            yanked,
+            published_at: None,
+            rust_version: None,
+            features: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn rust_version_bumps() {
+        let with_rust_version = |version: &str, rust_version: Option<&str>| PublishedVersion {
+            rust_version: rust_version.map(str::to_string),
+            ..published(version, false)
+        };
+
+        let versions = vec![
+            with_rust_version("1.0.0", Some("1.70")),
+            with_rust_version("1.1.0", Some("1.70.0")),
+            with_rust_version("2.0.0", Some("1.75")),
+            with_rust_version("2.1.0-rc.1", Some("1.80")),
+        ];
+
+        let note = |requirement: &str| {
+            rust_version_note(&versions, &VersionReq::parse(requirement).unwrap())
+        };
+
+        assert_eq!(
+            note("1").as_deref(),
+            Some("2.0.0 requires Rust 1.75 (the current version 1.1.0 needs 1.70.0)")
+        );
+        assert_eq!(note("2"), None, "already on the latest version");
+
+        let versions = vec![
+            with_rust_version("1.0.0", None),
+            with_rust_version("2.0.0", Some("1.56")),
+        ];
+        assert_eq!(
+            rust_version_note(&versions, &VersionReq::parse("1").unwrap()).as_deref(),
+            Some("2.0.0 requires Rust 1.56 (the current version 1.0.0 needs any version)")
+        );
+
+        let versions = vec![
+            with_rust_version("1.0.0", Some("1.80")),
+            with_rust_version("2.0.0", Some("1.75")),
+        ];
+        assert_eq!(
+            rust_version_note(&versions, &VersionReq::parse("1").unwrap()),
+            None,
+            "lowered requirements aren't worth pointing out"
+        );
+    }
+
+    #[test]
+    fn version_table_highlights_current_and_latest() {
+        let mut versions = vec![
+            published("1.0.0", false),
+            published("1.1.0", false),
+            published("1.2.0", true),
+            published("2.0.0", false),
+            published("2.1.0-rc.1", false),
+        ];
+        versions[0].published_at = OffsetDateTime::from_unix_timestamp(1704164645).ok();
+
+        let requirement = VersionReq::parse("1").unwrap();
+
+        assert_eq!(
+            version_table("foo", &versions, Some(&requirement), 4),
+            "**foo**\n\n\
+            | Version | Published | Yanked |\n\
+            |---|---|---|\n\
+            | 2.1.0-rc.1 |  |  |\n\
+            | **2.0.0** (latest) |  |  |\n\
+            | 1.2.0 |  | yes |\n\
+            | **1.1.0** (current) |  |  |\n\
+            \n1 older versions not shown."
+        );
+
+        assert!(version_table("foo", &versions, None, 10).contains("| 1.0.0 | 2024-01-02 |  |"));
+    }
+}
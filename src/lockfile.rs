@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+
+/// Versions of each registry package resolved within a `Cargo.lock` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lockfile {
+    packages: HashMap<String, Vec<Version>>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: Version,
+    /// Absent for path dependencies and workspace members.
+    #[serde(default)]
+    source: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Document {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+impl Lockfile {
+    /// Parse the `[[package]]` entries of a lockfile, ignoring packages which don't
+    /// come from a registry, such as git and path dependencies.
+    pub fn parse(source: &str) -> Option<Self> {
+        let document: Document = match toml::from_str(source) {
+            Ok(document) => document,
+            Err(err) => {
+                tracing::debug!(%err, "ignoring invalid lockfile");
+                return None;
+            }
+        };
+
+        let mut packages: HashMap<String, Vec<Version>> = HashMap::new();
+        for package in document.package {
+            let from_registry = package.source.is_some_and(|source| {
+                source.starts_with("registry+") || source.starts_with("sparse+")
+            });
+
+            if from_registry {
+                packages
+                    .entry(package.name)
+                    .or_default()
+                    .push(package.version);
+            }
+        }
+
+        Some(Lockfile { packages })
+    }
+
+    /// Every locked version of the crate, in the order they appear in the lockfile.
+    pub fn versions(&self, crate_name: &str) -> &[Version] {
+        self.packages
+            .get(crate_name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The newest locked version of the crate which satisfies the requirement. Several
+    /// versions of a crate may be locked at once, and lockfiles which haven't been
+    /// updated since the requirement changed may not contain a matching version at all.
+    pub fn locked(&self, crate_name: &str, requirement: &VersionReq) -> Option<&Version> {
+        self.packages
+            .get(crate_name)?
+            .iter()
+            .filter(|version| requirement.matches(version))
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use semver::{Version, VersionReq};
+
+    use super::Lockfile;
+
+    #[test]
+    fn locked_versions() {
+        let lockfile = Lockfile::parse(indoc! {r#"
+            # This file is automatically @generated by Cargo.
+            version = 4
+
+            [[package]]
+            name = "example"
+            version = "0.1.0"
+            dependencies = ["serde"]
+
+            [[package]]
+            name = "serde"
+            version = "1.0.200"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "ddc6f9cc94d67c0e21aaf7eda3a010fd3af78ebf6e096aa6e2e13c79749cce4f"
+
+            [[package]]
+            name = "rand"
+            version = "0.7.3"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "rand"
+            version = "0.8.5"
+            source = "sparse+https://index.crates.io/"
+
+            [[package]]
+            name = "local"
+            version = "1.0.0"
+            source = "git+https://example.com/local.git#0123456789abcdef"
+        "#})
+        .unwrap();
+
+        let locked = |name: &str, requirement: &str| {
+            lockfile
+                .locked(name, &VersionReq::parse(requirement).unwrap())
+                .cloned()
+        };
+
+        assert_eq!(locked("serde", "1"), Some(Version::new(1, 0, 200)));
+        assert_eq!(locked("rand", "0.7"), Some(Version::new(0, 7, 3)));
+        assert_eq!(locked("rand", ">=0.7"), Some(Version::new(0, 8, 5)));
+        assert_eq!(locked("serde", "2"), None, "stale lockfile");
+        assert_eq!(locked("example", "0.1"), None, "workspace member");
+        assert_eq!(locked("local", "1"), None, "git dependency");
+
+        assert_eq!(
+            lockfile.versions("rand"),
+            [Version::new(0, 7, 3), Version::new(0, 8, 5)]
+        );
+        assert!(lockfile.versions("local").is_empty());
+
+        assert_eq!(Lockfile::parse("[[package]]\nname = "), None);
+    }
+}
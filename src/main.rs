@@ -1,22 +1,77 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+
 use crate::parse::{Dependency, DependencyWithVersion};
+use args::Arguments;
 use crates::api::CrateApi;
-use crates::cache::CrateCache;
+use crates::cache::{CachedVersion, CrateCache, SearchCache, VersionsCache};
+use crates::git::GitTags;
 use crates::sparse::CrateIndex;
-use crates::CrateLookup;
-use parse::{DependencyVersion, ManifestTracker};
-use settings::Settings;
-use tower_lsp::jsonrpc::Result;
+use crates::{
+    Crate, CrateError, CrateLookup, Progress, PublishedVersion, Release, SharedClient,
+    VersionStream, WithFallback,
+};
+use encoding::PositionEncoding;
+use lockfile::Lockfile;
+use parse::{CompletionContext, DependencyVersion, ManifestTracker};
+use requirement::PrereleaseStatus;
+use semver::VersionReq;
+use settings::{InlayHintAnchor, ManifestSettings, Settings};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tower::util::MapRequest;
+use tower::ServiceExt;
+use tower_lsp::jsonrpc::{self, Result};
 use tower_lsp::lsp_types::*;
-use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService, Server};
 
+mod args;
+mod cargo_config;
+mod conflicts;
 mod crates;
+mod diagnostics;
+mod encoding;
+mod hints;
+mod hover;
+mod lockfile;
 mod parse;
+mod preview;
+mod process;
+mod requirement;
 mod settings;
+mod summary;
 
 mod diagnostic_codes {
     pub const UP_TO_DATE: i32 = 0;
     pub const NEEDS_UPDATE: i32 = 1;
     pub const UNKNOWN_DEP: i32 = 2;
+    pub const STALE_CRATE: i32 = 3;
+    pub const INVALID_NAME: i32 = 4;
+    pub const CONFLICTING_CRATE: i32 = 5;
+    pub const NEWER_GIT_TAG: i32 = 6;
+    pub const YANKED: i32 = 7;
+    pub const INVALID_REQ: i32 = 8;
+    pub const LOCKFILE_MISMATCH: i32 = 9;
+    pub const REQUIREMENT_STYLE: i32 = 10;
+    pub const EMPTY_FEATURES: i32 = 11;
+    pub const SHOULD_USE_WORKSPACE: i32 = 12;
+    pub const UNBOUNDED_REQ: i32 = 13;
+    pub const BELOW_MINIMUM_MAJOR: i32 = 14;
+    pub const SUMMARY: i32 = 15;
+    pub const REDUNDANT_PATCH: i32 = 16;
+}
+
+mod commands {
+    pub const REFRESH_CRATE: &str = "crates-lsp.refreshCrate";
+    pub const SHOW_CONFIG: &str = "crates-lsp.showConfig";
+    pub const SET_VERSION: &str = "crates-lsp.setVersion";
+    pub const CACHE_STATS: &str = "crates-lsp.cacheStats";
+    pub const PREVIEW_UPDATES: &str = "crates-lsp.previewUpdates";
+    pub const OPEN_REPOSITORY: &str = "crates-lsp.openRepository";
+    pub const LATEST_VERSION: &str = "crates-lsp.latestVersion";
 }
 
 #[derive(Debug, Clone)]
@@ -27,15 +82,694 @@ struct Backend {
     api: CrateApi,
     sparse: CrateIndex,
     cache: CrateCache,
+    searches: SearchCache,
+    versions: VersionsCache,
+    http: SharedClient,
+    git_tags: GitTags,
+    /// Whether the client supports server-initiated work done progress.
+    work_done_progress: Arc<AtomicBool>,
+    /// Whether the client supports being asked to show documents.
+    show_document: Arc<AtomicBool>,
+    /// Whether the client supports registering file watchers dynamically.
+    watch_files: Arc<AtomicBool>,
+    /// Whether the client supports being asked to request inlay hints anew.
+    inlay_hint_refresh: Arc<AtomicBool>,
+    /// Manifests whose inlay hints are refreshed once their pending lookups complete.
+    pending_hints: Arc<std::sync::Mutex<HashSet<Url>>>,
+    /// Latest version of each document received from the client, for dropping
+    /// diagnostics which were calculated for an older version.
+    document_versions: Arc<std::sync::Mutex<HashMap<Url, i32>>>,
+    /// Manifests whose diagnostics are published anew once their pending work, such as
+    /// lookups which outlasted the fetch deadline, completes, along with how much of it
+    /// remains.
+    pending_diagnostics: Arc<std::sync::Mutex<HashMap<Url, usize>>>,
+    /// Whether cargo is configured to be offline, cached per manifest directory, as
+    /// finding out means reading every `.cargo/config.toml` above it.
+    cargo_offline: Arc<std::sync::Mutex<HashMap<Option<PathBuf>, bool>>>,
+    /// Source of unique progress tokens.
+    progress_tokens: Arc<AtomicU32>,
+    /// Process id of the editor passed on the command line, if any.
+    client_process_id: Option<u32>,
 }
 
 impl Backend {
-    async fn calculate_diagnostics(&self, url: Url, content: &str) -> Vec<Diagnostic> {
-        if !self.settings.diagnostics().await {
+    /// Whether either backend may fall back to the other, if enabled by `fallbackBackend`.
+    /// Crates of a custom `--registry` must never be looked up elsewhere, so there is
+    /// only a fallback if `apiBaseUrl` points at that same registry.
+    async fn fallback_enabled(&self) -> bool {
+        if !self.settings.fallback_backend().await {
+            return false;
+        }
+
+        !self.sparse.is_custom()
+            || self.sparse.host().is_some()
+                && self.sparse.host() == crates::host(&self.api.api_base_url())
+    }
+
+    /// The API backend, falling back to the sparse index if enabled by `fallbackBackend`.
+    async fn api_lookup(&self) -> WithFallback<CrateApi, CrateIndex> {
+        let fallback = self.fallback_enabled().await;
+        WithFallback::new(self.api.clone(), fallback.then(|| self.sparse.clone()))
+    }
+
+    /// The sparse index backend, falling back to the API if enabled by `fallbackBackend`.
+    async fn sparse_lookup(&self) -> WithFallback<CrateIndex, CrateApi> {
+        let fallback = self.fallback_enabled().await;
+        WithFallback::new(self.sparse.clone(), fallback.then(|| self.api.clone()))
+    }
+
+    /// The cache of the registry which the configured backend looks crates up in, so
+    /// that crates of a custom `--registry` or `apiBaseUrl` are never mistaken for those
+    /// of crates.io, or the other way round.
+    async fn registry_cache(&self) -> CrateCache {
+        self.cache.for_registry(&self.registry_url().await)
+    }
+
+    /// The API base URL or index URL of whichever backend is configured.
+    async fn registry_url(&self) -> String {
+        if self.settings.use_api().await {
+            self.api.api_base_url()
+        } else {
+            self.sparse.root()
+        }
+    }
+
+    /// Name of the registry which crates are looked up in, for messages: crates.io, or
+    /// the host of whichever other registry is configured.
+    async fn registry_name(&self) -> String {
+        let url = self.registry_url().await;
+        if crates::cache::namespace(&url) == crates::cache::CRATES_IO_NAMESPACE {
+            return crates::cache::CRATES_IO_NAMESPACE.to_string();
+        }
+        crates::host(&url).unwrap_or(url)
+    }
+
+    /// How long to wait for lookups before answering without them, using whichever
+    /// backend is configured.
+    async fn fetch_deadline(&self) -> std::time::Duration {
+        if self.settings.use_api().await {
+            self.api_lookup().await.fetch_deadline()
+        } else {
+            self.sparse_lookup().await.fetch_deadline()
+        }
+    }
+
+    /// Fetch the newest versions of the given crates, using whichever backend is configured.
+    async fn fetch_versions(&self, crate_names: &[&str]) -> HashMap<String, Option<Release>> {
+        self.lookup_versions(crate_names, self.progress()).await
+    }
+
+    /// Stream the newest versions of the given crates as they become known, along with
+    /// how long to wait for them, using whichever backend is configured.
+    async fn stream_versions(&self, crate_names: &[&str]) -> (VersionStream, std::time::Duration) {
+        let progress = self.progress();
+        if self.settings.use_api().await {
+            let api = self.api_lookup().await;
+            let stream = api
+                .fetch_versions_stream(self.registry_cache().await, crate_names, progress)
+                .await;
+            (stream, api.fetch_deadline())
+        } else {
+            let sparse = self.sparse_lookup().await;
+            let stream = sparse
+                .fetch_versions_stream(self.registry_cache().await, crate_names, progress)
+                .await;
+            (stream, sparse.fetch_deadline())
+        }
+    }
+
+    /// Report the progress of a fetch to the client, if it supports it.
+    fn progress(&self) -> Option<Progress> {
+        if !self.work_done_progress.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let token = self.progress_tokens.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(report_progress(
+            self.client.clone(),
+            NumberOrString::String(format!("crates-lsp/fetch/{token}")),
+            rx,
+        ));
+        Some(tx)
+    }
+
+    /// Look up the newest versions of the given crates without reporting progress to
+    /// the client, using whichever backend is configured.
+    async fn lookup_versions(
+        &self,
+        crate_names: &[&str],
+        progress: Option<Progress>,
+    ) -> HashMap<String, Option<Release>> {
+        if self.settings.use_api().await {
+            self.api_lookup()
+                .await
+                .fetch_versions(self.registry_cache().await, crate_names, progress)
+                .await
+        } else {
+            self.sparse_lookup()
+                .await
+                .fetch_versions(self.registry_cache().await, crate_names, progress)
+                .await
+        }
+    }
+
+    /// Search for crates by name, reusing recent searches where possible.
+    async fn search_crates(&self, url: &Url, query: &str) -> Option<Vec<Crate>> {
+        if let Some(crates) = self.searches.get(query).await {
+            return Some(crates);
+        }
+
+        if self.is_offline(url).await {
+            return None;
+        }
+
+        let limit = self.settings.completion_result_limit().await;
+        let crates = self
+            .api
+            .search_crates(&query.to_string(), limit)
+            .await
+            .ok()?;
+        self.searches.put(query, crates.clone(), limit).await;
+        Some(crates)
+    }
+
+    /// List every published version of the given crate, using whichever backend is
+    /// configured. Lists fetched within the last few minutes are reused, as completions
+    /// and hovers ask for them on every request.
+    async fn crate_versions(
+        &self,
+        crate_name: &str,
+    ) -> std::result::Result<Vec<PublishedVersion>, CrateError> {
+        let registry = self.registry_url().await;
+        if let Some(published) = self.versions.get(&registry, crate_name).await {
+            return Ok(published);
+        }
+
+        let published = if self.settings.use_api().await {
+            self.api_lookup().await.versions(crate_name).await?
+        } else {
+            self.sparse_lookup().await.versions(crate_name).await?
+        };
+        self.versions
+            .put(&registry, crate_name, published.clone())
+            .await;
+        Ok(published)
+    }
+
+    /// Whether network lookups should be avoided for the given manifest, either
+    /// because it was explicitly configured, or because cargo is configured to be offline.
+    async fn is_offline(&self, url: &Url) -> bool {
+        if let Some(offline) = self.settings.offline().await {
+            return offline;
+        }
+
+        // Manifests opened through other schemes, such as `untitled:` documents,
+        // can still be subject to cargo's global configuration.
+        let manifest = url.to_file_path().ok();
+        let directory = manifest
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf);
+        if let Some(offline) = self
+            .cargo_offline
+            .lock()
+            .expect("cargo offline lock poisoned")
+            .get(&directory)
+        {
+            return *offline;
+        }
+
+        let offline = tokio::task::spawn_blocking(move || {
+            cargo_config::offline(manifest.as_deref()).unwrap_or_default()
+        })
+        .await
+        .unwrap_or_default();
+        self.cargo_offline
+            .lock()
+            .expect("cargo offline lock poisoned")
+            .insert(directory, offline);
+        offline
+    }
+
+    /// The lockfile of the manifest's workspace, if enabled through `useLockfile` and
+    /// the manifest is a local file with a readable lockfile. Lockfiles can be large,
+    /// so they're read and parsed off the async runtime.
+    async fn lockfile_for(&self, url: &Url) -> Option<Lockfile> {
+        if !self.settings.use_lockfile().await {
+            return None;
+        }
+
+        let manifest = url.to_file_path().ok()?;
+        tokio::task::spawn_blocking(move || Lockfile::parse(&cargo_config::lockfile(&manifest)?))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Start tracking a manifest for which the client never sent its contents, as some
+    /// clients only do for documents opened after the server started, by reading it
+    /// from disk. Documents without a path, such as `untitled:` buffers, can't be read.
+    async fn ensure_tracked(&self, url: &Url) {
+        if self.manifests.source(url).await.is_some() {
+            return;
+        }
+
+        let Ok(path) = url.to_file_path() else {
+            return;
+        };
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(source) => {
+                tracing::debug!(%url, "tracking manifest which was never opened");
+                self.manifests
+                    .update_from_source(url.clone(), &source)
+                    .await;
+            }
+            Err(err) => tracing::debug!(%url, %err, "failed to read untracked manifest"),
+        }
+    }
+
+    /// Resolve the crate given as the first argument of a command, either by name or
+    /// by the position of its dependency in a manifest, along with that manifest.
+    async fn crate_argument(
+        &self,
+        arguments: &[serde_json::Value],
+    ) -> Result<(Option<Url>, String)> {
+        let (url, crate_name) = match arguments.first() {
+            Some(serde_json::Value::String(crate_name)) => (None, Some(crate_name.clone())),
+            Some(argument) => {
+                match serde_json::from_value::<TextDocumentPositionParams>(argument.clone()) {
+                    Ok(position) => {
+                        let url = position.text_document.uri;
+                        let crate_name = self
+                            .manifests
+                            .name_on_line(&url, position.position.line)
+                            .await;
+                        (Some(url), crate_name)
+                    }
+                    Err(_) => (None, None),
+                }
+            }
+            None => (None, None),
+        };
+
+        match crate_name {
+            Some(crate_name) => Ok((url, crate_name)),
+            None => Err(tower_lsp::jsonrpc::Error::invalid_params(
+                "expected crate name or text document position as first argument",
+            )),
+        }
+    }
+
+    /// Resolve requirements on pre-releases against every published version of their
+    /// crate, if enabled through `prereleaseAware` and not offline.
+    ///
+    /// The crates are listed concurrently, and those not listed within the fetch
+    /// deadline are left out, although their lists are still cached once known.
+    async fn prerelease_statuses(
+        &self,
+        url: &Url,
+        dependencies: &[&DependencyWithVersion],
+    ) -> HashMap<String, PrereleaseStatus> {
+        let mut statuses = HashMap::new();
+        if !self.settings.prerelease_aware().await || self.is_offline(url).await {
+            return statuses;
+        }
+
+        let mut lookups = JoinSet::new();
+        for dependency in dependencies {
+            let DependencyVersion::Complete { version, .. } = &dependency.version else {
+                continue;
+            };
+            if version
+                .comparators
+                .iter()
+                .all(|comparator| comparator.pre.is_empty())
+            {
+                continue;
+            }
+
+            let backend = self.clone();
+            let crate_name = dependency.name.clone();
+            let version = version.clone();
+            lookups.spawn(async move {
+                let published = backend.crate_versions(&crate_name).await;
+                (crate_name, version, published)
+            });
+        }
+
+        if lookups.is_empty() {
+            return statuses;
+        }
+
+        let deadline = self.fetch_deadline().await;
+        let collect = async {
+            while let Some(lookup) = lookups.join_next().await {
+                let Ok((crate_name, version, published)) = lookup else {
+                    continue;
+                };
+                match published {
+                    Ok(published) => {
+                        if let Some(status) = requirement::prerelease_status(&version, &published) {
+                            statuses.insert(crate_name, status);
+                        }
+                    }
+                    Err(err) => tracing::warn!(crate_name, ?err, "failed to list versions"),
+                }
+            }
+        };
+        if tokio::time::timeout(deadline, collect).await.is_err() {
+            tracing::debug!(%url, "pre-release lookups outlasted the fetch deadline");
+            lookups.detach_all();
+        }
+
+        statuses
+    }
+
+    /// Get the newest versions of the given crates for the given manifest, only
+    /// relying on previously cached versions if offline.
+    async fn versions_for(
+        &self,
+        url: &Url,
+        crate_names: &[&str],
+    ) -> HashMap<String, Option<Release>> {
+        if self.is_offline(url).await {
+            self.registry_cache()
+                .await
+                .cached_versions(crate_names)
+                .await
+        } else {
+            self.fetch_versions(crate_names).await
+        }
+    }
+
+    /// Warm the cache with the newest versions of every dependency of the manifest in
+    /// the background, so that completions and inlay hints don't have to wait for the
+    /// registry. Lookups already in progress, such as those made for diagnostics, are
+    /// shared rather than repeated.
+    ///
+    /// Nothing is looked up unless diagnostics or inlay hints are enabled for the
+    /// manifest, and crates it ignores are skipped.
+    fn prefetch(&self, url: &Url, source: &str) {
+        let manifest_settings = ManifestSettings::from_manifest(source);
+        let crate_names: Vec<String> = parse::parse_manifest(source)
+            .into_iter()
+            .filter_map(|dependency| match dependency {
+                Dependency::WithVersion(DependencyWithVersion { name, .. })
+                | Dependency::WorkspaceInherited { name, .. } => Some(name),
+                Dependency::Partial { .. }
+                | Dependency::Git { .. }
+                | Dependency::GitSource { .. }
+                | Dependency::Other { .. } => None,
+            })
+            .filter(|name| parse::is_valid_crate_name(name))
+            .filter(|name| !manifest_settings.is_ignored(name))
+            .collect();
+
+        let backend = self.clone();
+        let url = url.clone();
+        tokio::spawn(async move {
+            let diagnostics_enabled = match manifest_settings.diagnostics {
+                Some(diagnostics) => diagnostics,
+                None => backend.settings.diagnostics_for(&url).await,
+            };
+            let inlay_hints_enabled = match manifest_settings.inlay_hints {
+                Some(inlay_hints) => inlay_hints,
+                None => backend.settings.inlay_hints_for(&url).await,
+            };
+            if !diagnostics_enabled && !inlay_hints_enabled {
+                return;
+            }
+
+            if backend.is_offline(&url).await {
+                return;
+            }
+
+            let crate_names: Vec<&str> = crate_names.iter().map(String::as_str).collect();
+            tracing::debug!(%url, crates = crate_names.len(), "prefetching versions");
+            backend.lookup_versions(&crate_names, None).await;
+        });
+    }
+
+    /// Look up the given crates in the background, asking the client to request the
+    /// inlay hints anew once they are known. Manifests with lookups already pending
+    /// are skipped, since their hints are about to be refreshed anyway.
+    fn refresh_hints_after_lookup(&self, url: &Url, crate_names: Vec<String>) {
+        let newly_pending = self
+            .pending_hints
+            .lock()
+            .expect("pending hints lock poisoned")
+            .insert(url.clone());
+        if !newly_pending {
+            return;
+        }
+
+        let backend = self.clone();
+        let url = url.clone();
+        tokio::spawn(async move {
+            let crate_names: Vec<&str> = crate_names.iter().map(String::as_str).collect();
+            tracing::debug!(%url, crates = crate_names.len(), "looking up versions for hints");
+            backend.lookup_versions(&crate_names, None).await;
+
+            backend
+                .pending_hints
+                .lock()
+                .expect("pending hints lock poisoned")
+                .remove(&url);
+            if let Err(err) = backend.client.inlay_hint_refresh().await {
+                tracing::warn!(?err, "failed to refresh inlay hints");
+            }
+        });
+    }
+
+    /// Let the remaining lookups of the stream complete in the background, then publish
+    /// the diagnostics of the manifest anew, see [`Backend::republish_after`].
+    fn republish_after_lookups(&self, url: Url, mut stream: VersionStream) {
+        self.republish_after(url, async move { while stream.recv().await.is_some() {} });
+    }
+
+    /// Complete the given work in the background, then publish the diagnostics of the
+    /// manifest anew, so that results which took too long to become known don't stay
+    /// unreported until the manifest is edited again.
+    ///
+    /// Diagnostics are only published anew once all work pending for the manifest has
+    /// completed, covering its latest content, rather than once for every change. They
+    /// are dropped if the manifest changes while they are being calculated, since that
+    /// change publishes diagnostics of its own.
+    fn republish_after(&self, url: Url, work: impl Future<Output = ()> + Send + 'static) {
+        *self
+            .pending_diagnostics
+            .lock()
+            .expect("pending diagnostics lock poisoned")
+            .entry(url.clone())
+            .or_default() += 1;
+
+        let backend = self.clone();
+        tokio::spawn(async move {
+            work.await;
+            {
+                let mut pending = backend
+                    .pending_diagnostics
+                    .lock()
+                    .expect("pending diagnostics lock poisoned");
+                let Some(remaining) = pending.get_mut(&url) else {
+                    return;
+                };
+                *remaining -= 1;
+                if *remaining > 0 {
+                    return;
+                }
+                pending.remove(&url);
+            }
+
+            let version = backend.document_version(&url);
+            let Some(source) = backend.manifests.source(&url).await else {
+                return;
+            };
+            tracing::debug!(%url, "lookups complete, publishing diagnostics anew");
+            let diagnostics = backend
+                .calculate_diagnostics(url.clone(), &source, None)
+                .await;
+
+            let unchanged = backend.document_version(&url) == version
+                && backend.manifests.source(&url).await.as_ref() == Some(&source);
+            if !unchanged {
+                tracing::debug!(%url, "manifest changed, dropping republished diagnostics");
+                return;
+            }
+
+            backend
+                .client
+                .publish_diagnostics(url, diagnostics, version)
+                .await;
+        });
+    }
+
+    /// Record the version of a document received from the client.
+    fn set_document_version(&self, url: &Url, version: i32) {
+        self.document_versions
+            .lock()
+            .expect("document versions lock poisoned")
+            .insert(url.clone(), version);
+    }
+
+    /// The latest version of the document received from the client, if any.
+    fn document_version(&self, url: &Url) -> Option<i32> {
+        self.document_versions
+            .lock()
+            .expect("document versions lock poisoned")
+            .get(url)
+            .copied()
+    }
+
+    /// Whether the given version is the latest version of the document received, i.e.
+    /// the document hasn't changed since.
+    fn is_latest_version(&self, url: &Url, version: i32) -> bool {
+        self.document_versions
+            .lock()
+            .expect("document versions lock poisoned")
+            .get(url)
+            .is_none_or(|latest| *latest == version)
+    }
+
+    /// Forget the version and pending work recorded for a document which was closed.
+    fn forget_document(&self, url: &Url) {
+        self.document_versions
+            .lock()
+            .expect("document versions lock poisoned")
+            .remove(url);
+        self.pending_hints
+            .lock()
+            .expect("pending hints lock poisoned")
+            .remove(url);
+        self.pending_diagnostics
+            .lock()
+            .expect("pending diagnostics lock poisoned")
+            .remove(url);
+    }
+
+    /// Evict a crate from the cache and fetch it anew, updating the diagnostics
+    /// of all open manifests which depend on it.
+    async fn refresh_crate(&self, crate_name: &str) {
+        self.registry_cache().await.remove(crate_name).await;
+        self.versions
+            .remove(&self.registry_url().await, crate_name)
+            .await;
+
+        let message = match self.fetch_versions(&[crate_name]).await.get(crate_name) {
+            Some(Some(release)) => format!("{crate_name}: {}", release.version),
+            _ => format!("{crate_name}: Unknown crate"),
+        };
+        self.client.log_message(MessageType::INFO, message).await;
+
+        for (url, source) in self.manifests.dependents(crate_name).await {
+            let diagnostics = self.calculate_diagnostics(url.clone(), &source, None).await;
+            self.client
+                .publish_diagnostics(url, diagnostics, None)
+                .await;
+        }
+    }
+
+    /// Severity of outdated dependencies, which is lowered to a hint for unpublished
+    /// manifests if configured to do so.
+    async fn needs_update_severity(&self, url: &Url) -> DiagnosticSeverity {
+        if let Some(severity) = self.manifests.settings(url).await.needs_update_severity {
+            return severity;
+        }
+
+        if self.settings.relax_unpublished().await && self.manifests.is_unpublished(url).await {
+            DiagnosticSeverity::HINT
+        } else {
+            self.settings.needs_update_severity().await
+        }
+    }
+
+    /// Point out git dependencies for which a newer semver tag is available.
+    ///
+    /// Only tags which were looked up recently are used. The remaining repositories are
+    /// looked up in the background, publishing the diagnostics anew once they are known,
+    /// so that slow remotes never hold up the diagnostics of the manifest.
+    async fn git_tag_diagnostics(&self, url: &Url, packages: &[Dependency]) -> Vec<Diagnostic> {
+        if !self.settings.check_git_tags().await {
             return Vec::new();
         }
 
-        let packages = self.manifests.update_from_source(url, content).await;
+        let nu_sev = self.needs_update_severity(url).await;
+
+        let mut diagnostics = Vec::new();
+        let mut uncached = Vec::new();
+        for dependency in packages {
+            let Dependency::Git {
+                name,
+                url: repository,
+                tag,
+                range,
+            } = dependency
+            else {
+                continue;
+            };
+
+            let Some(current) = crates::git::parse_tag(tag) else {
+                continue;
+            };
+
+            let Some(latest) = self.git_tags.cached(repository).await else {
+                uncached.push(repository.clone());
+                continue;
+            };
+            let Some((latest_tag, latest)) = latest else {
+                continue;
+            };
+
+            if latest > current {
+                diagnostics.push(Diagnostic::new(
+                    *range,
+                    Some(nu_sev),
+                    Some(NumberOrString::Number(diagnostic_codes::NEWER_GIT_TAG)),
+                    None,
+                    format!("{name}: {latest_tag}"),
+                    None,
+                    None,
+                ));
+            }
+        }
+
+        if !uncached.is_empty() {
+            let git_tags = self.git_tags.clone();
+            self.republish_after(url.clone(), async move { git_tags.look_up(uncached).await });
+        }
+
+        diagnostics
+    }
+
+    /// Calculate the diagnostics of the manifest. If the version of the document is
+    /// given, diagnostics are also published for it while versions are being looked up,
+    /// so that large manifests don't have to wait for the slowest lookup.
+    async fn calculate_diagnostics(
+        &self,
+        url: Url,
+        content: &str,
+        interim_version: Option<i32>,
+    ) -> Vec<Diagnostic> {
+        // Track the manifest regardless of whether diagnostics are enabled, since
+        // completions, hovers and inlay hints rely on it.
+        let packages = self
+            .manifests
+            .update_from_source(url.clone(), content)
+            .await;
+
+        // Avoid fetching any versions when diagnostics are disabled, leaving it to
+        // the features which do need them, so that using the server only for
+        // completions doesn't look up every dependency of every opened manifest.
+        let manifest_settings = self.manifests.settings(&url).await;
+        let diagnostics_enabled = match manifest_settings.diagnostics {
+            Some(diagnostics) => diagnostics,
+            None => self.settings.diagnostics_for(&url).await,
+        };
+        if !diagnostics_enabled {
+            return Vec::new();
+        }
 
         // Retrieve just the package names, so we can fetch the latest
         // versions via the crate registry.
@@ -43,103 +777,260 @@ impl Backend {
             .iter()
             .filter_map(|dependency| match dependency {
                 Dependency::Partial { .. } => None,
+                Dependency::WithVersion(dep) if manifest_settings.is_ignored(&dep.name) => None,
                 Dependency::WithVersion(dep) => Some(dep),
-                Dependency::Other { .. } => None,
+                Dependency::Git { .. }
+                | Dependency::GitSource { .. }
+                | Dependency::WorkspaceInherited { .. }
+                | Dependency::Other { .. } => None,
             })
             .collect();
 
-        if dependency_with_versions.is_empty() {
-            return Vec::new();
+        // Names which cargo would reject can't be looked up in the registry,
+        // so report them as invalid instead.
+        let (dependency_with_versions, invalid_names): (Vec<_>, Vec<_>) = dependency_with_versions
+            .into_iter()
+            .partition(|dependency| parse::is_valid_crate_name(&dependency.name));
+
+        let invalid_diagnostics: Vec<_> = invalid_names
+            .into_iter()
+            .map(|dependency| {
+                // Names given in a table header, e.g. `[dependencies.serde]`, have no range
+                // of their own, so point at the start of the version line instead.
+                let range = dependency.name_range.unwrap_or_else(|| {
+                    let line = dependency.version.range().start.line;
+                    Range::new(
+                        Position::new(line, 0),
+                        Position::new(line, dependency.name.len() as u32),
+                    )
+                });
+                Diagnostic::new(
+                    range,
+                    Some(DiagnosticSeverity::ERROR),
+                    Some(NumberOrString::Number(diagnostic_codes::INVALID_NAME)),
+                    None,
+                    format!("{}: Invalid dependency name", &dependency.name),
+                    None,
+                    None,
+                )
+            })
+            .collect();
+
+        let mut other_diagnostics = self.git_tag_diagnostics(&url, &packages).await;
+        other_diagnostics.extend(invalid_diagnostics);
+
+        let inherited = inherited_dependencies(&url, &packages).await;
+        let inherited: Vec<_> = inherited
+            .iter()
+            .filter(|dependency| !manifest_settings.is_ignored(&dependency.name))
+            .collect();
+
+        let patches = if self.settings.flag_redundant_patches().await {
+            parse::patched_dependencies(content)
+        } else {
+            Vec::new()
+        };
+
+        if dependency_with_versions.is_empty() && inherited.is_empty() && patches.is_empty() {
+            diagnostics::sort(
+                &mut other_diagnostics,
+                self.settings.diagnostic_order().await,
+            );
+            return other_diagnostics;
         }
 
         let crate_names: Vec<&str> = dependency_with_versions
             .iter()
+            .chain(&inherited)
             .map(|x| x.name.as_str())
+            .chain(patches.iter().map(|patch| {
+                parse::dependency_value(content, patch, "package").unwrap_or(&patch.name)
+            }))
             .collect();
-        // Get the newest version of each crate that appears in the manifest.
-        let newest_packages = if self.settings.use_api().await {
-            self.api
-                .fetch_versions(self.cache.clone(), &crate_names)
-                .await
-        } else {
-            self.sparse
-                .fetch_versions(self.cache.clone(), &crate_names)
-                .await
+
+        // Requirements on pre-releases are reported separately, if resolved against
+        // the published pre-releases.
+        let prereleases = self
+            .prerelease_statuses(&url, &dependency_with_versions)
+            .await;
+        let (prerelease_dependencies, release_dependencies): (Vec<_>, Vec<_>) =
+            dependency_with_versions
+                .iter()
+                .copied()
+                .partition(|dependency| prereleases.contains_key(&dependency.name));
+
+        let lockfile = self.lockfile_for(&url).await;
+        if let Some(lockfile) = &lockfile {
+            other_diagnostics.extend(diagnostics::lockfile_diagnostics(
+                &dependency_with_versions,
+                lockfile,
+            ));
+        }
+
+        let encoding = self.manifests.encoding().await;
+        other_diagnostics.extend(diagnostics::style_diagnostics(
+            content,
+            &dependency_with_versions,
+            self.settings.requirement_style_policy().await,
+            lockfile.as_ref(),
+            encoding,
+        ));
+
+        if self.settings.warn_empty_features().await {
+            other_diagnostics.extend(diagnostics::empty_features_diagnostics(
+                &dependency_with_versions,
+            ));
+        }
+
+        if self.settings.should_use_workspace_dep().await {
+            if let Some(root) = workspace_root(&url).await {
+                // The root's own `[workspace.dependencies]` are the ones being inherited.
+                let inheritable_lines: Vec<u32> = parse::workspace_dependencies(content)
+                    .iter()
+                    .map(|dependency| dependency.version.range().start.line)
+                    .collect();
+                let declared: Vec<_> = dependency_with_versions
+                    .iter()
+                    .copied()
+                    .filter(|dependency| {
+                        !inheritable_lines.contains(&dependency.version.range().start.line)
+                    })
+                    .collect();
+
+                other_diagnostics.extend(diagnostics::workspace_diagnostics(
+                    content,
+                    &declared,
+                    &parse::workspace_dependencies(&root),
+                    encoding,
+                ));
+            }
+        }
+
+        // Point out crates which are known to conflict. This is purely based on the
+        // names of the dependencies, and so doesn't require any lookups.
+        let conflicting_crates = self.settings.conflicting_crates().await;
+        if !conflicting_crates.is_empty() {
+            for (dependency, other) in
+                conflicts::find_conflicts(&dependency_with_versions, &conflicting_crates)
+            {
+                other_diagnostics.push(Diagnostic::new(
+                    dependency.version.range(),
+                    Some(DiagnosticSeverity::INFORMATION),
+                    Some(NumberOrString::Number(diagnostic_codes::CONFLICTING_CRATE)),
+                    None,
+                    format!("{} conflicts with {other}", &dependency.name),
+                    None,
+                    None,
+                ));
+            }
+        }
+
+        let manifest = ManifestDiagnostics {
+            content,
+            release_dependencies,
+            prerelease_dependencies,
+            inherited,
+            prereleases,
+            severities: diagnostics::Severities {
+                needs_update: self.needs_update_severity(&url).await,
+                up_to_date: self.settings.up_to_date_severity().await,
+                unknown_dep: self.settings.unknown_dep_severity().await,
+            },
+            lockfile,
+            encoding,
+            stale_crates: match self.settings.stale_crate_warning().await {
+                Some(threshold) => Some((threshold, self.settings.stale_crate_severity().await)),
+                None => None,
+            },
+            order: self.settings.diagnostic_order().await,
+            warn_unbounded: self.settings.warn_unbounded_req().await,
+            minimum_majors: self.settings.minimum_major_versions().await,
+            summary: self.settings.summary_diagnostic().await,
+            patches: &patches,
+            registry: self.registry_name().await,
+            other: other_diagnostics,
         };
 
-        // Produce diagnostic hints for each crate where we might be helpful.
-        let nu_sev = self.settings.needs_update_severity().await;
-        let utd_sev = self.settings.up_to_date_severity().await;
-        let ud_sev = self.settings.unknown_dep_severity().await;
-        let diagnostics: Vec<_> = dependency_with_versions
-            .into_iter()
-            .map(|dependency| {
-                if let Some(Some(newest_version)) = newest_packages.get(&dependency.name) {
-                    match &dependency.version {
-                        DependencyVersion::Complete { range, version } => {
-                            if !version.matches(newest_version) {
-                                Diagnostic {
-                                    range: *range,
-                                    severity: Some(nu_sev),
-                                    code: Some(NumberOrString::Number(
-                                        diagnostic_codes::NEEDS_UPDATE,
-                                    )),
-                                    code_description: None,
-                                    source: None,
-                                    message: format!("{}: {newest_version}", &dependency.name),
-                                    related_information: None,
-                                    tags: None,
-                                    data: Some(serde_json::json!({
-                                        "newest_version": newest_version,
-                                    })),
-                                }
-                            } else {
-                                let range = Range {
-                                    start: Position::new(range.start.line, 0),
-                                    end: Position::new(range.start.line, 0),
-                                };
-                                Diagnostic::new(
-                                    range,
-                                    Some(utd_sev),
-                                    Some(NumberOrString::Number(diagnostic_codes::UP_TO_DATE)),
-                                    None,
-                                    "✓".to_string(),
-                                    None,
-                                    None,
-                                )
+        // Get the newest version of each crate that appears in the manifest, publishing
+        // diagnostics for those already known while waiting for the rest.
+        let newest_packages = match interim_version {
+            Some(version) if !self.is_offline(&url).await => {
+                let (mut stream, deadline) = self.stream_versions(&crate_names).await;
+                let deadline = tokio::time::sleep(deadline);
+                tokio::pin!(deadline);
+
+                let mut interim = tokio::time::interval(INTERIM_DIAGNOSTICS_INTERVAL);
+                // The first tick completes immediately.
+                interim.tick().await;
+
+                let mut newest_packages = HashMap::new();
+                let mut changed = false;
+                loop {
+                    tokio::select! {
+                        next = stream.recv() => {
+                            let Some((name, release)) = next else {
+                                break;
+                            };
+                            newest_packages.insert(name, release);
+                            changed = true;
+                        }
+                        _ = interim.tick(), if changed => {
+                            changed = false;
+                            // Diagnostics of a superseded version would replace those
+                            // already published for the newer one.
+                            if !self.is_latest_version(&url, version) {
+                                continue;
                             }
+                            self.client
+                                .publish_diagnostics(
+                                    url.clone(),
+                                    manifest.render(&newest_packages),
+                                    Some(version),
+                                )
+                                .await;
+                        }
+                        () = &mut deadline => {
+                            tracing::warn!(
+                                "deadline exceeded while fetching crates, returning partial results"
+                            );
+                            self.republish_after_lookups(url.clone(), stream);
+                            break;
                         }
-                        DependencyVersion::Partial { range, .. } => Diagnostic {
-                            range: *range,
-                            severity: Some(nu_sev),
-                            code: Some(NumberOrString::Number(diagnostic_codes::NEEDS_UPDATE)),
-                            code_description: None,
-                            source: None,
-                            message: format!("{}: {newest_version}", &dependency.name),
-                            related_information: None,
-                            tags: None,
-                            data: Some(serde_json::json!({
-                                "newest_version": newest_version,
-                            })),
-                        },
-                    }
-                } else {
-                    Diagnostic {
-                        range: dependency.version.range(),
-                        severity: Some(ud_sev),
-                        code: Some(NumberOrString::Number(diagnostic_codes::UNKNOWN_DEP)),
-                        code_description: None,
-                        source: None,
-                        message: format!("{}: Unknown crate", &dependency.name),
-                        related_information: None,
-                        tags: None,
-                        data: None,
                     }
                 }
-            })
+                newest_packages
+            }
+            _ => self.versions_for(&url, &crate_names).await,
+        };
+
+        manifest.render(&newest_packages)
+    }
+
+    async fn settings_schema(&self) -> Result<serde_json::Value> {
+        Ok(settings::schema())
+    }
+
+    /// Summarize the status of the dependencies of every tracked manifest, for display
+    /// in a status bar. Only cached versions are used, so this never waits for lookups.
+    async fn status(&self) -> Result<Status> {
+        let dependencies = self.manifests.all_dependencies().await;
+        let mut crate_names: Vec<&str> = dependencies
+            .iter()
+            .map(|dependency| dependency.name.as_str())
             .collect();
+        crate_names.sort_unstable();
+        crate_names.dedup();
 
-        diagnostics
+        let newest_packages = self
+            .registry_cache()
+            .await
+            .cached_versions(&crate_names)
+            .await;
+
+        Ok(Status {
+            counts: summary::count(&dependencies, &newest_packages),
+            fetching: self.cache.lookups_in_flight() > 0,
+        })
     }
 }
 
@@ -150,9 +1041,56 @@ impl LanguageServer for Backend {
             self.settings.populate_from(settings).await;
         }
 
+        // Exit once the editor does, rather than lingering if it crashed without
+        // shutting the server down.
+        if let Some(pid) = params.process_id.or(self.client_process_id) {
+            let cache = self.cache.clone();
+            tokio::spawn(async move {
+                process::wait_for_exit(pid).await;
+                tracing::info!(pid, "client process exited, shutting down");
+
+                if let Err(err) = cache.flush().await {
+                    tracing::warn!(%err, "failed to flush crate cache");
+                }
+                std::process::exit(1);
+            });
+        }
+
+        let encoding = PositionEncoding::negotiate(&params.capabilities);
+        self.manifests.set_encoding(encoding).await;
+
+        let window = params.capabilities.window.unwrap_or_default();
+        self.work_done_progress.store(
+            window.work_done_progress.unwrap_or_default(),
+            Ordering::Relaxed,
+        );
+        self.show_document.store(
+            window
+                .show_document
+                .is_some_and(|show_document| show_document.support),
+            Ordering::Relaxed,
+        );
+
+        let workspace = params.capabilities.workspace.unwrap_or_default();
+        self.watch_files.store(
+            workspace
+                .did_change_watched_files
+                .and_then(|watched_files| watched_files.dynamic_registration)
+                .unwrap_or_default(),
+            Ordering::Relaxed,
+        );
+        self.inlay_hint_refresh.store(
+            workspace
+                .inlay_hint
+                .and_then(|inlay_hint| inlay_hint.refresh_support)
+                .unwrap_or_default(),
+            Ordering::Relaxed,
+        );
+
         Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
+                position_encoding: Some(encoding.kind()),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
                     TextDocumentSyncKind::FULL,
                 )),
@@ -168,9 +1106,18 @@ impl LanguageServer for Backend {
                     ..Default::default()
                 }),
                 inlay_hint_provider: Some(OneOf::Left(true)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
                 code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
                 execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: vec!["dummy.do_something".to_string()],
+                    commands: vec![
+                        commands::REFRESH_CRATE.to_string(),
+                        commands::SHOW_CONFIG.to_string(),
+                        commands::SET_VERSION.to_string(),
+                        commands::CACHE_STATS.to_string(),
+                        commands::PREVIEW_UPDATES.to_string(),
+                        commands::OPEN_REPOSITORY.to_string(),
+                        commands::LATEST_VERSION.to_string(),
+                    ],
                     work_done_progress_options: Default::default(),
                 }),
                 workspace: Some(WorkspaceServerCapabilities {
@@ -187,20 +1134,132 @@ impl LanguageServer for Backend {
     }
 
     async fn initialized(&self, _: InitializedParams) {
+        self.cache
+            .set_capacity(self.settings.max_cache_entries().await)
+            .await;
+
+        self.api.set_base_url(&self.settings.api_base_url().await);
+
+        // The registry passed on the command line and the configured `apiBaseUrl` are
+        // trusted like the rest of the settings are, so their users don't have to list
+        // them in `allowedHosts` as well.
+        let mut allowed_hosts = self.settings.allowed_hosts().await;
+        if self.sparse.is_custom() {
+            allowed_hosts.extend(self.sparse.host());
+        }
+        allowed_hosts.extend(crates::host(&self.api.api_base_url()));
+        self.http.set_allowed_hosts(allowed_hosts);
+
+        let options = self.settings.client_options().await;
+
+        if options.accept_invalid_certs {
+            tracing::warn!("TLS certificate verification is disabled");
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    "crates-lsp: TLS certificate verification is disabled by the dangerouslyAcceptInvalidCerts setting!",
+                )
+                .await;
+        }
+
+        if options.accept_invalid_certs || options.extra_ca_cert.is_some() {
+            if let Err(err) = self.http.configure(&options) {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("crates-lsp: failed to configure http client: {err}"),
+                    )
+                    .await
+            }
+        }
+
+        // Reload `crates-lsp.toml` files and cargo configuration as they're edited,
+        // rather than only picking them up once the editor is restarted.
+        if self.watch_files.load(Ordering::Relaxed) {
+            let mut watchers = vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String(format!(
+                    "**/{}",
+                    settings::DIRECTORY_SETTINGS_FILE
+                )),
+                kind: None,
+            }];
+            watchers.extend(cargo_config::CONFIG_FILES.map(|file| FileSystemWatcher {
+                glob_pattern: GlobPattern::String(format!("**/.cargo/{file}")),
+                kind: None,
+            }));
+            let options = DidChangeWatchedFilesRegistrationOptions { watchers };
+            let registration = Registration {
+                id: "crates-lsp/watchSettings".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: serde_json::to_value(options).ok(),
+            };
+            if let Err(err) = self.client.register_capability(vec![registration]).await {
+                tracing::warn!(%err, "failed to watch configuration files");
+            }
+        }
+
         self.client
             .log_message(MessageType::INFO, "crates-lsp initialized.")
             .await;
     }
 
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let mut changed = false;
+        for change in params.changes {
+            let Ok(path) = change.uri.to_file_path() else {
+                continue;
+            };
+            if path.file_name() == Some(settings::DIRECTORY_SETTINGS_FILE.as_ref()) {
+                tracing::info!(?path, "reloading directory settings");
+                self.settings.forget_directory_settings(&path).await;
+                changed = true;
+            } else if cargo_config::is_config_file(&path) {
+                // A configuration file applies to every directory below it, so
+                // rather than working out which are affected, all are forgotten.
+                tracing::info!(?path, "reloading cargo configuration");
+                self.cargo_offline
+                    .lock()
+                    .expect("cargo offline lock poisoned")
+                    .clear();
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return;
+        }
+
+        for (url, source) in self.manifests.sources().await {
+            let diagnostics = self.calculate_diagnostics(url.clone(), &source, None).await;
+            self.client
+                .publish_diagnostics(url, diagnostics, None)
+                .await;
+        }
+    }
+
     async fn shutdown(&self) -> Result<()> {
+        if let Err(err) = self.cache.flush().await {
+            tracing::warn!(%err, "failed to flush crate cache");
+        }
+
         Ok(())
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         if let Some(content) = params.content_changes.first() {
+            let url = &params.text_document.uri;
+            self.set_document_version(url, params.text_document.version);
+
             let diagnostics = self
-                .calculate_diagnostics(params.text_document.uri.clone(), &content.text)
+                .calculate_diagnostics(
+                    params.text_document.uri.clone(),
+                    &content.text,
+                    Some(params.text_document.version),
+                )
                 .await;
+            if !self.is_latest_version(url, params.text_document.version) {
+                return;
+            }
 
             self.client
                 .publish_diagnostics(
@@ -213,9 +1272,20 @@ impl LanguageServer for Backend {
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let url = &params.text_document.uri;
+        self.set_document_version(url, params.text_document.version);
+        self.prefetch(url, &params.text_document.text);
+
         let diagnostics = self
-            .calculate_diagnostics(params.text_document.uri.clone(), &params.text_document.text)
+            .calculate_diagnostics(
+                params.text_document.uri.clone(),
+                &params.text_document.text,
+                Some(params.text_document.version),
+            )
             .await;
+        if !self.is_latest_version(url, params.text_document.version) {
+            return;
+        }
 
         self.client
             .publish_diagnostics(
@@ -226,192 +1296,634 @@ impl LanguageServer for Backend {
             .await;
     }
 
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        // Forget what was kept about the document while it was open, so that it doesn't
+        // accumulate over the session. Pending lookups still complete in the background.
+        self.forget_document(&params.text_document.uri);
+    }
+
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let url = &params.text_document_position.text_document.uri;
         let cursor = params.text_document_position.position;
 
-        let Some(dependencies) = self
+        self.ensure_tracked(url).await;
+
+        if let Some(sections) = self.settings.completion_sections().await {
+            let source = self.manifests.source(url).await.unwrap_or_default();
+            let enabled = parse::dependency_kind(&source, cursor.line)
+                .is_some_and(|kind| sections.iter().any(|section| section == kind));
+            if !enabled {
+                return Ok(None);
+            }
+        }
+
+        let trigger = params
+            .context
+            .as_ref()
+            .and_then(|context| context.trigger_character.as_deref());
+        let Some(context) = self
             .manifests
-            .get(&params.text_document_position.text_document.uri)
+            .completion_context(url, cursor, trigger)
             .await
         else {
             return Ok(None);
         };
 
-        let Some(dependency) = dependencies
-            .into_iter()
-            .find(|dependency| match dependency {
-                Dependency::Partial { line, .. } => *line == cursor.line,
-                Dependency::WithVersion(dep) => {
-                    dep.version.range().start.line == cursor.line
-                        && dep.version.range().start.character <= cursor.character
-                        && dep.version.range().end.character >= cursor.character
+        match context {
+            // When editing the name of a dependency which already has a version, offer
+            // other crates to replace just the name with.
+            CompletionContext::Name(name, range) => {
+                // Positions are in the negotiated encoding, while the name is sliced by
+                // byte offset.
+                let source = self.manifests.source(url).await.unwrap_or_default();
+                let encoding = self.manifests.encoding().await;
+                let typed = encoding.decode(&source, Range::new(range.start, cursor));
+                let typed = typed
+                    .end
+                    .character
+                    .checked_sub(typed.start.character)
+                    .and_then(|length| name.get(..length as usize));
+                let Some(typed) = typed.filter(|typed| !typed.is_empty()) else {
+                    return Ok(None);
+                };
+
+                let Some(crates) = self.search_crates(url, typed).await else {
+                    return Ok(None);
+                };
+
+                Ok(Some(name_completions(crates, range)))
+            }
+            // When editing a feature of a dependency, offer the features of the newest
+            // release matching its requirement.
+            CompletionContext::Feature(dependency, range) => {
+                if self.is_offline(url).await {
+                    return Ok(None);
                 }
-                Dependency::Other { .. } => false,
-            })
-        else {
-            return Ok(None);
-        };
 
-        match dependency {
-            Dependency::Partial { name, .. } => {
-                let Ok(crates) = self.sparse.search_crates(&name).await else {
+                let published = match self.crate_versions(&dependency.name).await {
+                    Ok(published) => published,
+                    Err(err) => {
+                        tracing::warn!(
+                            crate_name = dependency.name,
+                            ?err,
+                            "failed to list versions"
+                        );
+                        return Ok(None);
+                    }
+                };
+
+                Ok(feature_completions(&dependency, &published, range))
+            }
+            CompletionContext::Partial(name) => {
+                let Some(crates) = self.search_crates(url, &name).await else {
                     return Ok(None);
                 };
+
                 let range = Range::new(Position::new(cursor.line, 0), cursor);
-                Ok(Some(CompletionResponse::Array(
-                    crates
-                        .into_iter()
-                        .map(|x| CompletionItem {
-                            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
-                                range,
-                                x.name.clone(),
-                            ))),
-                            label: x.name,
-                            ..CompletionItem::default()
-                        })
-                        .collect(),
-                )))
+                Ok(Some(name_completions(crates, range)))
             }
-            Dependency::WithVersion(dependency) => {
+            CompletionContext::Version(dependency) => {
                 let packages = self
-                    .sparse
-                    .fetch_versions(self.cache.clone(), &[&dependency.name])
+                    .versions_for(
+                        &params.text_document_position.text_document.uri,
+                        &[&dependency.name],
+                    )
                     .await;
 
-                if let Some(Some(newest_version)) = packages.get(&dependency.name) {
-                    let specified_version = dependency.version.to_string();
+                if let Some(Some(Release {
+                    version: newest_version,
+                    ..
+                })) = packages.get(&dependency.name)
+                {
+                    let Some(source) = self
+                        .manifests
+                        .source(&params.text_document_position.text_document.uri)
+                        .await
+                    else {
+                        return Ok(None);
+                    };
+
+                    // Versions to offer, along with whether they have been yanked.
+                    let mut versions = vec![(newest_version.clone(), false)];
+
+                    let include_prereleases = self.settings.completion_include_prereleases().await;
+                    let include_yanked = self.settings.completion_include_yanked().await;
+                    if include_prereleases || include_yanked {
+                        match self.crate_versions(&dependency.name).await {
+                            Ok(published) => {
+                                if include_prereleases {
+                                    versions.extend(
+                                        crates::newest_prerelease(&published, newest_version)
+                                            .map(|version| (version.clone(), false)),
+                                    );
+                                }
+                                if include_yanked {
+                                    versions.extend(
+                                        crates::newer_yanked(
+                                            &published,
+                                            newest_version,
+                                            include_prereleases,
+                                        )
+                                        .into_iter()
+                                        .map(|version| (version.clone(), true)),
+                                    );
+                                }
+                            }
+                            Err(err) => tracing::warn!(
+                                crate_name = dependency.name,
+                                ?err,
+                                "failed to list versions"
+                            ),
+                        }
+                    }
+
+                    // Edits are calculated using byte offsets, so convert to and from
+                    // the negotiated encoding.
+                    let encoding = self.manifests.encoding().await;
+                    let range = encoding.decode(&source, dependency.version.range());
+                    let cursor = params.text_document_position.position;
+                    let cursor = encoding.decode(&source, Range::new(cursor, cursor)).start;
+
+                    let items: Vec<_> = versions
+                        .into_iter()
+                        .filter_map(|(version, yanked)| {
+                            let mut edit = parse::version_edit(&source, range, cursor, &version)?;
+                            // Requirements typed without quotes get them added as well.
+                            let quotes =
+                                parse::quote_edits(&source, range, &mut edit).map(|quotes| {
+                                    quotes
+                                        .into_iter()
+                                        .map(|quote| TextEdit {
+                                            range: encoding.encode(&source, quote.range),
+                                            ..quote
+                                        })
+                                        .collect()
+                                });
+                            edit.range = encoding.encode(&source, edit.range);
+
+                            // Clients render deprecated items struck through.
+                            let (detail, tags) = if yanked {
+                                (
+                                    Some("yanked".to_string()),
+                                    Some(vec![CompletionItemTag::DEPRECATED]),
+                                )
+                            } else {
+                                (None, None)
+                            };
+
+                            Some(CompletionItem {
+                                label: version.to_string(),
+                                detail,
+                                tags,
+                                filter_text: Some(
+                                    edit.new_text.trim().trim_end_matches('"').to_string(),
+                                ),
+                                text_edit: Some(CompletionTextEdit::Edit(edit)),
+                                additional_text_edits: quotes,
+                                ..CompletionItem::default()
+                            })
+                        })
+                        .collect();
+
+                    if items.is_empty() {
+                        return Ok(None);
+                    }
+
+                    Ok(Some(CompletionResponse::Array(items)))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let url = &params.text_document_position_params.text_document.uri;
+
+        // Only dependencies whose version requirement is under the cursor are found,
+        // so hovering the crate name itself shows nothing.
+        let Some(Dependency::WithVersion(dependency)) = self
+            .manifests
+            .dependency_at(url, params.text_document_position_params.position)
+            .await
+        else {
+            return Ok(None);
+        };
+
+        if self.is_offline(url).await {
+            return Ok(None);
+        }
 
-                    let newest_version = newest_version.to_string();
+        let versions = match self.crate_versions(&dependency.name).await {
+            Ok(versions) => versions,
+            Err(err) => {
+                tracing::warn!(
+                    crate_name = dependency.name,
+                    ?err,
+                    "failed to list versions"
+                );
+                return Ok(None);
+            }
+        };
 
-                    let truncated_version = newest_version
-                        .as_str()
-                        .strip_prefix(
-                            specified_version
-                                .trim_start_matches(&['<', '>', '=', '^', '~'] as &[_]),
-                        )
-                        .unwrap_or(&newest_version)
-                        .to_string();
+        let requirement = match &dependency.version {
+            DependencyVersion::Complete { version, .. } => Some(version),
+            DependencyVersion::Partial { .. } | DependencyVersion::Invalid { .. } => None,
+        };
 
-                    Ok(Some(CompletionResponse::Array(vec![CompletionItem {
-                        insert_text: Some(truncated_version.clone()),
-                        label: newest_version.clone(),
+        let mut value = hover::version_table(
+            &dependency.name,
+            &versions,
+            requirement,
+            hover::RECENT_VERSIONS,
+        );
 
-                        ..CompletionItem::default()
-                    }])))
-                } else {
-                    Ok(None)
-                }
-            }
-            Dependency::Other { .. } => {
-                return Ok(None);
+        if self.settings.msrv_aware().await {
+            if let Some(note) =
+                requirement.and_then(|requirement| hover::rust_version_note(&versions, requirement))
+            {
+                value.push_str(&format!("\n\n{note}"));
             }
         }
+
+        Ok(Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(dependency.version.range()),
+        }))
     }
 
     async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
-        if !self.settings.inlay_hints().await {
+        let manifest_settings = self.manifests.settings(&params.text_document.uri).await;
+        let inlay_hints_enabled = match manifest_settings.inlay_hints {
+            Some(inlay_hints) => inlay_hints,
+            None => {
+                self.settings
+                    .inlay_hints_for(&params.text_document.uri)
+                    .await
+            }
+        };
+        if !inlay_hints_enabled {
             return Ok(None);
         }
 
-        let utd_hint = self.settings.up_to_date_hint().await;
-        let nu_hint = self.settings.needs_update_hint().await;
+        let (utd_hint, nu_hint) = self.settings.hint_templates().await;
+
+        let sections: Vec<_> = if self.settings.section_summary_hints().await {
+            self.manifests
+                .sections(&params.text_document.uri)
+                .await
+                .into_iter()
+                .filter(|section| {
+                    section.header.start >= params.range.start
+                        && section.header.end <= params.range.end
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-        if utd_hint.is_empty() && nu_hint.is_empty() {
+        if utd_hint.is_empty() && nu_hint.is_empty() && sections.is_empty() {
             return Ok(None);
         }
 
-        let Some(dependencies) = self.manifests.get(&params.text_document.uri).await else {
-            return Ok(None);
-        };
-        let dependencies_with_versions: Vec<DependencyWithVersion> = dependencies
-            .into_iter()
-            .filter_map(|d| match d {
-                Dependency::WithVersion(v) => (v.version.range().start >= params.range.start
-                    && v.version.range().end <= params.range.end)
-                    .then_some(v),
-                Dependency::Other { .. } | Dependency::Partial { .. } => None,
+        let versioned = |dependencies: Vec<Dependency>| {
+            dependencies.into_iter().filter_map(|d| match d {
+                Dependency::WithVersion(v) if manifest_settings.is_ignored(&v.name) => None,
+                Dependency::WithVersion(v) => Some(v),
+                Dependency::Git { .. }
+                | Dependency::GitSource { .. }
+                | Dependency::WorkspaceInherited { .. }
+                | Dependency::Other { .. }
+                | Dependency::Partial { .. } => None,
             })
-            .collect();
+        };
+
+        // Summaries cover their entire section, even if only the header is visible.
+        let summarized: Vec<DependencyWithVersion> = match (sections.first(), sections.last()) {
+            (Some(first), Some(last)) => {
+                let range = Range::new(first.header.start, Position::new(last.last_line, 0));
+                versioned(
+                    self.manifests
+                        .within(&params.text_document.uri, range)
+                        .await,
+                )
+                .filter(|dep| sections.iter().any(|section| section.contains(dep)))
+                .collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let dependencies_with_versions: Vec<DependencyWithVersion> = versioned(
+            self.manifests
+                .within(&params.text_document.uri, params.range)
+                .await,
+        )
+        .filter(|v| {
+            v.version.range().start >= params.range.start
+                && v.version.range().end <= params.range.end
+        })
+        .collect();
 
-        if dependencies_with_versions.is_empty() {
+        if dependencies_with_versions.is_empty() && summarized.is_empty() {
             return Ok(None);
         }
 
-        let crate_names: Vec<&str> = dependencies_with_versions
+        let mut crate_names: Vec<&str> = dependencies_with_versions
             .iter()
+            .chain(&summarized)
             .map(|x| x.name.as_str())
             .collect();
+        crate_names.sort_unstable();
+        crate_names.dedup();
 
-        let newest_packages = if self.settings.use_api().await {
-            self.api
-                .fetch_versions(self.cache.clone(), &crate_names)
-                .await
-        } else {
-            self.sparse
-                .fetch_versions(self.cache.clone(), &crate_names)
-                .await
-        };
+        // Rather than waiting for the registry, hint the crates already looked up and
+        // have the client request the hints anew once the rest are known, if it can.
+        // Expired crates are hinted as well, until they have been looked up anew.
+        let url = &params.text_document.uri;
+        let newest_packages =
+            if self.inlay_hint_refresh.load(Ordering::Relaxed) && !self.is_offline(url).await {
+                let cache = self.registry_cache().await;
+                let cached = cache.cached_versions(&crate_names).await;
+                let mut missing = Vec::new();
+                for crate_name in &crate_names {
+                    if !matches!(
+                        cache.get(crate_name).await,
+                        CachedVersion::Known(_) | CachedVersion::DoesNotExist
+                    ) {
+                        missing.push(crate_name.to_string());
+                    }
+                }
+                if !missing.is_empty() {
+                    self.refresh_hints_after_lookup(url, missing);
+                }
+                cached
+            } else {
+                self.versions_for(url, &crate_names).await
+            };
 
-        let mut v = if utd_hint.is_empty() || nu_hint.is_empty() {
-            Vec::new() // if either is empty we dont know how many elements there are
-        } else {
-            Vec::with_capacity(dependencies_with_versions.len())
-        };
+        let mut v = Vec::new();
 
-        for dep in dependencies_with_versions {
-            let Some(Some(newest_version)) = newest_packages.get(&dep.name) else {
+        for section in &sections {
+            let Some(summary) = summary::section_summary(section, &summarized, &newest_packages)
+            else {
                 continue;
             };
-            let (hint, tip, pos) = match dep.version {
-                DependencyVersion::Complete { range, version } => {
-                    let (hint, tip) = if version.matches(newest_version) {
-                        if utd_hint.is_empty() {
-                            continue;
-                        }
-                        (
-                            utd_hint.replace("{}", &version.to_string()),
-                            "up to date".to_string(),
-                        )
-                    } else {
-                        if nu_hint.is_empty() {
-                            continue;
-                        }
-                        (
-                            nu_hint.replace("{}", &newest_version.to_string()),
-                            "latest stable version".to_string(),
-                        )
-                    };
-                    (
-                        hint,
-                        tip,
-                        Position::new(range.end.line, range.end.character + 1),
-                    )
-                }
-                DependencyVersion::Partial { range, .. } => {
-                    if nu_hint.is_empty() {
-                        continue;
-                    }
-                    (
-                        nu_hint.replace("{}", &newest_version.to_string()),
-                        "latest stable version".to_string(),
-                        Position::new(range.end.line, range.end.character + 1),
-                    )
-                }
-            };
+
             v.push(InlayHint {
-                position: pos,
-                label: InlayHintLabel::String(hint),
+                position: section.header.end,
+                label: InlayHintLabel::String(summary),
                 kind: None,
                 text_edits: None,
-                tooltip: Some(InlayHintTooltip::String(tip)),
+                tooltip: None,
                 padding_left: Some(true),
                 padding_right: None,
                 data: None,
             });
         }
+
+        let source = self
+            .manifests
+            .source(&params.text_document.uri)
+            .await
+            .unwrap_or_default();
+        let lines: Vec<_> = source.lines().collect();
+        let anchor = match self.settings.inlay_hint_anchor().await {
+            InlayHintAnchor::VersionEnd => hints::Anchor::VersionEnd,
+            InlayHintAnchor::LineEnd => hints::Anchor::LineEnd {
+                lines: &lines,
+                encoding: self.manifests.encoding().await,
+            },
+        };
+
+        let lockfile = self.lockfile_for(&params.text_document.uri).await;
+        v.extend(hints::version_hints(
+            dependencies_with_versions,
+            &newest_packages,
+            lockfile.as_ref(),
+            &utd_hint,
+            &nu_hint,
+            anchor,
+            time::OffsetDateTime::now_utc(),
+        ));
         Ok(Some(v))
     }
 
+    async fn execute_command(
+        &self,
+        params: ExecuteCommandParams,
+    ) -> Result<Option<serde_json::Value>> {
+        match params.command.as_str() {
+            commands::REFRESH_CRATE => {
+                let Some(serde_json::Value::String(crate_name)) = params.arguments.first() else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "expected crate name as first argument",
+                    ));
+                };
+
+                self.refresh_crate(crate_name).await;
+                Ok(None)
+            }
+            commands::SHOW_CONFIG => {
+                let config = self.settings.effective().await;
+                self.client
+                    .log_message(
+                        MessageType::INFO,
+                        format!("crates-lsp configuration: {config:#}"),
+                    )
+                    .await;
+                Ok(Some(config))
+            }
+            commands::CACHE_STATS => {
+                let stats = self.cache.stats().await;
+                let hit_rate = stats
+                    .hit_rate
+                    .map(|rate| format!("{:.1}%", rate * 100.0))
+                    .unwrap_or_else(|| "n/a".to_string());
+                self.client
+                    .log_message(
+                        MessageType::INFO,
+                        format!(
+                            "crates-lsp cache: {}/{} entries, {} hits, {} misses ({hit_rate} hit rate), {} evictions",
+                            stats.entries, stats.capacity, stats.hits, stats.misses, stats.evictions
+                        ),
+                    )
+                    .await;
+                Ok(serde_json::to_value(stats).ok())
+            }
+            commands::SET_VERSION => {
+                let mut arguments = params.arguments.iter().map(serde_json::Value::as_str);
+                let (Some(Some(uri)), Some(Some(crate_name)), Some(Some(version))) =
+                    (arguments.next(), arguments.next(), arguments.next())
+                else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "expected manifest uri, crate name and version as arguments",
+                    ));
+                };
+
+                let Ok(uri) = Url::parse(uri) else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                        "invalid manifest uri: {uri}"
+                    )));
+                };
+
+                if VersionReq::parse(version).is_err() {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                        "invalid version requirement: {version}"
+                    )));
+                }
+
+                let Some(range) = self.manifests.version_range(&uri, crate_name).await else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                        "no versioned dependency named {crate_name} in {uri}"
+                    )));
+                };
+
+                let edit = WorkspaceEdit {
+                    changes: Some(
+                        [(
+                            uri,
+                            vec![TextEdit {
+                                range,
+                                new_text: version.to_string(),
+                            }],
+                        )]
+                        .into(),
+                    ),
+                    document_changes: None,
+                    change_annotations: None,
+                };
+
+                Ok(serde_json::to_value(edit).ok())
+            }
+            commands::LATEST_VERSION => {
+                let (url, crate_name) = self.crate_argument(&params.arguments).await?;
+
+                let versions = match &url {
+                    Some(url) => self.versions_for(url, &[&crate_name]).await,
+                    None if self.settings.offline().await.unwrap_or_default() => {
+                        self.registry_cache()
+                            .await
+                            .cached_versions(&[&crate_name])
+                            .await
+                    }
+                    None => self.fetch_versions(&[&crate_name]).await,
+                };
+
+                match versions.get(&crate_name) {
+                    Some(Some(release)) => {
+                        Ok(Some(serde_json::Value::String(release.version.to_string())))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            commands::OPEN_REPOSITORY => {
+                let (url, crate_name) = self.crate_argument(&params.arguments).await?;
+
+                let offline = match &url {
+                    Some(url) => self.is_offline(url).await,
+                    None => self.settings.offline().await.unwrap_or_default(),
+                };
+
+                // Crates without a repository, or which couldn't be looked up, are
+                // opened on crates.io instead.
+                let repository = if offline {
+                    None
+                } else {
+                    match self.api.repository(&crate_name).await {
+                        Ok(repository) => repository,
+                        Err(err) => {
+                            tracing::warn!(crate_name, ?err, "failed to look up repository");
+                            None
+                        }
+                    }
+                };
+                let link = repository
+                    .or_else(|| Url::parse(&format!("https://crates.io/crates/{crate_name}")).ok());
+
+                let Some(link) = link else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                        "invalid crate name: {crate_name}"
+                    )));
+                };
+
+                if self.show_document.load(Ordering::Relaxed) {
+                    let params = ShowDocumentParams {
+                        uri: link.clone(),
+                        external: Some(true),
+                        take_focus: Some(true),
+                        selection: None,
+                    };
+                    if let Err(err) = self.client.show_document(params).await {
+                        tracing::warn!(%link, ?err, "failed to show repository");
+                    }
+                }
+
+                Ok(Some(serde_json::Value::String(link.to_string())))
+            }
+            commands::PREVIEW_UPDATES => {
+                let Some(serde_json::Value::String(uri)) = params.arguments.first() else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(
+                        "expected manifest uri as first argument",
+                    ));
+                };
+
+                let Ok(uri) = Url::parse(uri) else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                        "invalid manifest uri: {uri}"
+                    )));
+                };
+
+                let Some(source) = self.manifests.source(&uri).await else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                        "manifest is not open: {uri}"
+                    )));
+                };
+
+                let edits: Vec<_> = self
+                    .calculate_diagnostics(uri.clone(), &source, None)
+                    .await
+                    .iter()
+                    .flat_map(|diagnostic| {
+                        if let Some(edits) = diagnostics::summary_edits(diagnostic) {
+                            return edits;
+                        }
+                        diagnostics::suggested_update(diagnostic)
+                            .map(|update| TextEdit::new(diagnostic.range, update.replacement))
+                            .into_iter()
+                            .collect()
+                    })
+                    .collect();
+
+                // The diff is rendered from byte offsets, unlike the edits themselves.
+                let encoding = self.manifests.encoding().await;
+                let decoded: Vec<_> = edits
+                    .iter()
+                    .map(|edit| {
+                        TextEdit::new(encoding.decode(&source, edit.range), edit.new_text.clone())
+                    })
+                    .collect();
+
+                let name = uri
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .unwrap_or("Cargo.toml");
+
+                Ok(Some(serde_json::json!({
+                    "diff": preview::unified_diff(name, &source, &decoded),
+                    "edit": WorkspaceEdit {
+                        changes: Some([(uri.clone(), edits)].into()),
+                        document_changes: None,
+                        change_annotations: None,
+                    },
+                })))
+            }
+            _ => Err(tower_lsp::jsonrpc::Error::method_not_found()),
+        }
+    }
+
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
         let mut response = CodeActionResponse::new();
         for d in params
@@ -420,23 +1932,74 @@ impl LanguageServer for Backend {
             .into_iter()
             .filter(|d| d.range.start <= params.range.start && d.range.end >= params.range.end)
         {
-            let Some(NumberOrString::Number(diagnostic_codes::NEEDS_UPDATE)) = d.code else {
+            if let Some(edits) = diagnostics::summary_edits(&d) {
+                response.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Update All Dependencies".to_string(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some([(params.text_document.uri.clone(), edits)].into()),
+                        document_changes: None,
+                        change_annotations: None,
+                    }),
+                    diagnostics: Some(vec![d]),
+                    ..CodeAction::default()
+                }));
                 continue;
-            };
+            }
 
-            let Some(serde_json::Value::Object(ref data)) = d.data else {
+            if let Some(edit) = diagnostics::workspace_fix(&d) {
+                response.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: "Inherit from Workspace".to_string(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some([(params.text_document.uri.clone(), vec![edit])].into()),
+                        document_changes: None,
+                        change_annotations: None,
+                    }),
+                    diagnostics: Some(vec![d]),
+                    ..CodeAction::default()
+                }));
                 continue;
-            };
+            }
+
+            if let Some(replacement) = diagnostics::style_fix(&d) {
+                response.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("Rewrite Requirement as: {replacement}"),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    edit: Some(WorkspaceEdit {
+                        changes: Some(
+                            [(
+                                params.text_document.uri.clone(),
+                                vec![TextEdit::new(d.range, replacement)],
+                            )]
+                            .into(),
+                        ),
+                        document_changes: None,
+                        change_annotations: None,
+                    }),
+                    diagnostics: Some(vec![d]),
+                    ..CodeAction::default()
+                }));
+                continue;
+            }
 
-            let Some(serde_json::Value::String(newest_version)) = data.get("newest_version") else {
+            let Some(diagnostics::Update {
+                newest_version,
+                replacement,
+                breaking,
+            }) = diagnostics::suggested_update(&d)
+            else {
                 continue;
             };
 
             let range = d.range;
-            let newest_version = newest_version.clone();
 
             response.push(CodeActionOrCommand::CodeAction(CodeAction {
-                title: format!("Update Version to: {newest_version}"),
+                title: if breaking {
+                    format!("Update Version to: {newest_version} (breaking)")
+                } else {
+                    format!("Update Version to: {newest_version}")
+                },
                 kind: Some(CodeActionKind::QUICKFIX),
                 diagnostics: Some(vec![d]),
                 edit: Some(WorkspaceEdit {
@@ -445,7 +2008,7 @@ impl LanguageServer for Backend {
                             params.text_document.uri.clone(),
                             vec![TextEdit {
                                 range,
-                                new_text: newest_version,
+                                new_text: replacement,
                             }],
                         )]
                         .into(),
@@ -459,21 +2022,500 @@ impl LanguageServer for Backend {
                 data: None,
             }))
         }
+        // Show the newest version of the dependency under the cursor for reference,
+        // without touching the manifest. Clients ask for code actions whenever the
+        // cursor moves, so only versions which are already cached are shown.
+        let url = &params.text_document.uri;
+        if let Some(crate_name) = self
+            .manifests
+            .name_on_line(url, params.range.start.line)
+            .await
+        {
+            if let Some(Some(release)) = self
+                .registry_cache()
+                .await
+                .cached_versions(&[&crate_name])
+                .await
+                .get(&crate_name)
+            {
+                response.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: format!("Latest Version: {}", release.version),
+                    ..CodeAction::default()
+                }));
+            }
+        }
+
         Ok(Some(response))
     }
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
-    let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
+/// Offer the given crates as completions replacing the crate name at the given range.
+fn name_completions(crates: Vec<Crate>, range: Range) -> CompletionResponse {
+    CompletionResponse::Array(
+        crates
+            .into_iter()
+            .map(|x| CompletionItem {
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    range,
+                    x.name.clone(),
+                ))),
+                label: x.name,
+                ..CompletionItem::default()
+            })
+            .collect(),
+    )
+}
+
+/// Offer the features of the newest release matching the dependency's requirement as
+/// completions replacing the feature name at the given range. Features which are
+/// already enabled aren't offered again.
+fn feature_completions(
+    dependency: &DependencyWithVersion,
+    published: &[PublishedVersion],
+    range: Range,
+) -> Option<CompletionResponse> {
+    let release = published
+        .iter()
+        .filter(|release| !release.yanked)
+        .filter(|release| match &dependency.version {
+            DependencyVersion::Complete { version, .. } => version.matches(&release.version),
+            _ => true,
+        })
+        .max_by(|a, b| a.version.cmp(&b.version))?;
+
+    let items: Vec<_> = release
+        .features
+        .iter()
+        .filter(|name| {
+            !dependency
+                .features
+                .iter()
+                .any(|feature| feature.range != range && &feature.name == *name)
+        })
+        .map(|name| CompletionItem {
+            label: name.clone(),
+            detail: Some(format!("{} {}", dependency.name, release.version)),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, name.clone()))),
+            ..CompletionItem::default()
+        })
+        .collect();
+
+    if items.is_empty() {
+        return None;
+    }
+
+    Some(CompletionResponse::Array(items))
+}
+
+/// Read the manifest of the workspace the manifest belongs to, off the async runtime
+/// since every ancestor directory may be searched.
+async fn workspace_root(url: &Url) -> Option<String> {
+    let manifest = url.to_file_path().ok()?;
+    tokio::task::spawn_blocking(move || cargo_config::workspace_root(&manifest))
+        .await
+        .ok()
+        .flatten()
+}
+
+/// Resolve the dependencies which the manifest inherits from its workspace root,
+/// with their ranges pointing to the names of the inheriting dependencies.
+async fn inherited_dependencies(url: &Url, packages: &[Dependency]) -> Vec<DependencyWithVersion> {
+    let inherited: Vec<_> = packages
+        .iter()
+        .filter_map(|dependency| match dependency {
+            Dependency::WorkspaceInherited { name, line } => Some((name, *line)),
+            _ => None,
+        })
+        .collect();
+
+    if inherited.is_empty() {
+        return Vec::new();
+    }
+
+    let Some(root) = workspace_root(url).await else {
+        return Vec::new();
+    };
+
+    let workspace = parse::workspace_dependencies(&root);
+
+    inherited
+        .into_iter()
+        .filter_map(|(name, line)| {
+            let dependency = workspace
+                .iter()
+                .find(|dependency| dependency.name == *name)?;
+            let DependencyVersion::Complete { version, .. } = &dependency.version else {
+                return None;
+            };
+
+            // Workspace dependencies renamed through `package` are looked up under
+            // the crate they refer to.
+            let package = parse::dependency_value(&root, dependency, "package").unwrap_or(name);
+
+            Some(DependencyWithVersion {
+                name: package.to_string(),
+                name_range: None,
+                version: DependencyVersion::Complete {
+                    range: Range::new(
+                        Position::new(line, 0),
+                        Position::new(line, name.len() as u32),
+                    ),
+                    version: version.clone(),
+                },
+                default_features: dependency.default_features,
+                features: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// Response to the `crates-lsp/status` request.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    #[serde(flatten)]
+    counts: summary::StatusCounts,
+    /// Whether any registry lookups are in progress.
+    fetching: bool,
+}
+
+/// Everything needed to produce the diagnostics of a manifest, other than the newest
+/// versions of its dependencies, so that they can be produced again as more of those
+/// become known.
+struct ManifestDiagnostics<'a> {
+    content: &'a str,
+    release_dependencies: Vec<&'a DependencyWithVersion>,
+    /// Dependencies on pre-releases which have been resolved through `prereleases`.
+    prerelease_dependencies: Vec<&'a DependencyWithVersion>,
+    inherited: Vec<&'a DependencyWithVersion>,
+    prereleases: HashMap<String, PrereleaseStatus>,
+    severities: diagnostics::Severities,
+    lockfile: Option<Lockfile>,
+    encoding: PositionEncoding,
+    /// Age in years after which crates are reported as stale, and at what severity.
+    stale_crates: Option<(u32, DiagnosticSeverity)>,
+    order: settings::DiagnosticOrder,
+    /// Whether to report requirements without an upper bound.
+    warn_unbounded: bool,
+    /// Lowest major version required of specific crates.
+    minimum_majors: HashMap<String, u64>,
+    /// Whether to summarize the versions in a single diagnostic instead of per line.
+    summary: bool,
+    /// Entries of `[patch.crates-io]` to check against the registry, if enabled.
+    patches: &'a [DependencyWithVersion],
+    /// Name of the registry the crates were looked up in, for messages.
+    registry: String,
+    /// Diagnostics which don't depend on the newest versions.
+    other: Vec<Diagnostic>,
+}
+
+impl ManifestDiagnostics<'_> {
+    fn render(&self, newest_packages: &HashMap<String, Option<Release>>) -> Vec<Diagnostic> {
+        let mut diagnostics = diagnostics::version_diagnostics(
+            self.content,
+            &self.release_dependencies,
+            newest_packages,
+            &self.severities,
+            self.lockfile.as_ref(),
+            self.encoding,
+        );
+        diagnostics.extend(diagnostics::prerelease_diagnostics(
+            self.content,
+            &self.prerelease_dependencies,
+            &self.prereleases,
+            &self.severities,
+            self.encoding,
+        ));
+
+        // Inherited dependencies can only be updated in the workspace root, so
+        // don't offer to replace the `workspace = true` entry with a version.
+        diagnostics.extend(
+            diagnostics::version_diagnostics(
+                self.content,
+                &self.inherited,
+                newest_packages,
+                &self.severities,
+                self.lockfile.as_ref(),
+                self.encoding,
+            )
+            .into_iter()
+            .map(|diagnostic| Diagnostic {
+                data: None,
+                ..diagnostic
+            }),
+        );
+
+        diagnostics.extend(self.other.iter().cloned());
+
+        let dependencies: Vec<_> = self
+            .release_dependencies
+            .iter()
+            .chain(&self.prerelease_dependencies)
+            .copied()
+            .collect();
+        if self.warn_unbounded {
+            diagnostics.extend(diagnostics::unbounded_diagnostics(
+                &dependencies,
+                newest_packages,
+            ));
+        }
+        if !self.minimum_majors.is_empty() {
+            diagnostics.extend(diagnostics::minimum_major_diagnostics(
+                self.content,
+                &dependencies,
+                &self.minimum_majors,
+                newest_packages,
+                self.encoding,
+            ));
+        }
+        diagnostics.extend(diagnostics::redundant_patch_diagnostics(
+            self.content,
+            self.patches,
+            newest_packages,
+            &self.registry,
+            self.encoding,
+        ));
+
+        // Flag crates which haven't seen a release in a long time, as this might
+        // indicate that they have been abandoned or superseded.
+        if let Some((threshold, severity)) = self.stale_crates {
+            let dependencies: Vec<_> = self
+                .release_dependencies
+                .iter()
+                .chain(&self.prerelease_dependencies)
+                .copied()
+                .collect();
+            diagnostics.extend(diagnostics::stale_diagnostics(
+                &dependencies,
+                newest_packages,
+                threshold,
+                severity,
+                time::OffsetDateTime::now_utc(),
+            ));
+        }
+
+        if self.summary {
+            diagnostics =
+                diagnostics::summarize(self.content, diagnostics, &self.severities, self.encoding);
+        }
+
+        diagnostics::sort(&mut diagnostics, self.order);
+        diagnostics
+    }
+}
+
+/// How often diagnostics are published while the versions of a manifest's
+/// dependencies are still being looked up.
+const INTERIM_DIAGNOSTICS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Only report progress when fetching at least this many crates, so that small
+/// manifests don't cause progress indicators to flicker.
+const PROGRESS_THRESHOLD: usize = 10;
+
+/// Relay the progress of a crate fetch to the client as work done progress,
+/// ending it once the fetch completes and the channel is closed.
+async fn report_progress(
+    client: Client,
+    token: NumberOrString,
+    mut progress: mpsc::UnboundedReceiver<(usize, usize)>,
+) {
+    let mut begun = false;
+
+    while let Some((completed, total)) = progress.recv().await {
+        if total < PROGRESS_THRESHOLD {
+            continue;
+        }
+
+        let message = Some(format!("Fetching crate versions... {completed}/{total}"));
+        let percentage = Some((completed * 100 / total) as u32);
+
+        let value = if begun {
+            WorkDoneProgress::Report(WorkDoneProgressReport {
+                cancellable: Some(false),
+                message,
+                percentage,
+            })
+        } else {
+            let created = client
+                .send_request::<request::WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                    token: token.clone(),
+                })
+                .await;
+
+            if created.is_err() {
+                return;
+            }
+
+            begun = true;
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "crates-lsp".to_string(),
+                cancellable: Some(false),
+                message,
+                percentage,
+            })
+        };
+
+        client
+            .send_notification::<notification::Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(value),
+            })
+            .await;
+    }
+
+    if begun {
+        client
+            .send_notification::<notification::Progress>(ProgressParams {
+                token,
+                value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(WorkDoneProgressEnd {
+                    message: None,
+                })),
+            })
+            .await;
+    }
+}
+
+/// Write the server's operational log to the given file. This must never write to
+/// stdout, since that is used for communicating with the client.
+fn init_logging(path: &std::path::Path) -> std::io::Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+
+    tracing_subscriber::fmt()
+        .with_writer(std::sync::Mutex::new(file))
+        .with_ansi(false)
+        .with_max_level(tracing::Level::DEBUG)
+        // Log the fields of the metrics spans once they're complete.
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .init();
+
+    Ok(())
+}
+
+/// Custom requests which take no parameters.
+const PARAMETERLESS_REQUESTS: [&str; 2] = ["crates-lsp/settingsSchema", "crates-lsp/status"];
+
+/// Drop the parameters of requests which take none. tower-lsp rejects any `params`
+/// for those, but clients may still send an empty object or `null`.
+fn ignore_params(request: jsonrpc::Request) -> jsonrpc::Request {
+    if !PARAMETERLESS_REQUESTS.contains(&request.method()) {
+        return request;
+    }
+
+    let (method, id, _) = request.into_parts();
+    let request = jsonrpc::Request::build(method);
+    match id {
+        Some(id) => request.id(id).finish(),
+        None => request.finish(),
+    }
+}
 
-    let (service, socket) = LspService::new(|client| Backend {
+type LanguageService = MapRequest<LspService<Backend>, fn(jsonrpc::Request) -> jsonrpc::Request>;
+
+/// Build the language server, including its custom `crates-lsp/*` requests.
+fn service(
+    http: SharedClient,
+    sparse: CrateIndex,
+    client_process_id: Option<u32>,
+) -> (LanguageService, ClientSocket) {
+    let git_tags = GitTags::new(http.allowed_hosts());
+
+    let (service, socket) = LspService::build(|client| Backend {
         client,
         manifests: ManifestTracker::default(),
         settings: Settings::default(),
-        sparse: CrateIndex::default(),
-        api: CrateApi::default(),
+        sparse,
+        api: CrateApi::new(http.clone()),
         cache: CrateCache::default(),
-    });
+        searches: SearchCache::default(),
+        versions: VersionsCache::default(),
+        http,
+        git_tags,
+        work_done_progress: Arc::default(),
+        show_document: Arc::default(),
+        watch_files: Arc::default(),
+        inlay_hint_refresh: Arc::default(),
+        pending_hints: Arc::default(),
+        document_versions: Arc::default(),
+        pending_diagnostics: Arc::default(),
+        cargo_offline: Arc::default(),
+        progress_tokens: Arc::default(),
+        client_process_id,
+    })
+    .custom_method("crates-lsp/settingsSchema", Backend::settings_schema)
+    .custom_method("crates-lsp/status", Backend::status)
+    .finish();
+
+    (service.map_request(ignore_params as fn(_) -> _), socket)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let arguments = Arguments::parse(std::env::args().skip(1));
+
+    if let Some(log_file) = &arguments.log_file {
+        if let Err(err) = init_logging(log_file) {
+            eprintln!("failed to open log file {}: {err}", log_file.display());
+        }
+    }
+
+    let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
+
+    let http = SharedClient::default();
+    let sparse = CrateIndex::new(http.clone());
+    if let Some(registry) = arguments
+        .registry
+        .clone()
+        .or_else(|| std::env::var("CRATES_LSP_REGISTRY").ok())
+    {
+        tracing::info!(registry, "looking up crates in custom registry");
+        sparse.set_index_url(&registry);
+    }
+
+    let (service, socket) = service(http, sparse, arguments.client_process_id);
     Server::new(stdin, stdout, socket).serve(service).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tower::{Service, ServiceExt};
+    use tower_lsp::jsonrpc::Request;
+
+    use crate::crates::sparse::CrateIndex;
+    use crate::crates::SharedClient;
+
+    #[tokio::test]
+    async fn parameterless_requests_ignore_params() {
+        let http = SharedClient::default();
+        let (mut service, _socket) = super::service(http.clone(), CrateIndex::new(http), None);
+
+        let initialize = Request::build("initialize")
+            .id(0)
+            .params(json!({ "capabilities": {} }))
+            .finish();
+        service
+            .ready()
+            .await
+            .unwrap()
+            .call(initialize)
+            .await
+            .unwrap();
+
+        for method in ["crates-lsp/status", "crates-lsp/settingsSchema"] {
+            let requests = [
+                Request::build(method).id(1).finish(),
+                Request::build(method).id(2).params(json!(null)).finish(),
+                Request::build(method).id(3).params(json!({})).finish(),
+            ];
+
+            for request in requests {
+                let response = service.ready().await.unwrap().call(request).await.unwrap();
+                let (id, result) = response.unwrap().into_parts();
+                assert!(result.is_ok(), "{method} request {id} failed: {result:?}");
+            }
+        }
+    }
+}
@@ -1,8 +1,16 @@
-use std::{collections::HashMap, fmt::Display, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+    sync::Arc,
+};
 
-use semver::VersionReq;
+use semver::{Version, VersionReq};
 use tokio::sync::RwLock;
-use tower_lsp::lsp_types::{Position, Range, Url};
+use tower_lsp::lsp_types::{Position, Range, TextEdit, Url};
+
+use crate::encoding::PositionEncoding;
+use crate::requirement;
+use crate::settings::ManifestSettings;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Dependency {
@@ -12,7 +20,26 @@ pub enum Dependency {
         line: u32,
     },
     WithVersion(DependencyWithVersion),
-    /// e.g: anyhow = { git = ".."}
+    /// e.g: anyhow = { git = "..", tag = "v1.0.0" }
+    Git {
+        name: String,
+        url: String,
+        tag: String,
+        /// Range of the tag value.
+        range: Range,
+    },
+    /// e.g: anyhow = { git = "..", branch = "main" }, or with a `version`, which only
+    /// states what the checkout claims to be.
+    GitSource {
+        name: String,
+        url: String,
+    },
+    /// e.g: anyhow = { workspace = true, features = ["backtrace"] }
+    WorkspaceInherited {
+        name: String,
+        line: u32,
+    },
+    /// e.g: anyhow = { path = ".." }
     Other {
         name: String,
     },
@@ -20,7 +47,22 @@ pub enum Dependency {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DependencyWithVersion {
     pub name: String,
+    /// Range of the crate name, unless specified in table form, e.g. `[dependencies.serde]`.
+    pub name_range: Option<Range>,
     pub version: DependencyVersion,
+    /// Whether the crate's default features are enabled, i.e. not disabled
+    /// through `default-features = false`.
+    pub default_features: bool,
+    /// Features enabled through `features = [...]`, which may span several lines.
+    pub features: Vec<Feature>,
+}
+
+/// A feature enabled for a dependency, e.g. `"derive"` in `features = ["derive"]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feature {
+    pub name: String,
+    /// Range of the feature name, excluding the quotes.
+    pub range: Range,
 }
 // pub struct Dependency {
 //     pub name: String,
@@ -32,7 +74,10 @@ impl Dependency {
         match self {
             Dependency::Partial { .. } => None,
             Dependency::WithVersion(dep) => Some(&dep.name),
-            Dependency::Other { name } => Some(name),
+            Dependency::Git { name, .. }
+            | Dependency::GitSource { name, .. }
+            | Dependency::WorkspaceInherited { name, .. }
+            | Dependency::Other { name } => Some(name),
         }
     }
 
@@ -40,15 +85,86 @@ impl Dependency {
         match self {
             Dependency::Partial { .. } => None,
             Dependency::WithVersion(dep) => Some(&mut dep.name),
-            Dependency::Other { name } => Some(name),
+            Dependency::Git { name, .. }
+            | Dependency::GitSource { name, .. }
+            | Dependency::WorkspaceInherited { name, .. }
+            | Dependency::Other { name } => Some(name),
         }
     }
 
-    pub fn version_mut(&mut self) -> Option<&mut DependencyVersion> {
+    /// Convert the byte offsets produced by the parser to the negotiated encoding,
+    /// given the lines of the source.
+    fn encode_ranges(&mut self, lines: &[&str], encoding: PositionEncoding) {
+        let encode = |range: &mut Range| {
+            let line = lines
+                .get(range.start.line as usize)
+                .copied()
+                .unwrap_or_default();
+            *range = encoding.encode_line(line, *range)
+        };
+
         match self {
-            Dependency::Partial { .. } => None,
-            Dependency::WithVersion(dep) => Some(&mut dep.version),
-            Dependency::Other { .. } => None,
+            Dependency::WithVersion(dep) => {
+                if let Some(name_range) = &mut dep.name_range {
+                    encode(name_range);
+                }
+                encode(dep.version.range_mut());
+                for feature in &mut dep.features {
+                    encode(&mut feature.range);
+                }
+            }
+            Dependency::Git { range, .. } => encode(range),
+            Dependency::Partial { .. }
+            | Dependency::GitSource { .. }
+            | Dependency::WorkspaceInherited { .. }
+            | Dependency::Other { .. } => (),
+        }
+    }
+
+    /// Lines on which the dependency has ranges, such as those of its requirement and
+    /// features, for looking it up by position.
+    fn lines(&self) -> Vec<u32> {
+        match self {
+            Dependency::WithVersion(dep) => {
+                let mut lines: Vec<_> = dep
+                    .name_range
+                    .iter()
+                    .map(|range| range.start.line)
+                    .chain([dep.version.range().start.line])
+                    .chain(dep.features.iter().map(|feature| feature.range.start.line))
+                    .collect();
+                lines.sort_unstable();
+                lines.dedup();
+                lines
+            }
+            Dependency::Partial { line, .. } | Dependency::WorkspaceInherited { line, .. } => {
+                vec![*line]
+            }
+            Dependency::Git { range, .. } => vec![range.start.line],
+            Dependency::GitSource { .. } | Dependency::Other { .. } => Vec::new(),
+        }
+    }
+
+    /// Line::parse assumes line 0 and is given the line without its indentation, so
+    /// ranges have to be moved to the actual line and past the indentation.
+    fn set_position(&mut self, line: u32, indent: u32) {
+        let move_range = |range: &mut Range| {
+            range.start = Position::new(line, range.start.character + indent);
+            range.end = Position::new(line, range.end.character + indent);
+        };
+
+        match self {
+            Dependency::WithVersion(dep) => {
+                if let Some(name_range) = &mut dep.name_range {
+                    move_range(name_range);
+                }
+                move_range(dep.version.range_mut());
+            }
+            Dependency::Git { range, .. } => move_range(range),
+            Dependency::Partial { .. }
+            | Dependency::GitSource { .. }
+            | Dependency::WorkspaceInherited { .. }
+            | Dependency::Other { .. } => (),
         }
     }
 }
@@ -62,6 +178,15 @@ impl Display for Dependency {
             Dependency::WithVersion(dep) => {
                 write!(f, "{} = \"{}\"", dep.name, dep.version)
             }
+            Dependency::Git { name, tag, .. } => {
+                write!(f, "{} = {{ tag = \"{}\" }}", name, tag)
+            }
+            Dependency::GitSource { name, url } => {
+                write!(f, "{} = {{ git = \"{}\" }}", name, url)
+            }
+            Dependency::WorkspaceInherited { name, .. } => {
+                write!(f, "{} = {{ workspace = true }}", name)
+            }
             Dependency::Other { name } => {
                 write!(f, "{} = \"?\"", name)
             }
@@ -71,22 +196,37 @@ impl Display for Dependency {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DependencyVersion {
-    Partial { range: Range, version: String },
-    Complete { range: Range, version: VersionReq },
+    /// e.g: anyhow = "1.
+    Partial {
+        range: Range,
+        version: String,
+    },
+    Complete {
+        range: Range,
+        version: VersionReq,
+    },
+    /// A closed string which isn't a valid requirement, e.g: anyhow = "~>1.0"
+    Invalid {
+        range: Range,
+        version: String,
+        error: String,
+    },
 }
 
 impl DependencyVersion {
     pub fn range(&self) -> Range {
         match self {
             DependencyVersion::Partial { range, .. }
-            | DependencyVersion::Complete { range, .. } => *range,
+            | DependencyVersion::Complete { range, .. }
+            | DependencyVersion::Invalid { range, .. } => *range,
         }
     }
 
     fn range_mut(&mut self) -> &mut Range {
         match self {
             DependencyVersion::Partial { range, .. }
-            | DependencyVersion::Complete { range, .. } => range,
+            | DependencyVersion::Complete { range, .. }
+            | DependencyVersion::Invalid { range, .. } => range,
         }
     }
 }
@@ -94,18 +234,454 @@ impl DependencyVersion {
 impl Display for DependencyVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            DependencyVersion::Partial { version, .. } => f.write_str(version),
+            DependencyVersion::Partial { version, .. }
+            | DependencyVersion::Invalid { version, .. } => f.write_str(version),
             DependencyVersion::Complete { version, .. } => write!(f, "{}", version),
         }
     }
 }
 
+/// Find the quoted string assigned to `key` within an inline table, returning the
+/// byte offset of the value within the line, along with the value itself.
+fn inline_value<'a>(line: &'a str, key: &str) -> Option<(usize, &'a str)> {
+    line.match_indices(key).find_map(|(i, _)| {
+        // Make sure we matched the whole key, and not just the end of a longer one.
+        if line[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            return None;
+        }
+
+        let value = line[i + key.len()..]
+            .trim_start()
+            .strip_prefix('=')?
+            .trim_start()
+            .strip_prefix('"')?;
+
+        let start = line.len() - value.len();
+        let end = value.find('"')?;
+        Some((start, &value[..end]))
+    })
+}
+
+/// Check whether the registry named by a dependency's `registry` key is crates.io,
+/// which is also the registry used when none is named.
+fn is_default_registry(registry: &str) -> bool {
+    matches!(registry, "crates-io" | "crates.io")
+}
+
+/// Check whether the line names a registry other than crates.io, e.g.
+/// `registry = "my-registry"`, which the crate can't be looked up in.
+fn uses_other_registry(line: &str) -> bool {
+    inline_value(line, "registry").is_some_and(|(_, registry)| !is_default_registry(registry))
+}
+
+/// Check whether the line inherits the dependency from the workspace, e.g. `workspace = true`.
+fn inherits_workspace(line: &str) -> bool {
+    line.match_indices("workspace").any(|(i, _)| {
+        !line[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_')
+            && line[i + "workspace".len()..]
+                .trim_start()
+                .strip_prefix('=')
+                .is_some_and(|value| value.trim_start().starts_with("true"))
+    })
+}
+
+/// Check whether the manifest is the root of a workspace.
+pub fn is_workspace_root(source: &str) -> bool {
+    source
+        .lines()
+        .any(|line| line.trim().starts_with("[workspace]"))
+}
+
+/// Parse the dependencies declared in the `[workspace.dependencies]` table of a
+/// workspace root manifest, which members can inherit using `workspace = true`.
+/// Members inherit dependencies by their key, so renamed dependencies are named
+/// after their key rather than their `package`.
+pub fn workspace_dependencies(source: &str) -> Vec<DependencyWithVersion> {
+    // Blank out every other section, so the regular manifest parsing can be reused
+    // while preserving line numbers.
+    let mut in_workspace = false;
+    let source: Vec<_> = source
+        .lines()
+        .map(|line| {
+            if let Some(header) = line.trim().strip_prefix('[') {
+                in_workspace = header.starts_with("workspace.dependencies");
+                line
+            } else if in_workspace {
+                line
+            } else {
+                ""
+            }
+        })
+        .collect();
+
+    parse_dependencies(&source.join("\n"), false)
+        .into_iter()
+        .filter_map(|dependency| match dependency {
+            Dependency::WithVersion(dependency) => Some(dependency),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parse the versioned entries of the `[patch.crates-io]` table, which replace crates
+/// from crates.io with another source, such as a fork, usually until a fix has been
+/// released. The ranges are byte offsets.
+pub fn patched_dependencies(source: &str) -> Vec<DependencyWithVersion> {
+    // Within `[patch.crates-io.<name>]` tables the crate is named by the header, while
+    // every line of `[patch.crates-io]` itself names a crate of its own.
+    let mut table: Option<Option<String>> = None;
+    let mut patches = Vec::new();
+
+    for (i, raw_line) in source.lines().enumerate() {
+        let line = raw_line.trim();
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.split(']').next().unwrap_or_default().trim();
+            table = match header.strip_prefix("patch.crates-io") {
+                Some("") => Some(None),
+                Some(name) => name
+                    .strip_prefix('.')
+                    .map(|name| Some(name.trim().to_string())),
+                None => None,
+            };
+            continue;
+        }
+
+        let dependency = match &table {
+            Some(None) => Line::parse_requirement(line, i),
+            Some(Some(name)) if table_key(line) == Some("version") => {
+                Line::parse_requirement(line, i).map(|mut dependency| {
+                    if let Some(x) = dependency.name_mut() {
+                        x.clone_from(name)
+                    }
+                    dependency
+                })
+            }
+            _ => None,
+        };
+
+        if let Some(mut dependency) = dependency {
+            let indent = (raw_line.len() - raw_line.trim_start().len()) as u32;
+            dependency.set_position(i as u32, indent);
+            if let Dependency::WithVersion(mut dependency) = dependency {
+                if matches!(table, Some(Some(_))) {
+                    dependency.name_range = None;
+                }
+                patches.push(dependency);
+            }
+        }
+    }
+
+    patches
+}
+
+/// Find the quoted string assigned to `key` alongside the given dependency, either
+/// within its inline table or within its `[dependencies.<name>]` table.
+pub fn dependency_value<'a>(
+    source: &'a str,
+    dependency: &DependencyWithVersion,
+    key: &str,
+) -> Option<&'a str> {
+    let lines: Vec<_> = source.lines().map(str::trim).collect();
+    let line = dependency.version.range().start.line as usize;
+    lines.get(line)?;
+
+    // Only tables named by their header span several lines.
+    let table = if dependency.name_range.is_some() {
+        line..line + 1
+    } else {
+        let start = lines[..line]
+            .iter()
+            .rposition(|line| line.starts_with('['))
+            .map_or(0, |header| header + 1);
+        let end = lines[line..]
+            .iter()
+            .position(|line| line.starts_with('['))
+            .map_or(lines.len(), |header| line + header);
+        start..end
+    };
+
+    lines[table]
+        .iter()
+        .find_map(|line| inline_value(line, key).map(|(_, value)| value))
+}
+
+/// The key of a `key = value` line, or `None` if the line has no value yet.
+fn table_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once('=')?;
+    Some(key.trim())
+}
+
+/// Check whether the line disables default features, e.g. `default-features = false`.
+fn disables_default_features(line: &str) -> bool {
+    ["default-features", "default_features"].iter().any(|key| {
+        line.match_indices(key).any(|(i, _)| {
+            line[i + key.len()..]
+                .trim_start()
+                .strip_prefix('=')
+                .is_some_and(|value| value.trim_start().starts_with("false"))
+        })
+    })
+}
+
+/// Collect the features listed within a `features = [...]` array on the line, returning
+/// the bracket depth at the end of the line, which is non-zero if the array continues
+/// on the following lines.
+///
+/// If `depth` is zero, the array must be opened on this line, otherwise the line is
+/// assumed to continue an array opened on a previous line.
+fn scan_features(line: &str, line_no: u32, mut depth: usize, features: &mut Vec<Feature>) -> usize {
+    let start = if depth > 0 {
+        0
+    } else {
+        let opening = line.match_indices("features").find_map(|(i, _)| {
+            // Make sure we matched the whole key, and not e.g. `default-features`.
+            if line[..i]
+                .chars()
+                .next_back()
+                .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_')
+            {
+                return None;
+            }
+
+            let value = line[i + "features".len()..]
+                .trim_start()
+                .strip_prefix('=')?
+                .trim_start();
+            value.starts_with('[').then(|| line.len() - value.len())
+        });
+
+        match opening {
+            Some(opening) => opening,
+            None => return 0,
+        }
+    };
+
+    let mut quoted: Option<usize> = None;
+    for (i, c) in line.char_indices().skip_while(|(i, _)| *i < start) {
+        match (c, quoted) {
+            ('"', Some(name_start)) => {
+                features.push(Feature {
+                    name: line[name_start..i].to_string(),
+                    range: Range::new(
+                        Position::new(line_no, name_start as u32),
+                        Position::new(line_no, i as u32),
+                    ),
+                });
+                quoted = None;
+            }
+            ('"', None) => quoted = Some(i + 1),
+            (_, Some(_)) => (),
+            ('[', None) => depth += 1,
+            (']', None) => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    break;
+                }
+            }
+            ('#', None) => break,
+            _ => (),
+        }
+    }
+
+    depth
+}
+
+/// Check whether the name is accepted by cargo: it must start with an ASCII letter,
+/// followed by any number of ASCII letters, digits, `-` or `_`.
+pub fn is_valid_crate_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Retrieve the text covered by a single-line range of byte offsets within the source document.
+pub fn text_at(source: &str, range: Range) -> Option<&str> {
+    source
+        .lines()
+        .nth(range.start.line as usize)?
+        .get(range.start.character as usize..range.end.character as usize)
+}
+
+/// Build the edit replacing the version requirement being typed at the given range
+/// with the given version, preserving its operator and closing the quote if missing.
+///
+/// Within a comma-separated list of comparators such as `>=1.2, <2`, only the
+/// comparator the cursor is placed within is replaced.
+pub fn version_edit(
+    source: &str,
+    range: Range,
+    cursor: Position,
+    version: &Version,
+) -> Option<TextEdit> {
+    let line = source.lines().nth(range.start.line as usize)?;
+    let start = range.start.character as usize;
+
+    // Complete requirements end right before the closing quote, whereas unterminated
+    // ones extend to the end of the line, so only replace the requirement itself.
+    let closed = line.get(range.end.character as usize..)?.starts_with('"');
+    let end = if closed {
+        range.end.character as usize
+    } else {
+        start + unterminated_requirement_len(line.get(start..)?)
+    };
+
+    let requirement = line.get(start..end)?;
+    if !requirement.contains(',') {
+        let mut new_text = requirement::update_requirement(requirement, version);
+        if !closed {
+            new_text.push('"');
+        }
+
+        return Some(TextEdit::new(
+            Range::new(range.start, Position::new(range.start.line, end as u32)),
+            new_text,
+        ));
+    }
+
+    // Find the comparator which the cursor is placed within.
+    let cursor = (cursor.character as usize).saturating_sub(start);
+    let mut comparator_start = 0;
+    for comparator in requirement.split(',') {
+        let comparator_end = comparator_start + comparator.len();
+        if cursor <= comparator_end {
+            let trimmed = comparator.trim_start();
+            let whitespace = &comparator[..comparator.len() - trimmed.len()];
+            let operator_len = trimmed
+                .find(|c: char| !matches!(c, '<' | '>' | '=' | '~' | '^'))
+                .unwrap_or(trimmed.len());
+
+            let mut new_text = format!("{whitespace}{}{version}", &trimmed[..operator_len]);
+            if !closed && comparator_end == requirement.len() {
+                new_text.push('"');
+            }
+
+            return Some(TextEdit::new(
+                Range::new(
+                    Position::new(range.start.line, (start + comparator_start) as u32),
+                    Position::new(range.start.line, (start + comparator_end) as u32),
+                ),
+                new_text,
+            ));
+        }
+
+        // Skip past the comma.
+        comparator_start = comparator_end + 1;
+    }
+
+    None
+}
+
+/// Check whether the requirement at the given range, in byte offsets, is quoted,
+/// unlike one typed without quotes, as in `serde = 1`.
+pub fn is_quoted(source: &str, range: Range) -> bool {
+    source
+        .lines()
+        .nth(range.start.line as usize)
+        .and_then(|line| line.get(..range.start.character as usize))
+        .is_some_and(|before| before.trim_end().ends_with('"'))
+}
+
+/// Turn the [`version_edit`] of a requirement typed without quotes, as in `serde = 1`,
+/// into one which leaves quoting it to the returned edits, inserting the quotes around
+/// the requirement at the given range. Returns `None` if the requirement is quoted.
+pub fn quote_edits(source: &str, range: Range, edit: &mut TextEdit) -> Option<Vec<TextEdit>> {
+    if is_quoted(source, range) {
+        return None;
+    }
+
+    // The closing quote is inserted separately instead.
+    if let Some(new_text) = edit.new_text.strip_suffix('"') {
+        edit.new_text = new_text.to_string();
+    }
+
+    let quote = |position| TextEdit::new(Range::new(position, position), "\"".to_string());
+    Some(vec![quote(range.start), quote(range.end)])
+}
+
+/// Length of a requirement lacking its closing quote. Commas end the requirement,
+/// unless followed by another comparator rather than another key.
+fn unterminated_requirement_len(remainder: &str) -> usize {
+    for (i, c) in remainder.char_indices() {
+        match c {
+            ',' => {
+                let next = remainder[i + 1..].trim_start();
+                if !next.starts_with(['<', '>', '=', '~', '^']) {
+                    return i;
+                }
+            }
+            '}' => return i,
+            // Whitespace may separate a comma from the next comparator.
+            c if c.is_whitespace() && !remainder[..i].trim_end().ends_with(',') => return i,
+            _ => (),
+        }
+    }
+
+    remainder.len()
+}
+
 enum DocumentState {
     Dependencies,
     Dependency(String),
     Other,
 }
 
+impl DocumentState {
+    /// Determine the state from a section header, with the leading `[` already stripped.
+    fn from_header(header: &str) -> Self {
+        let header = header.split(']').next().unwrap_or_default().trim();
+
+        let is_dependency_table = |table: &str| {
+            table == "dependencies"
+                || table.ends_with(".dependencies")
+                || table.ends_with("-dependencies")
+        };
+
+        // This is the case where a dependency is specified over multiple lines, for example:
+        //
+        // ```toml
+        // [dependencies.serde]
+        // version = "1.0.108"
+        //
+        // [target.'cfg(windows)'.dependencies.winapi]
+        // version = "0.3"
+        // ```
+        //
+        // Crate names cannot contain dots, so the last segment is always the name. This must be
+        // checked first, so as not to mistake packages ending in "dependencies" for a section:
+        //
+        // ```toml
+        // [dependencies.crate-ending-in-dependencies]
+        // version = "1"
+        // ```
+        if let Some((table, package)) = header.rsplit_once('.') {
+            if is_dependency_table(table) {
+                return DocumentState::Dependency(package.trim().to_string());
+            }
+        }
+
+        // Covers [dependencies], [build-dependencies], [dev-dependencies],
+        // [target.'cfg(unix)'.dependencies], etc.
+        if is_dependency_table(header) {
+            return DocumentState::Dependencies;
+        }
+
+        // Anything else, such as [package], [lints] or [profile.release.package.serde]
+        // cannot contain dependencies.
+        DocumentState::Other
+    }
+}
+
 #[derive(Debug)]
 enum Line<'a> {
     Start,
@@ -127,7 +703,6 @@ enum Line<'a> {
     Complete {
         name: &'a str,
         start: usize,
-        end: usize,
         version: &'a str,
     },
     Partial {
@@ -139,14 +714,31 @@ enum Line<'a> {
 
 impl<'a> Line<'a> {
     pub fn parse(line: &'a str, line_no: usize) -> Option<Dependency> {
+        let dependency = Self::parse_requirement(line, line_no);
+
+        // A `version` next to `git` only states what the checkout claims to be, so the
+        // crate must not be looked up in the registry.
+        match dependency {
+            Some(Dependency::WithVersion(dependency)) if inline_value(line, "git").is_some() => {
+                git_dependency(line, &dependency.name)
+            }
+            dependency => dependency,
+        }
+    }
+
+    /// Parse the line regardless of its `git` key, keeping the version requirement of
+    /// crates sourced from git.
+    fn parse_requirement(line: &'a str, line_no: usize) -> Option<Dependency> {
         use Line::*;
         let mut state = Start;
 
-        for (i, c) in line.chars().enumerate() {
+        for (i, c) in line.char_indices() {
             state = match state {
                 Complete { .. } | Partial { .. } => break,
                 Start => {
-                    if c.is_alphabetic() {
+                    // Crate names must start with a letter, but we accept digits here too,
+                    // so that invalid names can be reported rather than silently ignored.
+                    if c.is_alphanumeric() {
                         PartialName { start: i }
                     } else {
                         return None;
@@ -195,15 +787,21 @@ impl<'a> Line<'a> {
                     '"' => Complete {
                         name,
                         start,
-                        end: i,
                         version: &line[start..i],
                     },
                     'a'..='z' | 'A'..='Z' => {
                         if first {
-                            Partial {
-                                name,
-                                start,
-                                version: &line[start..i],
+                            match closed_requirement(line, start, i) {
+                                Some(version) => Complete {
+                                    name,
+                                    start,
+                                    version,
+                                },
+                                None => Partial {
+                                    name,
+                                    start,
+                                    version: &line[start..i],
+                                },
                             }
                         } else {
                             VersionSelector {
@@ -213,53 +811,89 @@ impl<'a> Line<'a> {
                             }
                         }
                     }
-                    '0'..='9' | '.' | '*' | '_' | '-' | '<' | '>' | '=' | ',' => VersionSelector {
-                        name,
-                        start,
-                        first: false,
-                    },
+                    '0'..='9' | '.' | '*' | '_' | '-' | '<' | '>' | '=' | ',' | '+' | '^' | '~' => {
+                        VersionSelector {
+                            name,
+                            start,
+                            first: false,
+                        }
+                    }
                     ' ' => VersionSelector {
                         name,
                         start,
                         first: true,
                     },
-                    _ => Partial {
-                        name,
-                        start,
-                        version: &line[start..i],
+                    _ => match closed_requirement(line, start, i) {
+                        Some(version) => Complete {
+                            name,
+                            start,
+                            version,
+                        },
+                        None => Partial {
+                            name,
+                            start,
+                            version: &line[start..i],
+                        },
                     },
                 },
             };
         }
 
+        // Requirements typed without quotes aren't valid TOML, but are common while
+        // editing, so treat them as partial requirements which completions can quote.
+        if let Name { name } = state {
+            if let Some((start, version)) = unquoted_requirement(line) {
+                return Some(Dependency::WithVersion(DependencyWithVersion {
+                    name: name.to_string(),
+                    name_range: Some(name_range(name)),
+                    version: DependencyVersion::Partial {
+                        version: version.to_string(),
+                        range: Range::new(
+                            Position::new(0, start as u32),
+                            Position::new(0, (start + version.len()) as u32),
+                        ),
+                    },
+                    default_features: true,
+                    features: Vec::new(),
+                }));
+            }
+        }
+
         match state {
             Complete {
                 name,
                 version,
                 start,
-                end,
             } => {
-                let version = version.trim();
-                let version = if let Ok(version) = VersionReq::parse(version) {
-                    DependencyVersion::Complete {
-                        version,
-                        range: Range::new(
-                            Position::new(0, start as u32),
-                            Position::new(0, end as u32),
-                        ),
-                    }
-                } else {
-                    DependencyVersion::Partial {
+                // Spaces within the quotes aren't part of the requirement, so leave them
+                // out of the range, lest edits replacing it leave them behind.
+                let trimmed = version.trim_start();
+                let start = start + version.len() - trimmed.len();
+                let version = trimmed.trim_end();
+                let end = start + version.len();
+                let range =
+                    Range::new(Position::new(0, start as u32), Position::new(0, end as u32));
+                // The closing quote is present, so a requirement which doesn't parse
+                // is invalid rather than still being typed, unless it is empty, as
+                // happens when the editor closes the quote automatically.
+                let version = match VersionReq::parse(version) {
+                    Ok(version) => DependencyVersion::Complete { version, range },
+                    Err(_) if version.is_empty() => DependencyVersion::Partial {
+                        version: String::new(),
+                        range,
+                    },
+                    Err(err) => DependencyVersion::Invalid {
                         version: version.to_string(),
-                        range: Range::new(
-                            Position::new(0, start as u32),
-                            Position::new(0, end as u32),
-                        ),
-                    }
+                        error: err.to_string(),
+                        range,
+                    },
                 };
                 Some(Dependency::WithVersion(DependencyWithVersion {
                     name: name.to_string(),
+                    name_range: Some(name_range(name)),
                     version,
+                    default_features: !disables_default_features(line),
+                    features: Vec::new(),
                 }))
             }
             Partial {
@@ -276,15 +910,30 @@ impl<'a> Line<'a> {
                 };
                 Some(Dependency::WithVersion(DependencyWithVersion {
                     name: name.to_string(),
+                    name_range: Some(name_range(name)),
                     version,
+                    default_features: !disables_default_features(line),
+                    features: Vec::new(),
                 }))
             }
-            Name { name, .. } | Struct { name, .. } => Some(Dependency::Other {
-                name: name.to_string(),
-            }),
+            Name { name, .. } | Struct { name, .. } => {
+                if let Some(dependency) = git_dependency(line, name) {
+                    Some(dependency)
+                } else if inherits_workspace(line) {
+                    Some(Dependency::WorkspaceInherited {
+                        name: name.to_string(),
+                        line: line_no as u32,
+                    })
+                } else {
+                    Some(Dependency::Other {
+                        name: name.to_string(),
+                    })
+                }
+            }
             VersionSelector { name, start, .. } => {
                 Some(Dependency::WithVersion(DependencyWithVersion {
                     name: name.to_string(),
+                    name_range: Some(name_range(name)),
                     version: DependencyVersion::Partial {
                         version: line[start..].trim().to_string(),
                         range: Range::new(
@@ -292,6 +941,8 @@ impl<'a> Line<'a> {
                             Position::new(0, line.len() as u32),
                         ),
                     },
+                    default_features: !disables_default_features(line),
+                    features: Vec::new(),
                 }))
             }
             PartialName { start } => Some(Dependency::Partial {
@@ -303,153 +954,793 @@ impl<'a> Line<'a> {
     }
 }
 
-#[derive(Default, Debug, Clone)]
-pub struct ManifestTracker {
-    manifests: Arc<RwLock<HashMap<Url, Vec<Dependency>>>>,
+/// The requirement starting at `start`, if a closing quote follows the character at
+/// `i`, meaning that the requirement, such as `"latest"`, has been typed in full rather
+/// than still being typed. Quotes of later keys, as in `version = "1.2, features = ["`,
+/// don't close the requirement.
+fn closed_requirement(line: &str, start: usize, i: usize) -> Option<&str> {
+    let end = i + line[i..].find('"')?;
+    let version = &line[start..end];
+    (!version.contains(['[', '{', '}']) && !version.contains(" = ")).then_some(version)
 }
 
-impl ManifestTracker {
-    pub async fn update_from_source(&self, url: Url, source: &str) -> Vec<Dependency> {
-        use DocumentState::*;
-        let mut packages = Vec::new();
-
-        // We use this to keep track of our current context within the document,
-        // since we only want to act on dependencies in actual dependency sections,
-        // and not pick up `version = "1.2.3"` as a dependency on a "version" crate
-        // in the middle of the package section.
-        let mut document = DocumentState::Other;
+/// Find a requirement assigned without quotes, e.g. `1.0` in `serde = 1.0`, returning
+/// its offset within the line.
+fn unquoted_requirement(line: &str) -> Option<(usize, &str)> {
+    let (_, value) = line.split_once('=')?;
+    let value = value.trim_start();
+    let start = line.len() - value.len();
 
-        for (i, line) in source.lines().enumerate() {
-            let line = line.trim();
+    let requirement = value.split('#').next()?.trim_end();
+    let unquoted = requirement.starts_with(|c: char| {
+        c.is_ascii_digit() || matches!(c, '<' | '>' | '=' | '~' | '^' | '*')
+    }) && !requirement.contains(['"', '\'', '{', '[']);
 
-            if line.is_empty() {
-                continue;
-            }
+    unquoted.then_some((start, requirement))
+}
 
-            // Detect start of new section.
-            if line.starts_with('[') {
-                if line.starts_with("[dependencies") {
-                    if let Some(package) = line.strip_prefix("[dependencies.") {
-                        // This is the case where a dependency is specified over multiple lines, for example:
-                        //
-                        // ```toml
-                        // [dependencies.serde]
-                        // version = "1.0.108"
-                        // ```
-                        document =
-                            DocumentState::Dependency(package.trim_end_matches(']').to_string());
-                    } else {
-                        // This is just a plain old [dependencies] section
-                        document = DocumentState::Dependencies;
-                    }
-                } else if line.ends_with("dependencies]") {
-                    // Covers [build-dependencies], [dev-dependencies], [target.'cfg(unix)'.dependencies], etc.
-                    // Crucially does *not* break specifying packages ending in "dependencies" in the verbose way
-                    // since that case is covered by the previous if-branch matching on '[dependencies':
-                    //
-                    // ```toml
-                    // [dependencies.crate-ending-in-dependencies]
-                    // version = "1"
-                    // ```
-                    document = DocumentState::Dependencies;
-                } else {
-                    document = DocumentState::Other;
-                }
+/// Parse a dependency sourced from the git repository named by its `git` key, if any.
+fn git_dependency(line: &str, name: &str) -> Option<Dependency> {
+    let (_, url) = inline_value(line, "git")?;
 
-                // Section starts cannot contain version information, so skip the rest of the loop.
-                continue;
-            }
+    Some(match inline_value(line, "tag") {
+        Some((start, tag)) => Dependency::Git {
+            name: name.to_string(),
+            url: url.to_string(),
+            tag: tag.to_string(),
+            range: Range::new(
+                Position::new(0, start as u32),
+                Position::new(0, (start + tag.len()) as u32),
+            ),
+        },
+        None => Dependency::GitSource {
+            name: name.to_string(),
+            url: url.to_string(),
+        },
+    })
+}
 
-            match document {
-                Dependencies => {
-                    // If we're in a generic dependency section, and find a line
-                    // which can be parsed as a versioned dependency, push it as a package.
-                    if let Some(mut dependency) = Line::parse(line, i) {
-                        // Line::parse assumes line 0, modify so we have to fix this manually.
-                        if let Some(version) = dependency.version_mut() {
-                            version.range_mut().start.line = i as u32;
-                            version.range_mut().end.line = i as u32;
-                        }
-                        packages.push(dependency)
-                    }
-                }
-                Dependency(ref name) => {
-                    // We parse the line as a regular dependency, and check if the dependency name is "version"
-                    // This is a hack, but it means we don't have to write custom parsing code for sections like this:
-
-                    // ```toml
-                    // [dependencies.serde]
-                    // version = "1"
-                    // ```
-                    if let Some(mut dependency) = Line::parse(line, i) {
-                        if dependency
-                            .name()
-                            .map(|x| x != "version")
-                            .unwrap_or_default()
-                        {
-                            continue;
-                        } else {
-                            // Rename to the package section, since the dependency is currently
-                            // named "version" because of the Line::parse logic assuming this is
-                            // a regular dependencies section.
-                            if let Some(x) = dependency.name_mut() {
-                                x.clone_from(name)
-                            }
-                        }
-                        // Line::parse assumes line 0, modify so we have to fix this manually.
-                        if let Some(version) = dependency.version_mut() {
-                            version.range_mut().start.line = i as u32;
-                            version.range_mut().end.line = i as u32;
-                        }
-                        packages.push(dependency)
-                    }
-                }
-                // We're either at the start of the document, or in an irrelevant section
-                // such as [package], do nothing.
-                Other => (),
-            };
-        }
+/// Lines are trimmed before being parsed, so names always start at the beginning.
+fn name_range(name: &str) -> Range {
+    Range::new(Position::new(0, 0), Position::new(0, name.len() as u32))
+}
 
-        let mut lock = self.manifests.write().await;
-        lock.insert(url, packages.clone());
+#[derive(Debug, Clone)]
+struct Manifest {
+    source: String,
+    dependencies: Vec<Dependency>,
+    /// Whether the package is never published, i.e. `publish = false`.
+    unpublished: bool,
+    sections: Vec<DependencySection>,
+    settings: ManifestSettings,
+    /// Indices of the dependencies by the lines they have ranges on, so that they can
+    /// be looked up by position without scanning every dependency of large manifests.
+    lines: BTreeMap<u32, Vec<usize>>,
+}
 
-        packages
+impl Manifest {
+    /// Dependencies with ranges on the given line, in document order.
+    fn on_line(&self, line: u32) -> impl Iterator<Item = &Dependency> {
+        self.lines
+            .get(&line)
+            .into_iter()
+            .flatten()
+            .map(|&index| &self.dependencies[index])
     }
+}
 
-    pub async fn get(&self, url: &Url) -> Option<Vec<Dependency>> {
-        let dependencies = {
-            let lock = self.manifests.read().await;
-            lock.get(url).cloned()
-        };
+/// What is being completed at the cursor, see [`ManifestTracker::completion_context`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompletionContext {
+    /// The name of a versioned dependency, along with its range.
+    Name(String, Range),
+    /// A feature of a versioned dependency, along with the range of the feature.
+    Feature(DependencyWithVersion, Range),
+    /// The name of a dependency which is still being typed.
+    Partial(String),
+    /// The version requirement of a dependency.
+    Version(DependencyWithVersion),
+}
 
-        dependencies
+/// A section listing dependencies, such as `[dependencies]` or `[dev-dependencies]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencySection {
+    /// Range of the section header.
+    pub header: Range,
+    /// The last line belonging to the section, before the next header or the end
+    /// of the document.
+    pub last_line: u32,
+}
+
+impl DependencySection {
+    /// Whether the dependency is declared within this section.
+    pub fn contains(&self, dependency: &DependencyWithVersion) -> bool {
+        let line = dependency.version.range().start.line;
+        self.header.start.line < line && line <= self.last_line
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use indoc::indoc;
-    use semver::VersionReq;
-    use tower_lsp::lsp_types::Position;
-    use tower_lsp::lsp_types::Range;
-    use tower_lsp::lsp_types::Url;
+/// Determine the kind of dependency table the given line belongs to, such as
+/// `dependencies`, `dev-dependencies` or `build-dependencies`, regardless of the
+/// target or workspace the table is specific to.
+pub fn dependency_kind(source: &str, line: u32) -> Option<&str> {
+    let header = source
+        .lines()
+        .take(line as usize + 1)
+        .filter_map(|line| line.trim().strip_prefix('['))
+        .last()?;
 
-    use crate::parse::DependencyVersion;
-    use crate::parse::Line;
-    use crate::parse::ManifestTracker;
-    use crate::parse::{Dependency, DependencyWithVersion};
+    let table = match DocumentState::from_header(header) {
+        DocumentState::Dependencies => header.split(']').next()?,
+        DocumentState::Dependency(_) => header.split(']').next()?.rsplit_once('.')?.0,
+        DocumentState::Other => return None,
+    };
 
-    #[tokio::test]
-    async fn detect_plain_version() {
-        let url = Url::parse("file:///test").unwrap();
+    table.rsplit('.').next().map(str::trim)
+}
 
-        let cargo = indoc! {r#"
-            [dependencies]
-            complete_simple_major = "1"
-            complete_simple_minor = "1.2"
-            complete_simple_patch = "1.2.3"
-            complete_simple_range = ">=1, <2"
-            complete = { version = "1.2.3" }
+/// Find the body of the `[package.metadata.crates-lsp]` table of the manifest, or the
+/// `[workspace.metadata.crates-lsp]` table if the former is absent.
+pub fn metadata_table(source: &str) -> Option<String> {
+    ["package", "workspace"].into_iter().find_map(|parent| {
+        let header = format!("{parent}.metadata.crates-lsp");
+
+        let mut lines = source.lines().map(str::trim);
+        lines.find(|line| {
+            line.strip_prefix('[')
+                .and_then(|line| line.split(']').next())
+                .is_some_and(|name| name.trim() == header)
+        })?;
+
+        Some(
+            lines
+                .take_while(|line| !line.starts_with('['))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    })
+}
+
+/// Find the sections listing dependencies, excluding tables dedicated to a single
+/// dependency such as `[dependencies.serde]`.
+pub fn dependency_sections(source: &str) -> Vec<DependencySection> {
+    let mut sections: Vec<DependencySection> = Vec::new();
+    let mut current: Option<DependencySection> = None;
+    let mut last_line = 0;
+
+    for (i, raw_line) in source.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let indent = (raw_line.len() - raw_line.trim_start().len()) as u32;
+            sections.extend(current.take().map(|section| DependencySection {
+                last_line,
+                ..section
+            }));
+
+            if let DocumentState::Dependencies = DocumentState::from_header(header) {
+                current = Some(DependencySection {
+                    header: Range::new(
+                        Position::new(i as u32, indent),
+                        Position::new(i as u32, indent + line.len() as u32),
+                    ),
+                    last_line: i as u32,
+                });
+            }
+        }
+
+        last_line = i as u32;
+    }
+
+    sections.extend(current.map(|section| DependencySection {
+        last_line,
+        ..section
+    }));
+    sections
+}
+
+/// Check whether the `[package]` section of the manifest prevents publishing it,
+/// either through `publish = false` or an empty list of registries.
+pub fn is_unpublished(source: &str) -> bool {
+    let mut in_package = false;
+
+    for line in source.lines() {
+        let line = line.trim();
+
+        if let Some(header) = line.strip_prefix('[') {
+            in_package = header.trim_end_matches(']').trim() == "package";
+            continue;
+        }
+
+        if !in_package {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        if key.trim() == "publish" {
+            let value = value.split('#').next().unwrap_or_default().trim();
+            return value == "false" || value.replace(' ', "") == "[]";
+        }
+    }
+
+    false
+}
+
+/// Parse all dependencies declared within the dependency sections of a manifest.
+/// Dependencies renamed through `package = "..."` are named after the crate they
+/// refer to, rather than their key.
+pub fn parse_manifest(source: &str) -> Vec<Dependency> {
+    parse_dependencies(source, true)
+}
+
+/// Parse all dependencies declared within the dependency sections of a manifest,
+/// naming renamed dependencies after their `package` if `resolve_packages` is set,
+/// and after their key otherwise.
+fn parse_dependencies(source: &str, resolve_packages: bool) -> Vec<Dependency> {
+    let mut packages = Vec::new();
+
+    // We use this to keep track of our current context within the document,
+    // since we only want to act on dependencies in actual dependency sections,
+    // and not pick up `version = "1.2.3"` as a dependency on a "version" crate
+    // in the middle of the package section.
+    let mut document = DocumentState::Other;
+
+    // Within [dependencies.<name>] tables, `default-features` can appear on a
+    // separate line either before or after the version, so keep track of both.
+    let mut table_dependency: Option<usize> = None;
+    let mut table_default_features = true;
+    let mut table_features = Vec::new();
+    // The same goes for `registry`, since crates on other registries can't be looked up.
+    let mut table_other_registry = false;
+    // Likewise for `git`, in which case the version only states what the checkout claims to be.
+    let mut table_git: Option<String> = None;
+    // And for `package`, which names the crate the dependency actually refers to.
+    let mut table_package: Option<String> = None;
+
+    // Feature arrays may span several lines, in which case the following lines are
+    // attributed to the dependency, if any, which opened the array.
+    let mut feature_depth = 0;
+    let mut feature_dependency: Option<usize> = None;
+
+    for (i, raw_line) in source.lines().enumerate() {
+        let line = raw_line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let indent = (raw_line.len() - raw_line.trim_start().len()) as u32;
+
+        // Detect start of new section.
+        if let Some(header) = line.strip_prefix('[') {
+            document = DocumentState::from_header(header);
+            table_dependency = None;
+            table_default_features = true;
+            table_other_registry = false;
+            table_git = None;
+            table_package = None;
+            table_features.clear();
+            feature_depth = 0;
+
+            // Section starts cannot contain version information, so skip the rest of the loop.
+            continue;
+        }
+
+        // Features are always strings, so anything else means the array was never
+        // closed, as happens while it is still being typed.
+        if !line.starts_with(['"', ']', ',', '#']) {
+            feature_depth = 0;
+        }
+
+        if feature_depth > 0 {
+            let mut features = Vec::new();
+            feature_depth = scan_features(raw_line, i as u32, feature_depth, &mut features);
+
+            match feature_dependency.and_then(|index| packages.get_mut(index)) {
+                Some(Dependency::WithVersion(dependency)) => dependency.features.extend(features),
+                Some(_) => (),
+                None => table_features.extend(features),
+            }
+            continue;
+        }
+
+        match document {
+            DocumentState::Dependencies => {
+                // If we're in a generic dependency section, and find a line
+                // which can be parsed as a versioned dependency, push it as a package.
+                if let Some(mut dependency) = Line::parse(line, i) {
+                    dependency.set_position(i as u32, indent);
+
+                    if uses_other_registry(line) {
+                        if let Some(name) = dependency.name() {
+                            dependency = Dependency::Other { name: name.clone() };
+                        }
+                    }
+
+                    let mut features = Vec::new();
+                    feature_depth = scan_features(raw_line, i as u32, 0, &mut features);
+                    feature_dependency = Some(packages.len());
+                    if let Dependency::WithVersion(dependency) = &mut dependency {
+                        dependency.features = features;
+                        if let Some((_, package)) =
+                            inline_value(line, "package").filter(|_| resolve_packages)
+                        {
+                            dependency.name = package.to_string();
+                        }
+                    }
+
+                    packages.push(dependency)
+                }
+            }
+            DocumentState::Dependency(ref name) => {
+                // Within sections like this, only the `version`, `features` and
+                // `default-features` keys are of interest:
+                //
+                // ```toml
+                // [dependencies.serde]
+                // version = "1"
+                // ```
+                //
+                // Partially typed keys have no `=` yet and are skipped as well, since
+                // they are not crate names we could offer completions for.
+                match table_key(line) {
+                    Some("default-features" | "default_features")
+                        if disables_default_features(line) =>
+                    {
+                        table_default_features = false;
+                        if let Some(Dependency::WithVersion(dependency)) =
+                            table_dependency.and_then(|index| packages.get_mut(index))
+                        {
+                            dependency.default_features = false;
+                        }
+                    }
+                    Some("features") => {
+                        let mut features = Vec::new();
+                        feature_depth = scan_features(raw_line, i as u32, 0, &mut features);
+                        feature_dependency = table_dependency;
+
+                        match table_dependency.and_then(|index| packages.get_mut(index)) {
+                            Some(Dependency::WithVersion(dependency)) => {
+                                dependency.features.extend(features)
+                            }
+                            Some(_) => (),
+                            None => table_features.extend(features),
+                        }
+                    }
+                    Some("registry") if uses_other_registry(line) => {
+                        table_other_registry = true;
+                        if let Some(dependency) =
+                            table_dependency.and_then(|index| packages.get_mut(index))
+                        {
+                            *dependency = Dependency::Other { name: name.clone() };
+                        }
+                    }
+                    Some("workspace") if inherits_workspace(line) => {
+                        table_dependency = Some(packages.len());
+                        packages.push(Dependency::WorkspaceInherited {
+                            name: name.clone(),
+                            line: i as u32,
+                        });
+                    }
+                    Some("package") if resolve_packages => {
+                        let Some((_, package)) = inline_value(line, "package") else {
+                            continue;
+                        };
+                        table_package = Some(package.to_string());
+                        if let Some(Dependency::WithVersion(dependency)) =
+                            table_dependency.and_then(|index| packages.get_mut(index))
+                        {
+                            dependency.name = package.to_string();
+                        }
+                    }
+                    Some("git") => {
+                        let Some((_, url)) = inline_value(line, "git") else {
+                            continue;
+                        };
+                        table_git = Some(url.to_string());
+                        if let Some(dependency @ Dependency::WithVersion(_)) =
+                            table_dependency.and_then(|index| packages.get_mut(index))
+                        {
+                            *dependency = Dependency::GitSource {
+                                name: name.clone(),
+                                url: url.to_string(),
+                            };
+                        }
+                    }
+                    Some("version") => {
+                        // The `version` key parses as a dependency on a crate of the same
+                        // name, so rename it to the crate named in the table header.
+                        let Some(mut dependency) = Line::parse(line, i) else {
+                            continue;
+                        };
+                        if table_other_registry {
+                            dependency = Dependency::Other { name: name.clone() };
+                        } else if let Some(url) = &table_git {
+                            dependency = Dependency::GitSource {
+                                name: name.clone(),
+                                url: url.clone(),
+                            };
+                        }
+                        if let Some(x) = dependency.name_mut() {
+                            x.clone_from(name)
+                        }
+                        dependency.set_position(i as u32, indent);
+                        if let Dependency::WithVersion(dependency) = &mut dependency {
+                            if let Some(package) = &table_package {
+                                dependency.name.clone_from(package);
+                            }
+                            // The name is part of the table header rather than this line.
+                            dependency.name_range = None;
+                            dependency.default_features = table_default_features;
+                            dependency.features = std::mem::take(&mut table_features);
+                        }
+                        table_dependency = Some(packages.len());
+                        packages.push(dependency)
+                    }
+                    _ => (),
+                }
+            }
+            // We're either at the start of the document, or in an irrelevant section
+            // such as [package], do nothing.
+            DocumentState::Other => (),
+        };
+    }
+
+    packages
+}
+
+/// The dependencies which have a version requirement, for tests of checks which only
+/// look at those.
+#[cfg(test)]
+pub fn versioned(packages: &[Dependency]) -> Vec<&DependencyWithVersion> {
+    packages
+        .iter()
+        .filter_map(|dependency| match dependency {
+            Dependency::WithVersion(dependency) => Some(dependency),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct ManifestTracker {
+    manifests: Arc<RwLock<HashMap<Url, Manifest>>>,
+    encoding: Arc<RwLock<PositionEncoding>>,
+}
+
+impl ManifestTracker {
+    /// Set the encoding of the ranges of manifests parsed from now on, as negotiated
+    /// with the client.
+    pub async fn set_encoding(&self, encoding: PositionEncoding) {
+        *self.encoding.write().await = encoding;
+    }
+
+    pub async fn encoding(&self) -> PositionEncoding {
+        *self.encoding.read().await
+    }
+
+    pub async fn update_from_source(&self, url: Url, source: &str) -> Vec<Dependency> {
+        let encoding = self.encoding().await;
+        let started = std::time::Instant::now();
+
+        let source_lines: Vec<_> = source.lines().collect();
+        let mut packages = parse_manifest(source);
+        let mut lines: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+        for (index, package) in packages.iter_mut().enumerate() {
+            package.encode_ranges(&source_lines, encoding);
+            for line in package.lines() {
+                lines.entry(line).or_default().push(index);
+            }
+        }
+
+        let sections = dependency_sections(source)
+            .into_iter()
+            .map(|section| {
+                let line = source_lines
+                    .get(section.header.start.line as usize)
+                    .copied()
+                    .unwrap_or_default();
+                DependencySection {
+                    header: encoding.encode_line(line, section.header),
+                    ..section
+                }
+            })
+            .collect();
+
+        tracing::info_span!(
+            target: crate::crates::METRICS_TARGET,
+            "parse",
+            %url,
+            dependencies = packages.len(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+        )
+        .in_scope(|| tracing::debug!(%url, dependencies = packages.len(), "parsed manifest"));
+
+        let mut lock = self.manifests.write().await;
+        lock.insert(
+            url,
+            Manifest {
+                source: source.to_string(),
+                dependencies: packages.clone(),
+                unpublished: is_unpublished(source),
+                sections,
+                settings: ManifestSettings::from_manifest(source),
+                lines,
+            },
+        );
+
+        packages
+    }
+
+    /// Every dependency of the manifest. Lookups by position should use the line index
+    /// through [`ManifestTracker::within`] and friends instead.
+    #[cfg(test)]
+    pub async fn get(&self, url: &Url) -> Option<Vec<Dependency>> {
+        let dependencies = {
+            let lock = self.manifests.read().await;
+            lock.get(url).map(|manifest| manifest.dependencies.clone())
+        };
+
+        dependencies
+    }
+
+    /// Whether the manifest is never published, see [`is_unpublished`].
+    pub async fn is_unpublished(&self, url: &Url) -> bool {
+        let lock = self.manifests.read().await;
+        lock.get(url).is_some_and(|manifest| manifest.unpublished)
+    }
+
+    /// Settings from the manifest's own `[package.metadata.crates-lsp]` table.
+    pub async fn settings(&self, url: &Url) -> ManifestSettings {
+        let lock = self.manifests.read().await;
+        lock.get(url)
+            .map(|manifest| manifest.settings.clone())
+            .unwrap_or_default()
+    }
+
+    pub async fn sections(&self, url: &Url) -> Vec<DependencySection> {
+        let lock = self.manifests.read().await;
+        lock.get(url)
+            .map(|manifest| manifest.sections.clone())
+            .unwrap_or_default()
+    }
+
+    /// Every dependency with a version, across all tracked manifests.
+    pub async fn all_dependencies(&self) -> Vec<DependencyWithVersion> {
+        let lock = self.manifests.read().await;
+        lock.values()
+            .flat_map(|manifest| &manifest.dependencies)
+            .filter_map(|dependency| match dependency {
+                Dependency::WithVersion(dependency) => Some(dependency.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Find all tracked manifests which depend on the given crate, along with their source.
+    pub async fn dependents(&self, crate_name: &str) -> Vec<(Url, String)> {
+        let lock = self.manifests.read().await;
+        lock.iter()
+            .filter(|(_, manifest)| {
+                manifest
+                    .dependencies
+                    .iter()
+                    .any(|dependency| dependency.name().is_some_and(|name| name == crate_name))
+            })
+            .map(|(url, manifest)| (url.clone(), manifest.source.clone()))
+            .collect()
+    }
+
+    /// Every tracked manifest, along with its source.
+    pub async fn sources(&self) -> Vec<(Url, String)> {
+        let lock = self.manifests.read().await;
+        lock.iter()
+            .map(|(url, manifest)| (url.clone(), manifest.source.clone()))
+            .collect()
+    }
+
+    pub async fn source(&self, url: &Url) -> Option<String> {
+        let lock = self.manifests.read().await;
+        lock.get(url).map(|manifest| manifest.source.clone())
+    }
+
+    /// Find the first dependency with ranges on the given line for which `f` returns a value.
+    async fn find_on_line<T>(
+        &self,
+        url: &Url,
+        line: u32,
+        f: impl FnMut(&Dependency) -> Option<T>,
+    ) -> Option<T> {
+        let lock = self.manifests.read().await;
+        let found = lock.get(url)?.on_line(line).find_map(f);
+        found
+    }
+
+    /// Find the dependencies with ranges on any of the lines spanned by the given range,
+    /// in document order.
+    pub async fn within(&self, url: &Url, range: Range) -> Vec<Dependency> {
+        let lock = self.manifests.read().await;
+        let Some(manifest) = lock.get(url) else {
+            return Vec::new();
+        };
+
+        let indices: BTreeSet<usize> = manifest
+            .lines
+            .range(range.start.line..=range.end.line)
+            .flat_map(|(_, indices)| indices.iter().copied())
+            .collect();
+
+        indices
+            .into_iter()
+            .map(|index| manifest.dependencies[index].clone())
+            .collect()
+    }
+
+    /// Find the name and its range of the versioned dependency whose name the given
+    /// cursor position is placed on, if any.
+    pub async fn name_at(&self, url: &Url, cursor: Position) -> Option<(String, Range)> {
+        self.find_on_line(url, cursor.line, |dependency| match dependency {
+            Dependency::WithVersion(DependencyWithVersion {
+                name,
+                name_range: Some(range),
+                ..
+            }) if range.start <= cursor && cursor <= range.end => Some((name.clone(), *range)),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Find the range of the version requirement of the given versioned dependency, if any.
+    pub async fn version_range(&self, url: &Url, crate_name: &str) -> Option<Range> {
+        let lock = self.manifests.read().await;
+        lock.get(url)?
+            .dependencies
+            .iter()
+            .find_map(|dependency| match dependency {
+                Dependency::WithVersion(dep) if dep.name == crate_name => Some(dep.version.range()),
+                _ => None,
+            })
+    }
+
+    /// Find the versioned dependency and the range of the feature name which the given
+    /// cursor position is placed on, if any.
+    pub async fn feature_at(
+        &self,
+        url: &Url,
+        cursor: Position,
+    ) -> Option<(DependencyWithVersion, Range)> {
+        self.find_on_line(url, cursor.line, |dependency| match dependency {
+            Dependency::WithVersion(dep) => {
+                let range = dep
+                    .features
+                    .iter()
+                    .map(|feature| feature.range)
+                    .find(|range| range.start <= cursor && cursor <= range.end)?;
+                Some((dep.clone(), range))
+            }
+            _ => None,
+        })
+        .await
+    }
+
+    /// Find the dependency which the given cursor position is placed on, if any.
+    pub async fn dependency_at(&self, url: &Url, cursor: Position) -> Option<Dependency> {
+        self.find_on_line(url, cursor.line, |dependency| {
+            let on_cursor = match dependency {
+                Dependency::Partial { line, .. } => *line == cursor.line,
+                Dependency::WithVersion(dep) => {
+                    dep.version.range().start.line == cursor.line
+                        && dep.version.range().start.character <= cursor.character
+                        && dep.version.range().end.character >= cursor.character
+                }
+                Dependency::Git { .. }
+                | Dependency::GitSource { .. }
+                | Dependency::WorkspaceInherited { .. }
+                | Dependency::Other { .. } => false,
+            };
+            on_cursor.then(|| dependency.clone())
+        })
+        .await
+    }
+
+    /// Determine what a completion at the cursor would complete, if anything, given
+    /// the character which triggered it.
+    pub async fn completion_context(
+        &self,
+        url: &Url,
+        cursor: Position,
+        trigger: Option<&str>,
+    ) -> Option<CompletionContext> {
+        // A `.` only continues a version such as `"1.`, elsewhere it's part of a dotted
+        // key like `serde.workspace`, a table header like `[dependencies.serde]` or a
+        // path, none of which are completed.
+        if trigger == Some(".") {
+            return match self.dependency_at(url, cursor).await? {
+                Dependency::WithVersion(dependency) => Some(CompletionContext::Version(dependency)),
+                _ => None,
+            };
+        }
+
+        if let Some((name, range)) = self.name_at(url, cursor).await {
+            return Some(CompletionContext::Name(name, range));
+        }
+
+        if let Some((dependency, range)) = self.feature_at(url, cursor).await {
+            return Some(CompletionContext::Feature(dependency, range));
+        }
+
+        match self.dependency_at(url, cursor).await? {
+            Dependency::Partial { name, .. } => Some(CompletionContext::Partial(name)),
+            Dependency::WithVersion(dependency) => Some(CompletionContext::Version(dependency)),
+            Dependency::Git { .. }
+            | Dependency::GitSource { .. }
+            | Dependency::WorkspaceInherited { .. }
+            | Dependency::Other { .. } => None,
+        }
+    }
+
+    /// Find the name of the dependency declared on the given line, if any.
+    pub async fn name_on_line(&self, url: &Url, line: u32) -> Option<String> {
+        self.find_on_line(url, line, |dependency| {
+            let dependency_line = match dependency {
+                Dependency::WithVersion(dep) => dep.version.range().start.line,
+                Dependency::Git { range, .. } => range.start.line,
+                Dependency::WorkspaceInherited { line, .. } => *line,
+                Dependency::Partial { .. }
+                | Dependency::GitSource { .. }
+                | Dependency::Other { .. } => return None,
+            };
+            (dependency_line == line).then(|| dependency.name().cloned())?
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use proptest::proptest;
+    use semver::{Version, VersionReq};
+    use tower_lsp::lsp_types::Position;
+    use tower_lsp::lsp_types::Range;
+    use tower_lsp::lsp_types::Url;
+
+    use crate::encoding::PositionEncoding;
+    use crate::parse::dependency_kind;
+    use crate::parse::dependency_sections;
+    use crate::parse::is_unpublished;
+    use crate::parse::is_valid_crate_name;
+    use crate::parse::is_workspace_root;
+    use crate::parse::metadata_table;
+    use crate::parse::parse_manifest;
+    use crate::parse::patched_dependencies;
+    use crate::parse::quote_edits;
+    use crate::parse::text_at;
+    use crate::parse::version_edit;
+    use crate::parse::workspace_dependencies;
+    use crate::parse::DependencySection;
+    use crate::parse::DependencyVersion;
+    use crate::parse::Line;
+    use crate::parse::ManifestTracker;
+    use crate::parse::{CompletionContext, Dependency, DependencyWithVersion};
+
+    #[tokio::test]
+    async fn detect_plain_version() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            complete_simple_major = "1"
+            complete_simple_minor = "1.2"
+            complete_simple_patch = "1.2.3"
+            complete_simple_range = ">=1, <2"
+            complete = { version = "1.2.3" }
             partial_simple = "1.2
             partial_simple_pre = "1.2.0-alpha.1"
             partial_struct1 = { version = "1.20 }
@@ -477,7 +1768,9 @@ mod tests {
         assert_eq!(line.name, name);
 
         match line.version {
-            DependencyVersion::Partial { .. } => panic!("expected complete version selector"),
+            DependencyVersion::Partial { .. } | DependencyVersion::Invalid { .. } => {
+                panic!("expected complete version selector")
+            }
             DependencyVersion::Complete { version, .. } => {
                 assert_eq!(version, expected_version)
             }
@@ -492,13 +1785,56 @@ mod tests {
         assert_eq!(line.name, name);
 
         match line.version {
-            DependencyVersion::Complete { .. } => panic!("expected partial version selector"),
+            DependencyVersion::Complete { .. } | DependencyVersion::Invalid { .. } => {
+                panic!("expected partial version selector")
+            }
             DependencyVersion::Partial { version, .. } => {
                 assert_eq!(version.as_str(), expected_version)
             }
         }
     }
 
+    fn matches_invalid(line: &str, name: &str, expected_version: &str) {
+        let Some(Dependency::WithVersion(line)) = Line::parse(line, 0) else {
+            panic!("expected dependency with version")
+        };
+        assert_eq!(line.name, name);
+
+        match line.version {
+            DependencyVersion::Invalid { version, error, .. } => {
+                assert_eq!(version.as_str(), expected_version);
+                assert!(!error.is_empty());
+            }
+            _ => panic!("expected invalid version selector"),
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        matches_invalid(r#"foo = "~>1.0""#, "foo", "~>1.0");
+        matches_invalid(r#"foo = "1.2.3.4""#, "foo", "1.2.3.4");
+        matches_invalid(r#"foo = { version = "~>1.0" }"#, "foo", "~>1.0");
+        matches_invalid(r#"foo = "latest""#, "foo", "latest");
+        matches_invalid(r#"foo = "v1.2""#, "foo", "v1.2");
+        matches_invalid(
+            r#"foo = { version = "v1.2", features = ["std"] }"#,
+            "foo",
+            "v1.2",
+        );
+
+        // Wildcards are accepted by cargo.
+        matches_complete(r#"foo = "1.2.x""#, "foo", "1.2.x");
+        matches_complete(r#"foo = "1.2.*""#, "foo", "1.2.*");
+
+        // Unterminated requirements are still being typed.
+        matches_partial(r#"foo = "~>1.0"#, "foo", "~>1.0");
+        matches_partial(
+            r#"foo = { version = "1.2, features = ["std"] }"#,
+            "foo",
+            "1.2",
+        );
+    }
+
     #[test]
     fn parse_complete() {
         matches_complete("complete = \"1.2.3\"", "complete", "1.2.3");
@@ -507,6 +1843,22 @@ mod tests {
         matches_complete("complete = \"=1.2\"", "complete", "=1.2");
         matches_complete("complete = \"1\"", "complete", "1");
         matches_complete("complete = \"=1\"", "complete", "=1");
+        matches_complete("complete = \"^1.2\"", "complete", "^1.2");
+        matches_complete("complete = \"~1.2.3\"", "complete", "~1.2.3");
+    }
+
+    #[test]
+    fn parse_build_metadata() {
+        matches_complete("foo = \"=1.2.3+build.5\"", "foo", "=1.2.3+build.5");
+
+        let Some(Dependency::WithVersion(dependency)) = Line::parse("foo = \"=1.2.3+build.5\"", 0)
+        else {
+            panic!("expected complete version selector")
+        };
+        assert_eq!(
+            dependency.version.range(),
+            Range::new(Position::new(0, 7), Position::new(0, 21))
+        );
     }
 
     #[test]
@@ -534,58 +1886,1560 @@ mod tests {
         matches_partial("partial \"1, features = [", "partial", "1");
     }
 
-    #[tokio::test]
-    async fn parse_independent_dependency_section() {
-        let url = Url::parse("file:///test").unwrap();
+    #[test]
+    fn parse_git_tag() {
+        assert_eq!(
+            Line::parse(
+                r#"foo = { git = "https://github.com/org/foo", tag = "v1.2.0" }"#,
+                0
+            ),
+            Some(Dependency::Git {
+                name: "foo".to_string(),
+                url: "https://github.com/org/foo".to_string(),
+                tag: "v1.2.0".to_string(),
+                range: Range::new(Position::new(0, 51), Position::new(0, 57)),
+            })
+        );
 
-        let cargo = indoc! {r#"
-            [dependencies]
-            log = "1"
-            
-            [dependencies.serde]
-            version = "1"
-            
-            [dependencies.tokio]
-            version = "1"
-        "#};
+        assert_eq!(
+            Line::parse(
+                r#"foo = { git = "https://github.com/org/foo", version = "1.2", tag = "v1.2.0" }"#,
+                0
+            ),
+            Some(Dependency::Git {
+                name: "foo".to_string(),
+                url: "https://github.com/org/foo".to_string(),
+                tag: "v1.2.0".to_string(),
+                range: Range::new(Position::new(0, 68), Position::new(0, 74)),
+            })
+        );
+    }
 
-        let manifests = ManifestTracker::default();
-        manifests.update_from_source(url.clone(), cargo).await;
+    #[test]
+    fn parse_git_source() {
+        let git_source = Some(Dependency::GitSource {
+            name: "foo".to_string(),
+            url: "https://github.com/org/foo".to_string(),
+        });
 
         assert_eq!(
-            manifests.get(&url).await.unwrap(),
-            vec![
-                Dependency::WithVersion(DependencyWithVersion {
-                    name: "log".to_string(),
-                    version: DependencyVersion::Complete {
-                        range: Range {
-                            start: Position::new(1, 7),
-                            end: Position::new(1, 8)
-                        },
-                        version: VersionReq::parse("1").unwrap()
-                    }
-                }),
-                Dependency::WithVersion(DependencyWithVersion {
+            Line::parse(r#"foo = { git = "https://github.com/org/foo" }"#, 0),
+            git_source
+        );
+        assert_eq!(
+            Line::parse(
+                r#"foo = { git = "https://github.com/org/foo", branch = "main" }"#,
+                0
+            ),
+            git_source
+        );
+        assert_eq!(
+            Line::parse(
+                r#"foo = { git = "https://github.com/org/foo", version = "1.0" }"#,
+                0
+            ),
+            git_source
+        );
+        assert_eq!(
+            Line::parse(
+                r#"foo = { version = "1.0", git = "https://github.com/org/foo" }"#,
+                0
+            ),
+            git_source
+        );
+
+        // Regardless of whether `git` is given before or after the version.
+        for cargo in [
+            indoc! {r#"
+                [dependencies.foo]
+                git = "https://github.com/org/foo"
+                version = "1.0"
+            "#},
+            indoc! {r#"
+                [dependencies.foo]
+                version = "1.0"
+                git = "https://github.com/org/foo"
+            "#},
+        ] {
+            assert_eq!(parse_manifest(cargo), vec![git_source.clone().unwrap()]);
+        }
+    }
+
+    #[test]
+    fn parse_workspace_inherited() {
+        assert_eq!(
+            Line::parse(r#"serde = { workspace = true, features = ["derive"] }"#, 3),
+            Some(Dependency::WorkspaceInherited {
+                name: "serde".to_string(),
+                line: 3,
+            })
+        );
+
+        assert_eq!(
+            Line::parse(r#"serde = { features = ["derive"], workspace = true }"#, 3),
+            Some(Dependency::WorkspaceInherited {
+                name: "serde".to_string(),
+                line: 3,
+            })
+        );
+
+        assert_eq!(
+            Line::parse("serde.workspace = true", 3),
+            Some(Dependency::WorkspaceInherited {
+                name: "serde".to_string(),
+                line: 3,
+            })
+        );
+
+        assert_eq!(
+            Line::parse(r#"serde = { workspace = false, features = ["derive"] }"#, 3),
+            Some(Dependency::Other {
+                name: "serde".to_string(),
+            })
+        );
+
+        let cargo = indoc! {r#"
+            [dependencies.serde]
+            features = ["derive"]
+            workspace = true
+
+            [dependencies.tokio]
+            workspace = false
+        "#};
+        assert_eq!(
+            parse_manifest(cargo),
+            vec![Dependency::WorkspaceInherited {
+                name: "serde".to_string(),
+                line: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_workspace_dependencies() {
+        let cargo = indoc! {r#"
+            [workspace]
+            members = ["crates/*"]
+
+            [workspace.dependencies]
+            serde = { version = "1.0.100", features = ["derive"] }
+
+            [workspace.dependencies.tokio]
+            version = "1.40"
+
+            [workspace.dependencies.http02]
+            package = "http"
+            version = "0.2"
+
+            [dependencies]
+            anyhow = "1"
+        "#};
+
+        assert!(is_workspace_root(cargo));
+        assert!(!is_workspace_root("[package]\nname = \"foo\"\n"));
+
+        // Members inherit renamed dependencies by their key.
+        let dependencies = workspace_dependencies(cargo);
+        let names: Vec<_> = dependencies
+            .iter()
+            .map(|dependency| dependency.name.as_str())
+            .collect();
+        assert_eq!(names, ["serde", "tokio", "http02"]);
+        assert_eq!(dependencies[0].version.range().start.line, 4);
+    }
+
+    #[test]
+    fn parse_patched_dependencies() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+
+            [patch.crates-io]
+            serde = { git = "https://github.com/serde-rs/serde", version = "1.0.200" }
+            log = { git = "https://github.com/rust-lang/log" }
+
+            [patch.crates-io.tokio]
+            path = "../tokio"
+            version = "1.41"
+
+            [patch."https://github.com/example/repo"]
+            anyhow = { path = "../anyhow", version = "1" }
+        "#};
+
+        let patches = patched_dependencies(cargo);
+        let names: Vec<_> = patches
+            .iter()
+            .map(|dependency| (dependency.name.as_str(), dependency.version.range().start))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("serde", Position::new(4, 64)),
+                ("tokio", Position::new(9, 11))
+            ]
+        );
+        assert_eq!(patches[1].name_range, None);
+
+        // Patches aren't dependencies of the manifest itself.
+        assert_eq!(parse_manifest(cargo).len(), 1);
+    }
+
+    #[test]
+    fn parse_leading_digit() {
+        matches_complete("4serde = \"1\"", "4serde", "1");
+        assert_eq!(Line::parse("# serde = \"1\"", 0), None);
+    }
+
+    #[test]
+    fn parse_compact_whitespace() {
+        matches_complete(r#"foo="1""#, "foo", "1");
+        matches_complete(r#"foo ="1""#, "foo", "1");
+        matches_complete(r#"foo= "1""#, "foo", "1");
+        matches_complete(r#"foo	=	"1""#, "foo", "1");
+        matches_complete(r#"foo={version="1"}"#, "foo", "1");
+        matches_complete(r#"foo = "1" # trailing"#, "foo", "1");
+
+        let cargo = "[dependencies]\n\tfoo=\"1\"\n  \tbar = { version = \"2\" }\n\n[dependencies.baz]\n\tversion\t=\t\"3\"\n";
+
+        let versions: Vec<_> = parse_manifest(cargo)
+            .into_iter()
+            .map(|dependency| {
+                let Dependency::WithVersion(dependency) = dependency else {
+                    panic!("expected dependency with version");
+                };
+                (dependency.name, dependency.version.range())
+            })
+            .collect();
+
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        assert_eq!(
+            versions,
+            vec![
+                ("foo".to_string(), range(1, 6, 7)),
+                ("bar".to_string(), range(2, 22, 23)),
+                ("baz".to_string(), range(5, 12, 13)),
+            ]
+        );
+    }
+
+    #[test]
+    fn valid_crate_names() {
+        assert!(is_valid_crate_name("serde"));
+        assert!(is_valid_crate_name("serde_json"));
+        assert!(is_valid_crate_name("tower-lsp"));
+        assert!(is_valid_crate_name("x25519-dalek"));
+
+        assert!(!is_valid_crate_name(""));
+        assert!(!is_valid_crate_name("4serde"));
+        assert!(!is_valid_crate_name("_serde"));
+        assert!(!is_valid_crate_name("-serde"));
+        assert!(!is_valid_crate_name("sérde"));
+    }
+
+    fn complete_version(line: &str, version: &str) -> String {
+        let Some(Dependency::WithVersion(dependency)) = Line::parse(line, 0) else {
+            panic!("expected dependency with version");
+        };
+
+        let range = dependency.version.range();
+        complete_version_at(line, range.end.character, version)
+    }
+
+    fn complete_version_at(line: &str, cursor: u32, version: &str) -> String {
+        let Some(Dependency::WithVersion(dependency)) = Line::parse(line, 0) else {
+            panic!("expected dependency with version");
+        };
+
+        let version = semver::Version::parse(version).unwrap();
+        let mut edit = version_edit(
+            line,
+            dependency.version.range(),
+            Position::new(0, cursor),
+            &version,
+        )
+        .unwrap();
+
+        let mut edits =
+            quote_edits(line, dependency.version.range(), &mut edit).unwrap_or_default();
+        edits.push(edit);
+
+        // Apply the rightmost edit first, so earlier offsets remain valid.
+        edits.sort_by_key(|edit| {
+            std::cmp::Reverse((edit.range.start.character, edit.range.end.character))
+        });
+        let mut line = line.to_string();
+        for edit in edits {
+            let start = edit.range.start.character as usize;
+            let end = edit.range.end.character as usize;
+            line.replace_range(start..end, &edit.new_text);
+        }
+        line
+    }
+
+    #[test]
+    fn complete_version_without_quotes() {
+        matches_partial("serde = 1", "serde", "1");
+        matches_partial("serde = ^1. # pinned", "serde", "^1.");
+
+        assert_eq!(
+            complete_version("serde = 1", "1.0.100"),
+            r#"serde = "1.0.100""#
+        );
+        assert_eq!(
+            complete_version("serde = ^1. # pinned", "1.0.100"),
+            r#"serde = "^1.0.100" # pinned"#
+        );
+        assert_eq!(
+            complete_version_at("foo = >=1., <2", 10, "1.40.0"),
+            r#"foo = ">=1.40.0, <2""#
+        );
+
+        // Other unquoted values aren't requirements.
+        assert!(matches!(
+            Line::parse("serde.workspace = true", 0),
+            Some(Dependency::WorkspaceInherited { .. })
+        ));
+    }
+
+    #[test]
+    fn complete_version_comparator_list() {
+        assert_eq!(
+            complete_version_at(r#"foo = ">=1.2, <""#, 15, "1.40.0"),
+            r#"foo = ">=1.2, <1.40.0""#
+        );
+        assert_eq!(
+            complete_version_at(r#"foo = ">=1.2, <"#, 15, "1.40.0"),
+            r#"foo = ">=1.2, <1.40.0""#
+        );
+        assert_eq!(
+            complete_version_at(r#"foo = ">=1., <2""#, 10, "1.40.0"),
+            r#"foo = ">=1.40.0, <2""#
+        );
+        assert_eq!(
+            complete_version_at(
+                r#"foo = { version = ">=1.2, <", features = [] }"#,
+                27,
+                "1.40.0"
+            ),
+            r#"foo = { version = ">=1.2, <1.40.0", features = [] }"#
+        );
+    }
+
+    #[test]
+    fn complete_version_quotes() {
+        assert_eq!(
+            complete_version(r#"serde = "1."#, "1.0.100"),
+            r#"serde = "1.0.100""#
+        );
+        assert_eq!(
+            complete_version(r#"serde = "1.""#, "1.0.100"),
+            r#"serde = "1.0.100""#
+        );
+        assert_eq!(
+            complete_version(r#"serde = """#, "1.0.100"),
+            r#"serde = "1.0.100""#
+        );
+        assert_eq!(
+            complete_version(r#"serde = "^1.""#, "1.0.100"),
+            r#"serde = "^1.0.100""#
+        );
+        assert_eq!(
+            complete_version(
+                r#"serde = { version = "1. features = ["derive"] }"#,
+                "1.0.100"
+            ),
+            r#"serde = { version = "1.0.100" features = ["derive"] }"#
+        );
+        assert_eq!(
+            complete_version(
+                r#"serde = { version = "1.", features = ["derive"] }"#,
+                "1.0.100"
+            ),
+            r#"serde = { version = "1.0.100", features = ["derive"] }"#
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_independent_dependency_section() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            log = "1"
+            
+            [dependencies.serde]
+            version = "1"
+            
+            [dependencies.tokio]
+            version = "1"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        assert_eq!(
+            manifests.get(&url).await.unwrap(),
+            vec![
+                Dependency::WithVersion(DependencyWithVersion {
+                    name: "log".to_string(),
+                    name_range: Some(Range::new(Position::new(1, 0), Position::new(1, 3))),
+                    version: DependencyVersion::Complete {
+                        range: Range {
+                            start: Position::new(1, 7),
+                            end: Position::new(1, 8)
+                        },
+                        version: VersionReq::parse("1").unwrap()
+                    },
+                    default_features: true,
+                    features: Vec::new()
+                }),
+                Dependency::WithVersion(DependencyWithVersion {
                     name: "serde".to_string(),
+                    name_range: None,
                     version: DependencyVersion::Complete {
                         range: Range {
                             start: Position::new(4, 11),
                             end: Position::new(4, 12)
                         },
                         version: VersionReq::parse("1").unwrap()
-                    }
+                    },
+                    default_features: true,
+                    features: Vec::new()
                 }),
                 Dependency::WithVersion(DependencyWithVersion {
                     name: "tokio".to_string(),
+                    name_range: None,
                     version: DependencyVersion::Complete {
                         range: Range {
                             start: Position::new(7, 11),
                             end: Position::new(7, 12)
                         },
                         version: VersionReq::parse("1").unwrap()
-                    }
+                    },
+                    default_features: true,
+                    features: Vec::new()
+                })
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_repeated_dependency_sections() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            log = "1"
+
+            [package]
+            version = "0.1.0"
+
+            [dependencies]
+            serde = "1"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        assert_eq!(
+            manifests.get(&url).await.unwrap(),
+            vec![
+                Dependency::WithVersion(DependencyWithVersion {
+                    name: "log".to_string(),
+                    name_range: Some(Range::new(Position::new(1, 0), Position::new(1, 3))),
+                    version: DependencyVersion::Complete {
+                        range: Range {
+                            start: Position::new(1, 7),
+                            end: Position::new(1, 8)
+                        },
+                        version: VersionReq::parse("1").unwrap()
+                    },
+                    default_features: true,
+                    features: Vec::new()
+                }),
+                Dependency::WithVersion(DependencyWithVersion {
+                    name: "serde".to_string(),
+                    name_range: Some(Range::new(Position::new(7, 0), Position::new(7, 5))),
+                    version: DependencyVersion::Complete {
+                        range: Range {
+                            start: Position::new(7, 9),
+                            end: Position::new(7, 10)
+                        },
+                        version: VersionReq::parse("1").unwrap()
+                    },
+                    default_features: true,
+                    features: Vec::new()
                 })
             ]
         );
     }
+
+    #[test]
+    fn parse_line_with_multibyte_characters() {
+        // Ranges are byte offsets, so characters spanning several bytes before the
+        // requirement shift it by their length in bytes rather than one.
+        let version_range = |line: &str| match Line::parse(line, 0) {
+            Some(Dependency::WithVersion(dependency)) => dependency.version.range(),
+            other => panic!("expected dependency with version, got {other:?}"),
+        };
+
+        let line = r#"foo = { path = "../føø", version = "1.0" }"#;
+        let range = version_range(line);
+        assert_eq!((range.start.character, range.end.character), (38, 41));
+        assert_eq!(&line[38..41], "1.0");
+
+        let line = r#"søren = "1""#;
+        let range = version_range(line);
+        assert_eq!((range.start.character, range.end.character), (10, 11));
+        assert_eq!(&line[10..11], "1");
+    }
+
+    #[tokio::test]
+    async fn complete_version_in_dependency_table() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies.serde]
+            vers
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        // A partially typed key must not be mistaken for a crate name.
+        assert_eq!(
+            manifests.dependency_at(&url, Position::new(1, 4)).await,
+            None
+        );
+
+        let cargo = indoc! {r#"
+            [dependencies.serde]
+            version = "
+        "#};
+
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        assert_eq!(
+            manifests.dependency_at(&url, Position::new(1, 11)).await,
+            Some(Dependency::WithVersion(DependencyWithVersion {
+                name: "serde".to_string(),
+                name_range: None,
+                version: DependencyVersion::Partial {
+                    range: Range {
+                        start: Position::new(1, 11),
+                        end: Position::new(1, 11)
+                    },
+                    version: String::new()
+                },
+                default_features: true,
+                features: Vec::new()
+            }))
+        );
+
+        let cargo = indoc! {r#"
+            [dependencies.serde]
+            version = ""
+        "#};
+
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        assert_eq!(
+            manifests.dependency_at(&url, Position::new(1, 11)).await,
+            Some(Dependency::WithVersion(DependencyWithVersion {
+                name: "serde".to_string(),
+                name_range: None,
+                version: DependencyVersion::Partial {
+                    range: Range {
+                        start: Position::new(1, 11),
+                        end: Position::new(1, 11)
+                    },
+                    version: String::new()
+                },
+                default_features: true,
+                features: Vec::new()
+            }))
+        );
+    }
+
+    /// Every range produced for a dependency must lie within the parsed line.
+    fn assert_ranges_within(line: &str, line_no: usize) {
+        let ranges = match Line::parse(line, line_no) {
+            Some(Dependency::WithVersion(dependency)) => {
+                vec![Some(dependency.version.range()), dependency.name_range]
+            }
+            Some(Dependency::Git { range, .. }) => vec![Some(range)],
+            _ => Vec::new(),
+        };
+
+        for range in ranges.into_iter().flatten() {
+            assert!(range.start <= range.end, "{range:?} of {line:?}");
+            assert!(
+                range.end.character as usize <= line.len(),
+                "{range:?} of {line:?}"
+            );
+            assert!(line.is_char_boundary(range.start.character as usize));
+            assert!(line.is_char_boundary(range.end.character as usize));
+        }
+    }
+
+    /// Every range produced for the dependencies of a manifest must lie within the
+    /// line it refers to, including those of features spanning several lines.
+    fn assert_manifest_ranges_within(source: &str) {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut dependencies = parse_manifest(source);
+        dependencies.extend(
+            patched_dependencies(source)
+                .into_iter()
+                .chain(workspace_dependencies(source))
+                .map(Dependency::WithVersion),
+        );
+        dependency_sections(source);
+
+        for dependency in dependencies {
+            let ranges = match dependency {
+                Dependency::WithVersion(dependency) => dependency
+                    .features
+                    .iter()
+                    .map(|feature| feature.range)
+                    .chain(dependency.name_range)
+                    .chain([dependency.version.range()])
+                    .collect(),
+                Dependency::Git { range, .. } => vec![range],
+                _ => Vec::new(),
+            };
+
+            for range in ranges {
+                let line = lines[range.start.line as usize];
+                assert_eq!(range.start.line, range.end.line, "{range:?} of {line:?}");
+                assert!(range.start <= range.end, "{range:?} of {line:?}");
+                assert!(
+                    line.get(range.start.character as usize..range.end.character as usize)
+                        .is_some(),
+                    "{range:?} of {line:?}"
+                );
+            }
+        }
+    }
+
+    const FIXTURES: [&str; 4] = [
+        include_str!("../tests/fixtures/multiline_features.toml"),
+        include_str!("../tests/fixtures/partial_edits.toml"),
+        include_str!("../tests/fixtures/requirements.toml"),
+        include_str!("../tests/fixtures/table_form.toml"),
+    ];
+
+    proptest! {
+        #[test]
+        fn parse_arbitrary_line(line in ".*", line_no in 0usize..1000) {
+            assert_ranges_within(&line, line_no);
+        }
+
+        #[test]
+        fn parse_truncated_manifest(
+            fixture in proptest::sample::select(&FIXTURES[..]),
+            cut in proptest::arbitrary::any::<proptest::sample::Index>(),
+        ) {
+            // Cut at any character boundary, as if the manifest was still being typed.
+            let boundaries: Vec<usize> = fixture.char_indices().map(|(i, _)| i).collect();
+            let cut = boundaries[cut.index(boundaries.len())];
+            assert_manifest_ranges_within(&fixture[..cut]);
+        }
+
+        #[test]
+        fn parse_arbitrary_manifest(
+            lines in proptest::collection::vec(
+                r#"\[(dependencies|patch\.crates-io|workspace\.dependencies)(\.[a-zé]{0,3})?\]?|[a-zé_-]{0,3} ?=? ?(\{ ?)?("[^"\n]{0,4}"?|\[ ?"?[a-zé]{0,2}"?,?)?|[^\n]{0,12}"#,
+                0..10,
+            ),
+        ) {
+            assert_manifest_ranges_within(&lines.join("\n"));
+        }
+
+        #[test]
+        fn edit_arbitrary_range(
+            line in r#"[a-zé ]{0,4}=? ?"?[ \d.,<>=~^é-]{0,10}"?"#,
+            start in 0u32..20,
+            end in 0u32..20,
+            cursor in 0u32..20,
+        ) {
+            let version = Version::new(1, 2, 3);
+            let range = Range::new(Position::new(0, start), Position::new(0, end));
+            text_at(&line, range);
+            if let Some(mut edit) = version_edit(&line, range, Position::new(0, cursor), &version) {
+                quote_edits(&line, range, &mut edit);
+            }
+        }
+
+        #[test]
+        fn parse_realistic_line(
+            name in "[a-zA-Z0-9_-]{0,12}",
+            separator in r"[ =]{0,3}",
+            value in r#"[{}" =.,<>*~^+a-z0-9-]{0,30}"#,
+        ) {
+            assert_ranges_within(&format!("{name}{separator}{value}"), 0);
+        }
+
+        #[test]
+        fn parse_realistic_inline_table(
+            name in "[a-z][a-z0-9_-]{0,12}",
+            keys in proptest::collection::vec(
+                r#"(version|git|tag|workspace|features|default-features) ?= ?("[^"]{0,8}"?|true|false|\[\])"#,
+                0..4,
+            ),
+        ) {
+            assert_ranges_within(&format!("{name} = {{ {} }}", keys.join(", ")), 0);
+        }
+    }
+
+    #[test]
+    fn parse_publish_false() {
+        assert!(is_unpublished(
+            "[package]\nname = \"foo\"\npublish = false\n"
+        ));
+        assert!(is_unpublished("[package]\npublish = [ ]\n"));
+        assert!(!is_unpublished("[package]\npublish = [\"internal\"]\n"));
+        assert!(!is_unpublished("[package]\nname = \"foo\"\n"));
+        assert!(!is_unpublished("[workspace.package]\npublish = false\n"));
+    }
+
+    #[tokio::test]
+    async fn non_file_urls() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+            tokio = { workspace = true }
+        "#};
+
+        let manifests = ManifestTracker::default();
+        for url in [
+            "untitled:Untitled-1",
+            "vscode-remote://ssh-remote+host/home/user/project/Cargo.toml",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(
+                manifests.update_from_source(url.clone(), cargo).await.len(),
+                2
+            );
+            assert_eq!(
+                manifests.version_range(&url, "serde").await,
+                Some(Range::new(Position::new(1, 9), Position::new(1, 10)))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn name_at_cursor() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+
+            [dependencies.tokio]
+            version = "1"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        let serde = Some((
+            "serde".to_string(),
+            Range::new(Position::new(1, 0), Position::new(1, 5)),
+        ));
+        assert_eq!(manifests.name_at(&url, Position::new(1, 4)).await, serde);
+        assert_eq!(manifests.name_at(&url, Position::new(1, 5)).await, serde);
+        assert_eq!(manifests.name_at(&url, Position::new(1, 9)).await, None);
+
+        // Names in table headers aren't part of the dependency's line.
+        assert_eq!(manifests.name_at(&url, Position::new(4, 3)).await, None);
+    }
+
+    #[tokio::test]
+    async fn dot_only_completes_versions() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1."
+            tokio.workspace = true
+            anyhow.
+            rand.version = "0.8"
+
+            [dependencies.regex]
+            version = "1"
+
+            [dev-dependencies]
+            log = 0
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        let (manifests, url) = (&manifests, &url);
+        let context = move |line, character, trigger| async move {
+            manifests
+                .completion_context(url, Position::new(line, character), trigger)
+                .await
+                .map(|context| match context {
+                    CompletionContext::Name(..) => "name",
+                    CompletionContext::Feature(..) => "feature",
+                    CompletionContext::Partial(..) => "partial",
+                    CompletionContext::Version(..) => "version",
+                })
+        };
+
+        assert_eq!(context(1, 11, Some(".")).await, Some("version"));
+        assert_eq!(context(2, 6, Some(".")).await, None);
+        assert_eq!(context(3, 7, Some(".")).await, None);
+        assert_eq!(context(6, 14, Some(".")).await, None);
+
+        // The name of a dotted key is only completed when not triggered by the dot.
+        assert_eq!(context(4, 5, Some(".")).await, None);
+        assert_eq!(context(4, 4, None).await, Some("name"));
+        assert_eq!(context(4, 17, Some(".")).await, Some("version"));
+        assert_eq!(context(10, 7, None).await, Some("version"));
+    }
+
+    #[tokio::test]
+    async fn name_on_line() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+            anyhow = { workspace = true }
+
+            [dependencies.tokio]
+            version = "1"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        let name = |line| manifests.name_on_line(&url, line);
+        assert_eq!(name(1).await.as_deref(), Some("serde"));
+        assert_eq!(name(2).await.as_deref(), Some("anyhow"));
+        assert_eq!(name(3).await, None);
+        assert_eq!(name(5).await.as_deref(), Some("tokio"));
+    }
+
+    #[tokio::test]
+    async fn feature_at_cursor() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = { version = "1.0", features = ["derive", "rc"] }
+
+            [dependencies.tokio]
+            version = "1"
+            features = [
+                "rt",
+            ]
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        let (manifests, url) = (&manifests, &url);
+        let feature = move |line, character| async move {
+            manifests
+                .feature_at(url, Position::new(line, character))
+                .await
+                .map(|(dependency, range)| (dependency.name, range.start.character))
+        };
+        assert_eq!(feature(1, 42).await, Some(("serde".to_string(), 40)));
+        assert_eq!(feature(1, 52).await, Some(("serde".to_string(), 50)));
+        assert_eq!(feature(1, 21).await, None);
+        assert_eq!(feature(6, 5).await, Some(("tokio".to_string(), 5)));
+    }
+
+    #[tokio::test]
+    async fn dependencies_within_range() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+            tokio = { version = "1", features = [
+                "rt",
+                "macros",
+            ] }
+            anyhow = { git = "https://github.com/dtolnay/anyhow" }
+            log = { workspace = true }
+
+            [dependencies.regex]
+            version = "1"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        let names = |start: u32, end: u32| {
+            let manifests = manifests.clone();
+            let url = url.clone();
+            async move {
+                manifests
+                    .within(
+                        &url,
+                        Range::new(Position::new(start, 0), Position::new(end, 0)),
+                    )
+                    .await
+                    .into_iter()
+                    .filter_map(|dependency| dependency.name().cloned())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(names(0, 0).await, Vec::<String>::new());
+        assert_eq!(names(1, 3).await, ["serde", "tokio"]);
+        assert_eq!(names(4, 4).await, ["tokio"]);
+        assert_eq!(names(5, 11).await, ["log", "regex"]);
+        assert_eq!(names(0, 100).await, ["serde", "tokio", "log", "regex"]);
+    }
+
+    #[tokio::test]
+    async fn version_range_by_name() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = { version = "1.0", features = ["derive"] }
+            git = { git = "https://github.com/rust-lang/git2-rs" }
+
+            [dependencies.tokio]
+            version = "1"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        assert_eq!(
+            manifests.version_range(&url, "serde").await,
+            Some(Range::new(Position::new(1, 21), Position::new(1, 24)))
+        );
+        assert_eq!(
+            manifests.version_range(&url, "tokio").await,
+            Some(Range::new(Position::new(5, 11), Position::new(5, 12)))
+        );
+        assert_eq!(manifests.version_range(&url, "git").await, None);
+        assert_eq!(manifests.version_range(&url, "rand").await, None);
+    }
+
+    #[test]
+    fn sections_of_manifest() {
+        let cargo = indoc! {r#"
+            [package]
+            name = "example"
+
+            [dependencies]
+            serde = "1"
+              [dependencies.tokio]
+            version = "1"
+
+            [dev-dependencies]
+
+            [target.'cfg(unix)'.dependencies]
+            libc = "0.2"
+        "#};
+
+        let section = |line: u32, len: u32, last_line: u32| DependencySection {
+            header: Range::new(Position::new(line, 0), Position::new(line, len)),
+            last_line,
+        };
+
+        assert_eq!(
+            dependency_sections(cargo),
+            vec![section(3, 14, 4), section(8, 18, 8), section(10, 33, 11)]
+        );
+    }
+
+    #[test]
+    fn dependency_kinds() {
+        let cargo = indoc! {r#"
+            [package]
+            name = "example"
+
+            [dependencies]
+            serde = "1"
+
+            [dev-dependencies.tokio]
+            version = "1"
+
+            [target.'cfg(target_os = "linux")'.build-dependencies]
+            cc = "1"
+
+            [workspace.dependencies]
+            anyhow = "1"
+        "#};
+
+        let kinds: Vec<_> = (0..cargo.lines().count() as u32)
+            .map(|line| dependency_kind(cargo, line))
+            .collect();
+
+        assert_eq!(
+            kinds,
+            [
+                None,
+                None,
+                None,
+                Some("dependencies"),
+                Some("dependencies"),
+                Some("dependencies"),
+                Some("dev-dependencies"),
+                Some("dev-dependencies"),
+                Some("dev-dependencies"),
+                Some("build-dependencies"),
+                Some("build-dependencies"),
+                Some("build-dependencies"),
+                Some("dependencies"),
+                Some("dependencies"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_explicit_default_registry() {
+        let explicit = indoc! {r#"
+            [dependencies]
+            foo = { version = "1", registry = "crates-io" }
+            bar = { version = "1", registry = "crates.io" }
+
+            [dependencies.baz]
+            registry = "crates-io"
+            version = "1"
+        "#};
+        let plain = indoc! {r#"
+            [dependencies]
+            foo = { version = "1" }
+            bar = { version = "1" }
+
+            [dependencies.baz]
+
+            version = "1"
+        "#};
+
+        assert_eq!(parse_manifest(explicit), parse_manifest(plain));
+    }
+
+    #[test]
+    fn parse_other_registry() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            foo = { version = "1", registry = "my-registry" }
+
+            [dependencies.bar]
+            version = "1"
+            registry = "my-registry"
+
+            [dependencies.baz]
+            registry = "my-registry"
+            version = "1"
+        "#};
+
+        let other = |name: &str| Dependency::Other {
+            name: name.to_string(),
+        };
+        assert_eq!(
+            parse_manifest(cargo),
+            vec![other("foo"), other("bar"), other("baz")]
+        );
+    }
+
+    #[test]
+    fn parse_crate_named_version() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            version = "1"
+
+            [dev-dependencies]
+            version = { version = "0.3", default-features = false }
+
+            [build-dependencies.version]
+            versioned = "3"
+            version = "2"
+        "#};
+
+        let dependencies: Vec<_> = parse_manifest(cargo)
+            .into_iter()
+            .map(|dependency| {
+                let Dependency::WithVersion(dependency) = dependency else {
+                    panic!("expected dependency with version");
+                };
+                (
+                    dependency.name,
+                    dependency.name_range.map(|range| range.start.line),
+                    dependency.version.range(),
+                    dependency.default_features,
+                )
+            })
+            .collect();
+
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        assert_eq!(
+            dependencies,
+            vec![
+                ("version".to_string(), Some(1), range(1, 11, 12), true),
+                ("version".to_string(), Some(4), range(4, 23, 26), false),
+                ("version".to_string(), None, range(8, 11, 12), true),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_build_dependency_table() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [build-dependencies.cc]
+            version = "1"
+            default-features = false
+
+            [target.'cfg(unix)'.build-dependencies.pkg-config]
+            version = "0.3"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        assert_eq!(
+            manifests.get(&url).await.unwrap(),
+            vec![
+                Dependency::WithVersion(DependencyWithVersion {
+                    name: "cc".to_string(),
+                    name_range: None,
+                    version: DependencyVersion::Complete {
+                        range: Range {
+                            start: Position::new(1, 11),
+                            end: Position::new(1, 12)
+                        },
+                        version: VersionReq::parse("1").unwrap()
+                    },
+                    default_features: false,
+                    features: Vec::new()
+                }),
+                Dependency::WithVersion(DependencyWithVersion {
+                    name: "pkg-config".to_string(),
+                    name_range: None,
+                    version: DependencyVersion::Complete {
+                        range: Range {
+                            start: Position::new(5, 11),
+                            end: Position::new(5, 14)
+                        },
+                        version: VersionReq::parse("0.3").unwrap()
+                    },
+                    default_features: true,
+                    features: Vec::new()
+                })
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_target_dependency_table() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [target.'cfg(windows)'.dependencies.winapi]
+            version = "0.3"
+
+            [profile.release.package.serde]
+            version = "1"
+
+            [dev-dependencies.tokio]
+            version = "1"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        assert_eq!(
+            manifests.get(&url).await.unwrap(),
+            vec![
+                Dependency::WithVersion(DependencyWithVersion {
+                    name: "winapi".to_string(),
+                    name_range: None,
+                    version: DependencyVersion::Complete {
+                        range: Range {
+                            start: Position::new(1, 11),
+                            end: Position::new(1, 14)
+                        },
+                        version: VersionReq::parse("0.3").unwrap()
+                    },
+                    default_features: true,
+                    features: Vec::new()
+                }),
+                Dependency::WithVersion(DependencyWithVersion {
+                    name: "tokio".to_string(),
+                    name_range: None,
+                    version: DependencyVersion::Complete {
+                        range: Range {
+                            start: Position::new(7, 11),
+                            end: Position::new(7, 12)
+                        },
+                        version: VersionReq::parse("1").unwrap()
+                    },
+                    default_features: true,
+                    features: Vec::new()
+                })
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn parse_default_features() {
+        let default_features = |line: &str| {
+            let Some(Dependency::WithVersion(dependency)) = Line::parse(line, 0) else {
+                panic!("expected dependency with version");
+            };
+            dependency.default_features
+        };
+
+        assert!(default_features(r#"foo = "1""#));
+        assert!(default_features(r#"foo = { version = "1" }"#));
+        assert!(default_features(
+            r#"foo = { version = "1", default-features = true }"#
+        ));
+        assert!(!default_features(
+            r#"foo = { version = "1", default-features = false }"#
+        ));
+        assert!(!default_features(
+            r#"foo = { default_features = false, version = "1" }"#
+        ));
+
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [dependencies.foo]
+            default-features = false
+            version = "1"
+
+            [dependencies.bar]
+            version = "1"
+            default-features = false
+
+            [dependencies.baz]
+            version = "1"
+        "#};
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+
+        let flags: Vec<_> = manifests
+            .get(&url)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|dependency| match dependency {
+                Dependency::WithVersion(dep) => Some((dep.name, dep.default_features)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            flags,
+            vec![
+                ("foo".to_string(), false),
+                ("bar".to_string(), false),
+                ("baz".to_string(), true)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn manifest_metadata_table() {
+        let url = Url::parse("file:///test").unwrap();
+
+        let cargo = indoc! {r#"
+            [package]
+            name = "example"
+
+            [package.metadata.crates-lsp]
+            ignore = ["serde"]
+
+            [dependencies]
+            serde = "1"
+        "#};
+
+        assert_eq!(
+            metadata_table(cargo).as_deref(),
+            Some("ignore = [\"serde\"]\n")
+        );
+        assert_eq!(
+            metadata_table("[package.metadata.other]\nignore = []"),
+            None
+        );
+
+        let manifests = ManifestTracker::default();
+        manifests.update_from_source(url.clone(), cargo).await;
+        assert!(manifests.settings(&url).await.is_ignored("serde"));
+        assert!(!manifests.settings(&url).await.is_ignored("tokio"));
+    }
+
+    #[test]
+    fn parse_feature_arrays() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            single = { version = "1", features = ["derive", "rc"] }
+            trailing = { version = "1", features = ["derive",] }
+            multiline = { version = "1", features = [
+                "derive",
+                # Reference counting
+                "rc",
+            ] }
+            unclosed = { version = "1", features = ["derive",
+            after = "1"
+
+            [dependencies.table]
+            features = [
+              "std"
+            ]
+            version = "1"
+        "#};
+
+        let features: Vec<_> = parse_manifest(cargo)
+            .into_iter()
+            .filter_map(|dependency| match dependency {
+                Dependency::WithVersion(dep) => Some((
+                    dep.name,
+                    dep.features
+                        .into_iter()
+                        .map(|feature| (feature.name, feature.range))
+                        .collect::<Vec<_>>(),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+
+        assert_eq!(
+            features,
+            vec![
+                (
+                    "single".to_string(),
+                    vec![
+                        ("derive".to_string(), range(1, 39, 45)),
+                        ("rc".to_string(), range(1, 49, 51))
+                    ]
+                ),
+                (
+                    "trailing".to_string(),
+                    vec![("derive".to_string(), range(2, 41, 47))]
+                ),
+                (
+                    "multiline".to_string(),
+                    vec![
+                        ("derive".to_string(), range(4, 5, 11)),
+                        ("rc".to_string(), range(6, 5, 7))
+                    ]
+                ),
+                (
+                    "unclosed".to_string(),
+                    vec![("derive".to_string(), range(8, 41, 47))]
+                ),
+                ("after".to_string(), vec![]),
+                (
+                    "table".to_string(),
+                    vec![("std".to_string(), range(13, 3, 6))]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_padded_requirement() {
+        matches_complete(r#"serde = " 1.0 ""#, "serde", "1.0");
+        matches_complete(r#"serde = "1.0 ""#, "serde", "1.0");
+
+        // The range covers the requirement alone, without the surrounding spaces.
+        for line in [
+            r#"serde = " 1.0 ""#,
+            r#"serde = "1.0 ""#,
+            r#"serde = {  version = "  1.0", features = [] }"#,
+        ] {
+            let Some(Dependency::WithVersion(dependency)) = Line::parse(line, 0) else {
+                panic!("expected a version requirement in {line:?}");
+            };
+            let range = dependency.version.range();
+            assert_eq!(
+                &line[range.start.character as usize..range.end.character as usize],
+                "1.0"
+            );
+        }
+
+        // Nothing but spaces is still being typed.
+        matches_partial(r#"serde = "  ""#, "serde", "");
+    }
+
+    #[test]
+    fn table_dependency_ranges() {
+        let cargo = indoc! {r#"
+            [dependencies.serde]
+            version = "1.0.100"
+
+            [dependencies.tokio]
+              default-features = false
+              version = "1.40"
+
+            [dependencies]
+                anyhow = { version = "1", features = ["backtrace"] }
+        "#};
+
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+
+        let ranges: Vec<_> = parse_manifest(cargo)
+            .into_iter()
+            .filter_map(|dependency| match dependency {
+                Dependency::WithVersion(dep) => Some((
+                    dep.name,
+                    dep.name_range,
+                    dep.version.range(),
+                    dep.features
+                        .into_iter()
+                        .map(|feature| feature.range)
+                        .collect::<Vec<_>>(),
+                )),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            ranges,
+            vec![
+                ("serde".to_string(), None, range(1, 11, 18), vec![]),
+                ("tokio".to_string(), None, range(5, 13, 17), vec![]),
+                (
+                    "anyhow".to_string(),
+                    Some(range(8, 4, 10)),
+                    range(8, 26, 27),
+                    vec![range(8, 43, 52)]
+                ),
+            ]
+        );
+
+        // The ranges point at the requirements themselves.
+        for (_, _, version, _) in &ranges {
+            let line = cargo.lines().nth(version.start.line as usize).unwrap();
+            let text = &line[version.start.character as usize..version.end.character as usize];
+            assert!(text.starts_with('1'), "{text:?}");
+            assert_eq!(text_at(cargo, *version), Some(text));
+        }
+    }
+
+    #[tokio::test]
+    async fn tracker_encodes_ranges() {
+        let url = Url::parse("file:///test").unwrap();
+        let cargo = indoc! {r#"
+            [dependencies]
+            foo = { path = "../føø", version = "1", features = ["bar"] }
+        "#};
+
+        let version_range = |dependencies: Vec<Dependency>| match &dependencies[0] {
+            Dependency::WithVersion(dep) => (dep.version.range(), dep.features[0].range),
+            _ => panic!("expected dependency with version"),
+        };
+
+        let manifests = ManifestTracker::default();
+        let (version, feature) =
+            version_range(manifests.update_from_source(url.clone(), cargo).await);
+        assert_eq!(version.start.character, 36);
+        assert_eq!(feature.start.character, 53);
+
+        manifests.set_encoding(PositionEncoding::Utf8).await;
+        let (version, feature) =
+            version_range(manifests.update_from_source(url.clone(), cargo).await);
+        assert_eq!(version.start.character, 38);
+        assert_eq!(feature.start.character, 55);
+    }
+
+    /// Describe the dependency on a single line, for comparing against the expected
+    /// output of the fixtures.
+    fn describe(dependency: &Dependency) -> String {
+        let at = |range: &Range| {
+            format!(
+                "{}:{}-{}",
+                range.start.line, range.start.character, range.end.character
+            )
+        };
+
+        match dependency {
+            Dependency::Partial { name, line } => format!("{line} partial {name}"),
+            Dependency::WithVersion(dep) => {
+                let version = match &dep.version {
+                    DependencyVersion::Complete { version, .. } => format!("complete {version}"),
+                    DependencyVersion::Partial { version, .. } => format!("partial {version:?}"),
+                    DependencyVersion::Invalid { version, .. } => format!("invalid {version:?}"),
+                };
+                let mut description =
+                    format!("{} {} {version}", at(&dep.version.range()), dep.name);
+                if !dep.default_features {
+                    description.push_str(" no-default-features");
+                }
+                if !dep.features.is_empty() {
+                    let features: Vec<_> = dep
+                        .features
+                        .iter()
+                        .map(|feature| format!("{}@{}", feature.name, at(&feature.range)))
+                        .collect();
+                    description.push_str(&format!(" features [{}]", features.join(", ")));
+                }
+                description
+            }
+            Dependency::Git {
+                name,
+                url,
+                tag,
+                range,
+            } => format!("{} {name} git {url} tag {tag}", at(range)),
+            Dependency::GitSource { name, url } => format!("{name} git {url}"),
+            Dependency::WorkspaceInherited { name, line } => format!("{line} {name} workspace"),
+            Dependency::Other { name } => format!("{name} other"),
+        }
+    }
+
+    /// Parse every manifest in `tests/fixtures`, comparing the result against the
+    /// `.expected` file next to it. Run with `UPDATE_FIXTURES=1` to write the expected
+    /// files instead, and review the changes before committing them.
+    #[test]
+    fn fixtures() {
+        let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+
+        let mut manifests: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "toml")
+            })
+            .collect();
+        manifests.sort();
+        assert!(manifests.len() >= 12);
+
+        for manifest in manifests {
+            let source = std::fs::read_to_string(&manifest).unwrap();
+            let parsed: String = parse_manifest(&source)
+                .iter()
+                .map(|dependency| describe(dependency) + "\n")
+                .collect();
+
+            let expected = manifest.with_extension("expected");
+            if update {
+                std::fs::write(&expected, &parsed).unwrap();
+                continue;
+            }
+
+            assert_eq!(
+                parsed,
+                std::fs::read_to_string(&expected).unwrap_or_default(),
+                "{} doesn't parse as expected",
+                manifest.display()
+            );
+        }
+    }
 }
@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+
+use tower_lsp::lsp_types::TextEdit;
+
+/// Render the single-line edits as a unified diff of the manifest, for reviewing
+/// updates before applying them. Each changed line gets a hunk of its own, without
+/// any surrounding context. Edit ranges are expected as byte offsets.
+pub fn unified_diff(name: &str, source: &str, edits: &[TextEdit]) -> String {
+    let mut lines: BTreeMap<u32, Vec<&TextEdit>> = BTreeMap::new();
+    for edit in edits {
+        lines.entry(edit.range.start.line).or_default().push(edit);
+    }
+
+    let mut diff = format!("--- a/{name}\n+++ b/{name}\n");
+
+    for (line_no, mut edits) in lines {
+        let Some(line) = source.lines().nth(line_no as usize) else {
+            continue;
+        };
+
+        // Apply the rightmost edit first, so earlier offsets remain valid.
+        edits.sort_by_key(|edit| std::cmp::Reverse(edit.range.start.character));
+
+        let mut updated = line.to_string();
+        for edit in edits {
+            let start = edit.range.start.character as usize;
+            let end = edit.range.end.character as usize;
+            if updated.get(start..end).is_some() {
+                updated.replace_range(start..end, &edit.new_text);
+            }
+        }
+
+        let line_no = line_no + 1;
+        diff.push_str(&format!(
+            "@@ -{line_no} +{line_no} @@\n-{line}\n+{updated}\n"
+        ));
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use tower_lsp::lsp_types::{Position, Range, TextEdit};
+
+    use super::unified_diff;
+
+    #[test]
+    fn diff_of_updates() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1.0.100"
+            tokio = { version = "=0.2", features = ["full"] }
+            anyhow = "1"
+        "#};
+
+        let edit = |line, start, end, new_text: &str| TextEdit {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            new_text: new_text.to_string(),
+        };
+
+        assert_eq!(
+            unified_diff(
+                "Cargo.toml",
+                cargo,
+                &[edit(2, 21, 25, "=1.40.0"), edit(1, 9, 16, "1.0.200")]
+            ),
+            indoc! {r#"
+                --- a/Cargo.toml
+                +++ b/Cargo.toml
+                @@ -2 +2 @@
+                -serde = "1.0.100"
+                +serde = "1.0.200"
+                @@ -3 +3 @@
+                -tokio = { version = "=0.2", features = ["full"] }
+                +tokio = { version = "=1.40.0", features = ["full"] }
+            "#}
+        );
+    }
+}
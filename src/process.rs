@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+/// How often to check whether the client process is still running.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Wait until the process with the given id has exited.
+pub async fn wait_for_exit(pid: u32) {
+    while is_alive(pid).await {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Check whether a process with the given id is running.
+#[cfg(unix)]
+pub async fn is_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+
+    // Signal 0 only checks whether the process exists and could be signalled.
+    // SAFETY: kill has no memory safety requirements.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+
+    // The process exists, but belongs to another user.
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Check whether a process with the given id is running.
+#[cfg(windows)]
+pub async fn is_alive(pid: u32) -> bool {
+    let output = tokio::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/FO", "CSV", "/NH"])
+        .kill_on_drop(true)
+        .output()
+        .await;
+
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).contains(&format!("\"{pid}\"")),
+        // If we can't tell, err on the side of keeping the server running.
+        Err(_) => true,
+    }
+}
+
+/// Check whether a process with the given id is running. On platforms without a
+/// supported way of checking, the process is always assumed to be running.
+#[cfg(not(any(unix, windows)))]
+pub async fn is_alive(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_alive;
+
+    #[tokio::test]
+    async fn current_process_is_alive() {
+        assert!(is_alive(std::process::id()).await);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn exited_process_is_not_alive() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+
+        assert!(!is_alive(pid).await);
+    }
+}
@@ -0,0 +1,430 @@
+use semver::{Op, Version, VersionReq};
+
+use crate::crates::{PublishedVersion, Release};
+use crate::settings::RequirementStyle;
+
+/// How a version requirement relates to the newest release of a crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The newest release satisfies the requirement.
+    UpToDate,
+    /// The newest release does not satisfy the requirement.
+    Outdated { replacement: String, breaking: bool },
+    /// The requirement pins an exact version which has since been yanked.
+    Yanked {
+        pinned: Version,
+        replacement: String,
+        breaking: bool,
+    },
+}
+
+/// Compare the requirement against the newest release, taking into account its
+/// operator, both when suggesting a replacement and when checking for yanked pins.
+///
+/// `text` is the requirement as written in the manifest, which is used for preserving
+/// its operator, since [`VersionReq`] normalizes it when displayed.
+pub fn status(text: &str, requirement: &VersionReq, release: &Release) -> Status {
+    let replacement = || update_requirement(text, &release.version);
+    let breaking = is_breaking_update(requirement, &release.version);
+
+    // Pointing out that a pinned version was yanked is more useful than merely
+    // reporting that a newer version exists.
+    if let Some(pinned) = pinned_version(requirement) {
+        if release.yanked.contains(&pinned) {
+            return Status::Yanked {
+                pinned,
+                replacement: replacement(),
+                breaking,
+            };
+        }
+    }
+
+    if requirement.matches(&release.version) {
+        Status::UpToDate
+    } else {
+        Status::Outdated {
+            replacement: replacement(),
+            breaking,
+        }
+    }
+}
+
+/// How a requirement on a pre-release, e.g. `1.0.0-beta.1`, relates to the releases
+/// published since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrereleaseStatus {
+    /// No newer release has been published.
+    UpToDate,
+    /// A stable release newer than the pre-release has been published.
+    Stable(Version),
+    /// Only newer pre-releases have been published since.
+    Prerelease(Version),
+}
+
+/// Resolve a requirement on a pre-release against the published versions, rather
+/// than just the newest stable release, which may be older than the pre-release.
+///
+/// Pre-releases may break compatibility between one another, so any release newer
+/// than the one written in the requirement is reported, even if the requirement
+/// would already allow it. Stable releases are preferred over newer pre-releases.
+/// Returns `None` if the requirement isn't on a pre-release.
+pub fn prerelease_status(
+    requirement: &VersionReq,
+    published: &[PublishedVersion],
+) -> Option<PrereleaseStatus> {
+    let comparator = requirement
+        .comparators
+        .iter()
+        .find(|comparator| !comparator.pre.is_empty())?;
+
+    let current = Version {
+        major: comparator.major,
+        minor: comparator.minor.unwrap_or_default(),
+        patch: comparator.patch.unwrap_or_default(),
+        pre: comparator.pre.clone(),
+        build: Default::default(),
+    };
+
+    let newer = published
+        .iter()
+        .filter(|published| !published.yanked && published.version > current)
+        .map(|published| &published.version);
+
+    let stable = newer.clone().filter(|version| version.pre.is_empty()).max();
+    Some(match stable {
+        Some(stable) => PrereleaseStatus::Stable(stable.clone()),
+        None => match newer.max() {
+            Some(prerelease) => PrereleaseStatus::Prerelease(prerelease.clone()),
+            None => PrereleaseStatus::UpToDate,
+        },
+    })
+}
+
+/// Check whether the requirement uses the operator required by the style policy.
+///
+/// `text` is the requirement as written in the manifest, since [`VersionReq`] doesn't
+/// distinguish bare requirements from explicit caret requirements.
+pub fn conforms_to_style(text: &str, requirement: &VersionReq, style: RequirementStyle) -> bool {
+    let [comparator] = requirement.comparators.as_slice() else {
+        return style == RequirementStyle::Any;
+    };
+
+    match style {
+        RequirementStyle::Any => true,
+        RequirementStyle::Caret => comparator.op == Op::Caret && text.trim().starts_with('^'),
+        RequirementStyle::Tilde => comparator.op == Op::Tilde,
+        RequirementStyle::Exact => pinned_version(requirement).is_some(),
+    }
+}
+
+/// Write the version as a requirement of the given style, e.g. `^1.2.3`.
+pub fn with_style(version: &Version, style: RequirementStyle) -> String {
+    let operator = match style {
+        RequirementStyle::Caret => "^",
+        RequirementStyle::Tilde => "~",
+        RequirementStyle::Exact => "=",
+        RequirementStyle::Any => "",
+    };
+
+    format!("{operator}{version}")
+}
+
+/// The lowest version allowed by the requirement, with missing components filled in
+/// with zeroes, if it has a lower bound at all.
+pub fn lower_bound(requirement: &VersionReq) -> Option<Version> {
+    let comparator = requirement.comparators.iter().find(|comparator| {
+        matches!(
+            comparator.op,
+            Op::Exact | Op::GreaterEq | Op::Tilde | Op::Caret | Op::Wildcard
+        )
+    })?;
+
+    Some(Version {
+        major: comparator.major,
+        minor: comparator.minor.unwrap_or_default(),
+        patch: comparator.patch.unwrap_or_default(),
+        pre: comparator.pre.clone(),
+        build: Default::default(),
+    })
+}
+
+/// Check whether the requirement has no upper bound at all, such as `>=1` or `*`,
+/// meaning it accepts every future breaking release of the crate.
+pub fn is_unbounded(requirement: &VersionReq) -> bool {
+    requirement
+        .comparators
+        .iter()
+        .all(|comparator| matches!(comparator.op, Op::Greater | Op::GreaterEq))
+}
+
+/// The version an `=x.y.z` requirement pins, if the requirement is an exact pin.
+fn pinned_version(requirement: &VersionReq) -> Option<Version> {
+    let [comparator] = requirement.comparators.as_slice() else {
+        return None;
+    };
+
+    if comparator.op != Op::Exact {
+        return None;
+    }
+
+    Some(Version {
+        major: comparator.major,
+        minor: comparator.minor?,
+        patch: comparator.patch?,
+        pre: comparator.pre.clone(),
+        build: Default::default(),
+    })
+}
+
+/// Rewrite a version requirement to target the given version, preserving the
+/// caret, tilde or equals operator of the original requirement, if any.
+pub fn update_requirement(requirement: &str, version: &Version) -> String {
+    let requirement = requirement.trim();
+
+    let operator = ["^", "~", "="]
+        .into_iter()
+        .find(|operator| requirement.starts_with(operator))
+        .unwrap_or_default();
+
+    format!("{operator}{version}")
+}
+
+/// Check whether updating the requirement to the given version would be a breaking
+/// change, taking into account that cargo treats `0.x` minor releases as incompatible.
+pub fn is_breaking_update(requirement: &VersionReq, version: &Version) -> bool {
+    let Some(comparator) = requirement.comparators.first() else {
+        return false;
+    };
+
+    if comparator.major != version.major {
+        return true;
+    }
+
+    if comparator.major > 0 {
+        return false;
+    }
+
+    match comparator.minor {
+        // A bare `0` requirement is compatible with all 0.x releases.
+        None => false,
+        Some(minor) if minor != version.minor => true,
+        Some(0) => comparator.patch.is_some_and(|patch| patch != version.patch),
+        Some(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use semver::{Version, VersionReq};
+
+    use crate::crates::{PublishedVersion, Release};
+    use crate::settings::RequirementStyle;
+
+    use super::{
+        conforms_to_style, is_breaking_update, is_unbounded, lower_bound, prerelease_status,
+        status, update_requirement, with_style, PrereleaseStatus, Status,
+    };
+
+    fn release(version: &str, yanked: &[&str]) -> Release {
+        Release {
+            version: Version::parse(version).unwrap(),
+            published_at: None,
+            yanked: yanked
+                .iter()
+                .map(|version| Version::parse(version).unwrap())
+                .collect(),
+        }
+    }
+
+    fn status_of(requirement: &str, release: &Release) -> Status {
+        status(
+            requirement,
+            &VersionReq::parse(requirement).unwrap(),
+            release,
+        )
+    }
+
+    #[test]
+    fn exact_pins() {
+        let newest = release("1.2.4", &[]);
+
+        assert_eq!(status_of("=1.2.4", &newest), Status::UpToDate);
+        assert_eq!(
+            status_of("=1.2.3", &newest),
+            Status::Outdated {
+                replacement: "=1.2.4".to_string(),
+                breaking: false,
+            }
+        );
+        assert_eq!(
+            status_of("1.2.3", &newest),
+            Status::UpToDate,
+            "caret requirements are satisfied by newer compatible releases"
+        );
+    }
+
+    #[test]
+    fn yanked_pins() {
+        let newest = release("1.2.4", &["1.2.3"]);
+
+        assert_eq!(
+            status_of("=1.2.3", &newest),
+            Status::Yanked {
+                pinned: Version::new(1, 2, 3),
+                replacement: "=1.2.4".to_string(),
+                breaking: false,
+            }
+        );
+
+        // Only exact pins can't be resolved to a release which isn't yanked.
+        assert_eq!(status_of("1.2.3", &newest), Status::UpToDate);
+        assert_eq!(
+            status_of("~1.1", &newest),
+            Status::Outdated {
+                replacement: "~1.2.4".to_string(),
+                breaking: false,
+            }
+        );
+        assert_eq!(
+            status_of("=1.2", &newest),
+            Status::UpToDate,
+            "partial pins are not exact"
+        );
+    }
+
+    #[test]
+    fn prerelease_requirements() {
+        let published = |versions: &[&str]| -> Vec<PublishedVersion> {
+            versions
+                .iter()
+                .map(|version| PublishedVersion {
+                    version: Version::parse(version.trim_end_matches(" (yanked)")).unwrap(),
+                    yanked: version.ends_with(" (yanked)"),
+                    published_at: None,
+                    rust_version: None,
+                    features: Vec::new(),
+                })
+                .collect()
+        };
+        let status = |requirement: &str, versions: &[&str]| {
+            prerelease_status(
+                &VersionReq::parse(requirement).unwrap(),
+                &published(versions),
+            )
+        };
+
+        // Only a newer pre-release, while the newest stable release is older.
+        assert_eq!(
+            status("1.0.0-beta.1", &["0.9.0", "1.0.0-beta.1", "1.0.0-beta.2"]),
+            Some(PrereleaseStatus::Prerelease(
+                Version::parse("1.0.0-beta.2").unwrap()
+            ))
+        );
+
+        // The stable release is preferred over newer pre-releases.
+        assert_eq!(
+            status("1.0.0-beta.1", &["1.0.0-beta.2", "1.0.0", "1.1.0-alpha.1"]),
+            Some(PrereleaseStatus::Stable(Version::new(1, 0, 0)))
+        );
+
+        assert_eq!(
+            status(
+                "=1.0.0-beta.2",
+                &["0.9.0", "1.0.0-beta.2", "1.0.0-beta.3 (yanked)"]
+            ),
+            Some(PrereleaseStatus::UpToDate)
+        );
+        assert_eq!(status("1.0.0", &["1.0.0-beta.2", "1.1.0"]), None);
+    }
+
+    #[test]
+    fn requirement_styles() {
+        let conforming = |style: RequirementStyle| -> Vec<&str> {
+            [
+                "1.2",
+                "^1.2.3",
+                "~1.2.3",
+                "=1.2.3",
+                "=1.2",
+                "1.*",
+                "*",
+                ">=1.2, <2",
+            ]
+            .into_iter()
+            .filter(|text| conforms_to_style(text, &VersionReq::parse(text).unwrap(), style))
+            .collect()
+        };
+
+        assert_eq!(conforming(RequirementStyle::Caret), ["^1.2.3"]);
+        assert_eq!(conforming(RequirementStyle::Tilde), ["~1.2.3"]);
+        assert_eq!(conforming(RequirementStyle::Exact), ["=1.2.3"]);
+        assert_eq!(conforming(RequirementStyle::Any).len(), 8);
+
+        let rewrite = |text: &str, style| {
+            lower_bound(&VersionReq::parse(text).unwrap())
+                .map(|version| with_style(&version, style))
+        };
+
+        assert_eq!(rewrite("1.2", RequirementStyle::Caret).unwrap(), "^1.2.0");
+        assert_eq!(
+            rewrite("^1.2.3", RequirementStyle::Tilde).unwrap(),
+            "~1.2.3"
+        );
+        assert_eq!(rewrite("~1.2", RequirementStyle::Exact).unwrap(), "=1.2.0");
+        assert_eq!(rewrite("1.*", RequirementStyle::Caret).unwrap(), "^1.0.0");
+        assert_eq!(
+            rewrite(">=1.2, <2", RequirementStyle::Caret).unwrap(),
+            "^1.2.0"
+        );
+        assert_eq!(rewrite("*", RequirementStyle::Caret), None);
+        assert_eq!(rewrite("<2", RequirementStyle::Caret), None);
+    }
+
+    #[test]
+    fn breaking_updates() {
+        let breaking = |requirement: &str, version: &str| {
+            is_breaking_update(
+                &VersionReq::parse(requirement).unwrap(),
+                &Version::parse(version).unwrap(),
+            )
+        };
+
+        assert!(!breaking("1.2", "1.3.0"));
+        assert!(breaking("1.2", "2.0.0"));
+        assert!(!breaking("0", "0.5.0"));
+        assert!(!breaking("0.1", "0.1.5"));
+        assert!(breaking("0.1", "0.2.0"));
+        assert!(breaking("0.1.2", "1.0.0"));
+        assert!(!breaking("0.0", "0.0.3"));
+        assert!(breaking("0.0.1", "0.0.2"));
+        assert!(breaking("0.0.1", "0.1.0"));
+    }
+
+    #[test]
+    fn unbounded_requirements() {
+        let unbounded = |requirement: &str| is_unbounded(&VersionReq::parse(requirement).unwrap());
+
+        assert!(unbounded(">=0"));
+        assert!(unbounded(">=0.0.0"));
+        assert!(unbounded(">=1"));
+        assert!(unbounded(">1.2, >=1.3"));
+        assert!(unbounded("*"));
+        assert!(!unbounded(">=1, <2"));
+        assert!(!unbounded("1.2"));
+        assert!(!unbounded("~1.2"));
+        assert!(!unbounded("=1.2.3"));
+        assert!(!unbounded("1.*"));
+        assert!(!unbounded("<=2"));
+    }
+
+    #[test]
+    fn update_requirement_preserves_operator() {
+        let version = Version::parse("1.3.0").unwrap();
+
+        assert_eq!(update_requirement("1.2.0", &version), "1.3.0");
+        assert_eq!(update_requirement("^1.2.0", &version), "^1.3.0");
+        assert_eq!(update_requirement("~1.2", &version), "~1.3.0");
+        assert_eq!(update_requirement("=1.2.0", &version), "=1.3.0");
+        assert_eq!(update_requirement(" ^1.2", &version), "^1.3.0");
+    }
+}
@@ -1,19 +1,105 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
 use tokio::sync::RwLock;
-use tower_lsp::lsp_types::DiagnosticSeverity;
+use tower_lsp::lsp_types::{DiagnosticSeverity, Url};
+
+use crate::conflicts::BUILTIN_CONFLICTS;
+use crate::crates::cache::DEFAULT_MAX_CACHE_ENTRIES;
+use crate::crates::{ClientOptions, DEFAULT_ALLOWED_HOSTS, DEFAULT_API_BASE_URL};
+use crate::parse;
+
+/// Name of the file which can override settings for all manifests within its directory.
+pub const DIRECTORY_SETTINGS_FILE: &str = "crates-lsp.toml";
+
+/// Inlay hint for outdated crates, unless configured otherwise through `needsUpdateHint`.
+const DEFAULT_NEEDS_UPDATE_HINT: &str = " {}";
 
 #[derive(Default, Debug, Clone)]
 pub struct Settings {
     inner: Arc<RwLock<InnerSettings>>,
+    /// Overrides found in `crates-lsp.toml` files, cached per directory.
+    directories: Arc<RwLock<HashMap<PathBuf, Option<DirectorySettings>>>>,
 }
 
 impl Settings {
     pub async fn populate_from(&self, value: serde_json::Value) {
-        if let Ok(new_settings) = serde_json::from_value(value) {
-            let mut internal_settings = self.inner.write().await;
-            *internal_settings = new_settings;
+        let Some(lsp) = value.get("lsp").cloned() else {
+            return;
+        };
+
+        let mut internal_settings = self.inner.write().await;
+        internal_settings.lsp = LspSettings::lenient(lsp);
+    }
+
+    /// Find the settings overridden by the nearest `crates-lsp.toml`, searching the
+    /// directory of the manifest and all of its ancestors. Each directory is read
+    /// once, off the async runtime's thread, and cached until its file changes.
+    async fn directory_settings(&self, url: &Url) -> DirectorySettings {
+        let Ok(manifest) = url.to_file_path() else {
+            return DirectorySettings::default();
+        };
+
+        for directory in manifest.ancestors().skip(1) {
+            if let Some(cached) = self.directories.read().await.get(directory) {
+                match cached {
+                    Some(settings) => return settings.clone(),
+                    None => continue,
+                }
+            }
+
+            let settings = tokio::fs::read_to_string(directory.join(DIRECTORY_SETTINGS_FILE))
+                .await
+                .ok()
+                .and_then(|content| match toml::from_str(&content) {
+                    Ok(settings) => Some(settings),
+                    Err(err) => {
+                        tracing::warn!(?directory, %err, "ignoring invalid {DIRECTORY_SETTINGS_FILE}");
+                        None
+                    }
+                });
+
+            self.directories
+                .write()
+                .await
+                .insert(directory.to_path_buf(), settings.clone());
+
+            if let Some(settings) = settings {
+                return settings;
+            }
+        }
+
+        DirectorySettings::default()
+    }
+
+    /// Forget the settings read from the `crates-lsp.toml` at the given path, so that
+    /// the file is read again the next time it's needed. If it has been deleted, the
+    /// manifests below it fall back to any other file further up, or the LSP settings.
+    pub async fn forget_directory_settings(&self, file: &Path) {
+        if let Some(directory) = file.parent() {
+            self.directories.write().await.remove(directory);
+        }
+    }
+
+    /// Whether diagnostics are enabled for the given manifest.
+    pub async fn diagnostics_for(&self, url: &Url) -> bool {
+        match self.directory_settings(url).await.diagnostics {
+            Some(diagnostics) => diagnostics,
+            None => self.diagnostics().await,
+        }
+    }
+
+    /// Whether inlay hints are enabled for the given manifest.
+    pub async fn inlay_hints_for(&self, url: &Url) -> bool {
+        match self.directory_settings(url).await.inlay_hints {
+            Some(inlay_hints) => inlay_hints,
+            None => self.inlay_hints().await,
         }
     }
 
@@ -21,14 +107,216 @@ impl Settings {
         self.inner.read().await.lsp.use_api.unwrap_or_default()
     }
 
+    pub async fn fallback_backend(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .fallback_backend
+            .unwrap_or_default()
+    }
+
+    pub async fn api_base_url(&self) -> String {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .api_base_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_API_BASE_URL.to_string())
+    }
+
+    /// Hosts which may be contacted over HTTP, defaulting to crates.io and its index.
+    pub async fn allowed_hosts(&self) -> Vec<String> {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .allowed_hosts
+            .clone()
+            .unwrap_or_else(|| {
+                DEFAULT_ALLOWED_HOSTS
+                    .iter()
+                    .map(|host| host.to_string())
+                    .collect()
+            })
+    }
+
+    /// Whether to avoid network lookups, if explicitly configured. When unset, the
+    /// cargo configuration of the manifest decides.
+    pub async fn offline(&self) -> Option<bool> {
+        self.inner.read().await.lsp.offline
+    }
+
     pub async fn inlay_hints(&self) -> bool {
         self.inner.read().await.lsp.inlay_hints.unwrap_or(true)
     }
 
+    /// Number of crates to offer when completing crate names, within the 1 to 100
+    /// results crates.io allows per page.
+    pub async fn completion_result_limit(&self) -> u8 {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .completion_result_limit
+            .filter(|limit| (1..=100).contains(limit))
+            .unwrap_or(5)
+    }
+
+    /// Kinds of dependency tables to offer completions in, or `None` for all of them.
+    pub async fn completion_sections(&self) -> Option<Vec<String>> {
+        self.inner.read().await.lsp.completion_sections.clone()
+    }
+
+    pub async fn completion_include_prereleases(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .completion_include_prereleases
+            .unwrap_or_default()
+    }
+
+    pub async fn completion_include_yanked(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .completion_include_yanked
+            .unwrap_or_default()
+    }
+
+    pub async fn msrv_aware(&self) -> bool {
+        self.inner.read().await.lsp.msrv_aware.unwrap_or_default()
+    }
+
+    pub async fn prerelease_aware(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .prerelease_aware
+            .unwrap_or_default()
+    }
+
+    pub async fn use_lockfile(&self) -> bool {
+        self.inner.read().await.lsp.use_lockfile.unwrap_or_default()
+    }
+
+    pub async fn section_summary_hints(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .section_summary_hints
+            .unwrap_or_default()
+    }
+
+    pub async fn relax_unpublished(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .relax_unpublished
+            .unwrap_or_default()
+    }
+
+    pub async fn check_git_tags(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .check_git_tags
+            .unwrap_or_default()
+    }
+
     pub async fn diagnostics(&self) -> bool {
         self.inner.read().await.lsp.diagnostics.unwrap_or(true)
     }
 
+    /// Number of crates kept in the in-memory cache.
+    pub async fn max_cache_entries(&self) -> usize {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .max_cache_entries
+            .unwrap_or(DEFAULT_MAX_CACHE_ENTRIES)
+    }
+
+    pub async fn inlay_hint_anchor(&self) -> InlayHintAnchor {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .inlay_hint_anchor
+            .unwrap_or_default()
+    }
+
+    pub async fn diagnostic_order(&self) -> DiagnosticOrder {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .diagnostic_order
+            .unwrap_or_default()
+    }
+
+    pub async fn warn_empty_features(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .warn_empty_features
+            .unwrap_or_default()
+    }
+
+    pub async fn should_use_workspace_dep(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .should_use_workspace_dep
+            .unwrap_or_default()
+    }
+
+    pub async fn warn_unbounded_req(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .warn_unbounded_req
+            .unwrap_or_default()
+    }
+
+    pub async fn flag_redundant_patches(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .flag_redundant_patches
+            .unwrap_or_default()
+    }
+
+    pub async fn summary_diagnostic(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .summary_diagnostic
+            .unwrap_or_default()
+    }
+
+    pub async fn requirement_style_policy(&self) -> RequirementStyle {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .requirement_style_policy
+            .unwrap_or_default()
+    }
+
     pub async fn needs_update_severity(&self) -> DiagnosticSeverity {
         self.inner
             .read()
@@ -59,6 +347,65 @@ impl Settings {
             .unwrap_or(DiagnosticSeverity::WARNING)
     }
 
+    pub async fn stale_crate_severity(&self) -> DiagnosticSeverity {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .stale_crate_severity
+            .filter(verify_severity)
+            .unwrap_or(DiagnosticSeverity::INFORMATION)
+    }
+
+    /// Age in years at which a crate's latest release is considered stale, if enabled.
+    pub async fn stale_crate_warning(&self) -> Option<u32> {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .stale_crate_warning
+            .filter(|years| *years > 0)
+    }
+
+    pub async fn client_options(&self) -> ClientOptions {
+        let settings = self.inner.read().await;
+        ClientOptions {
+            accept_invalid_certs: settings
+                .lsp
+                .dangerously_accept_invalid_certs
+                .unwrap_or_default(),
+            extra_ca_cert: settings.lsp.extra_ca_cert.clone(),
+        }
+    }
+
+    /// Pairs of crates which should be reported when both are dependencies.
+    pub async fn conflicting_crates(&self) -> Vec<(String, String)> {
+        let settings = self.inner.read().await;
+
+        let builtin = if settings.lsp.builtin_conflicts.unwrap_or_default() {
+            BUILTIN_CONFLICTS
+        } else {
+            &[]
+        };
+
+        builtin
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .chain(settings.lsp.conflicting_crates.iter().flatten().cloned())
+            .collect()
+    }
+
+    /// Lowest major version required of specific crates.
+    pub async fn minimum_major_versions(&self) -> HashMap<String, u64> {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .minimum_major_versions
+            .clone()
+            .unwrap_or_default()
+    }
+
     pub async fn up_to_date_hint(&self) -> String {
         self.inner
             .read()
@@ -76,7 +423,130 @@ impl Settings {
             .lsp
             .needs_update_hint
             .clone()
-            .unwrap_or_else(|| " {}".to_string())
+            .unwrap_or_else(|| DEFAULT_NEEDS_UPDATE_HINT.to_string())
+    }
+
+    pub async fn always_show_latest(&self) -> bool {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .always_show_latest
+            .unwrap_or_default()
+    }
+
+    pub async fn latest_hint(&self) -> String {
+        self.inner
+            .read()
+            .await
+            .lsp
+            .latest_hint
+            .clone()
+            .unwrap_or_else(|| "✓ {latest}".to_string())
+    }
+
+    /// The templates of the version hints of up to date and outdated dependencies. With
+    /// `alwaysShowLatest`, up to date dependencies use `latestHint` instead, and outdated
+    /// ones fall back to the default template if theirs is empty.
+    pub async fn hint_templates(&self) -> (String, String) {
+        let up_to_date = self.up_to_date_hint().await;
+        let needs_update = self.needs_update_hint().await;
+        if !self.always_show_latest().await {
+            return (up_to_date, needs_update);
+        }
+
+        let needs_update = if needs_update.is_empty() {
+            DEFAULT_NEEDS_UPDATE_HINT.to_string()
+        } else {
+            needs_update
+        };
+        (self.latest_hint().await, needs_update)
+    }
+
+    /// The settings in effect after applying defaults, for debugging purposes. Only
+    /// the settings listed here are included, so that anything sensitive which may
+    /// be configured is never accidentally exposed.
+    pub async fn effective(&self) -> serde_json::Value {
+        let client_options = self.client_options().await;
+
+        serde_json::json!({
+            "useApi": self.use_api().await,
+            "fallbackBackend": self.fallback_backend().await,
+            "apiBaseUrl": self.api_base_url().await,
+            "allowedHosts": self.allowed_hosts().await,
+            "offline": self.offline().await,
+            "inlayHints": self.inlay_hints().await,
+            "inlayHintAnchor": self.inlay_hint_anchor().await,
+            "diagnostics": self.diagnostics().await,
+            "diagnosticOrder": self.diagnostic_order().await,
+            "requirementStylePolicy": self.requirement_style_policy().await,
+            "warnEmptyFeatures": self.warn_empty_features().await,
+            "shouldUseWorkspaceDep": self.should_use_workspace_dep().await,
+            "warnUnboundedReq": self.warn_unbounded_req().await,
+            "summaryDiagnostic": self.summary_diagnostic().await,
+            "flagRedundantPatches": self.flag_redundant_patches().await,
+            "needsUpdateSeverity": self.needs_update_severity().await,
+            "upToDateSeverity": self.up_to_date_severity().await,
+            "unknownDepSeverity": self.unknown_dep_severity().await,
+            "staleCrateSeverity": self.stale_crate_severity().await,
+            "staleCrateWarning": self.stale_crate_warning().await,
+            "upToDateHint": self.up_to_date_hint().await,
+            "needsUpdateHint": self.needs_update_hint().await,
+            "alwaysShowLatest": self.always_show_latest().await,
+            "latestHint": self.latest_hint().await,
+            "dangerouslyAcceptInvalidCerts": client_options.accept_invalid_certs,
+            "extraCaCert": client_options.extra_ca_cert,
+            "conflictingCrates": self.conflicting_crates().await,
+            "minimumMajorVersions": self.minimum_major_versions().await,
+            "checkGitTags": self.check_git_tags().await,
+            "relaxUnpublished": self.relax_unpublished().await,
+            "maxCacheEntries": self.max_cache_entries().await,
+            "sectionSummaryHints": self.section_summary_hints().await,
+            "completionResultLimit": self.completion_result_limit().await,
+            "completionIncludePrereleases": self.completion_include_prereleases().await,
+            "completionIncludeYanked": self.completion_include_yanked().await,
+            "completionSections": self.completion_sections().await,
+            "msrvAware": self.msrv_aware().await,
+            "prereleaseAware": self.prerelease_aware().await,
+            "useLockfile": self.use_lockfile().await,
+        })
+    }
+}
+
+/// JSON Schema describing the settings accepted through `initializationOptions`.
+pub fn schema() -> serde_json::Value {
+    schemars::schema_for!(InnerSettings).to_value()
+}
+
+/// Fill in the `{current}`, `{latest}` and `{age}` placeholders of an inlay hint template.
+pub fn format_hint(template: &str, current: &str, latest: &str, age: &str) -> String {
+    template
+        .replace("{current}", current)
+        .replace("{latest}", latest)
+        .replace("{age}", age)
+}
+
+/// Describe how long ago a release was published, e.g. `2 weeks ago`, for use as the
+/// `{age}` placeholder. Releases without a known publication date are described as
+/// an empty string.
+pub fn humanize_age(published_at: Option<OffsetDateTime>, now: OffsetDateTime) -> String {
+    let Some(published_at) = published_at else {
+        return String::new();
+    };
+
+    let days = (now - published_at).whole_days().max(0);
+    let (count, unit) = match days {
+        0 => return "today".to_string(),
+        1..=13 => (days, "day"),
+        14..=59 => (days / 7, "week"),
+        60..=729 => (days / 30, "month"),
+        _ => (days / 365, "year"),
+    };
+
+    if count == 1 {
+        format!("{count} {unit} ago")
+    } else {
+        format!("{count} {unit}s ago")
     }
 }
 
@@ -85,28 +555,545 @@ fn verify_severity(d: &DiagnosticSeverity) -> bool {
     *d >= DiagnosticSeverity::ERROR && *d <= DiagnosticSeverity::HINT
 }
 
-#[derive(Default, Debug, Clone, Deserialize)]
+#[derive(Default, Debug, Clone, Deserialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct LspSettings {
     #[serde(default)]
     pub use_api: Option<bool>,
+    /// Look up crates which the configured backend couldn't reach in the other one,
+    /// i.e. the API if `useApi` is disabled and the sparse index otherwise. With a
+    /// custom `--registry`, only if `apiBaseUrl` points at that same registry.
+    #[serde(default)]
+    pub fallback_backend: Option<bool>,
+    /// Base URL of the crates.io compatible API used for searching crates, and for
+    /// looking up versions when `useApi` is enabled. Defaults to `https://crates.io`.
+    #[serde(default)]
+    pub api_base_url: Option<String>,
+    /// Hosts which may be contacted, such as those of git remotes. Requests and
+    /// redirects to any other host are refused. Defaults to `crates.io` and
+    /// `index.crates.io`, and the hosts of `--registry` and `apiBaseUrl` are always allowed.
+    #[serde(default)]
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Only use cached crate versions, never querying the registry. Defaults to
+    /// cargo's own `net.offline` configuration.
+    #[serde(default)]
+    pub offline: Option<bool>,
     #[serde(default)]
     pub inlay_hints: Option<bool>,
+    /// Where version inlay hints are placed on the line of their dependency.
+    #[serde(default)]
+    pub inlay_hint_anchor: Option<InlayHintAnchor>,
     #[serde(default)]
     pub diagnostics: Option<bool>,
+    /// Order in which diagnostics are published.
+    #[serde(default)]
+    pub diagnostic_order: Option<DiagnosticOrder>,
+    /// Report version requirements which don't use the given operator.
+    #[serde(default)]
+    pub requirement_style_policy: Option<RequirementStyle>,
+    /// Point out dependencies with `default-features = false` which don't enable any
+    /// features, and so may have had functionality disabled by accident.
     #[serde(default)]
+    pub warn_empty_features: Option<bool>,
+    /// Point out dependencies of workspace members which declare their own version,
+    /// even though the crate is declared in the root's `[workspace.dependencies]`.
+    #[serde(default)]
+    pub should_use_workspace_dep: Option<bool>,
+    /// Point out requirements without an upper bound, such as `>=0`, which accept
+    /// every future breaking release of the crate.
+    #[serde(default)]
+    pub warn_unbounded_req: Option<bool>,
+    /// Report the status of the dependencies in a single diagnostic at the top of the
+    /// manifest, e.g. `5 of 40 dependencies are outdated`, rather than on every line.
+    /// Other diagnostics, such as unknown crates, are still reported per line.
+    #[serde(default)]
+    pub summary_diagnostic: Option<bool>,
+    /// Point out `[patch.crates-io]` entries whose version has since been published in
+    /// the registry crates are looked up in, suggesting the patch may be removed.
+    #[serde(default)]
+    pub flag_redundant_patches: Option<bool>,
+    #[serde(default)]
+    #[schemars(with = "Option<u8>", range(min = 1, max = 4))]
     pub needs_update_severity: Option<DiagnosticSeverity>,
     #[serde(default)]
+    #[schemars(with = "Option<u8>", range(min = 1, max = 4))]
     pub up_to_date_severity: Option<DiagnosticSeverity>,
     #[serde(default)]
+    #[schemars(with = "Option<u8>", range(min = 1, max = 4))]
     pub unknown_dep_severity: Option<DiagnosticSeverity>,
     #[serde(default)]
+    #[schemars(with = "Option<u8>", range(min = 1, max = 4))]
+    pub stale_crate_severity: Option<DiagnosticSeverity>,
+    /// Warn about crates whose latest release is at least this many years old.
+    #[serde(default)]
+    pub stale_crate_warning: Option<u32>,
+    /// Inlay hint for up-to-date crates. `{}` and `{current}` are replaced with
+    /// the version requirement, `{latest}` with the newest version and `{age}` with
+    /// how long ago it was published, if known.
+    #[serde(default)]
     pub up_to_date_hint: Option<String>,
+    /// Inlay hint for outdated crates. `{}` and `{latest}` are replaced with
+    /// the newest version, `{current}` with the version requirement and `{age}` with
+    /// how long ago the newest version was published, if known, e.g. `{latest} ({age})`.
     #[serde(default)]
     pub needs_update_hint: Option<String>,
+    /// Show the newest version next to every dependency, including those which are up
+    /// to date, which are then hinted using `latestHint` rather than `upToDateHint`.
+    #[serde(default)]
+    pub always_show_latest: Option<bool>,
+    /// Inlay hint for up-to-date crates when `alwaysShowLatest` is enabled, with the
+    /// same placeholders as `upToDateHint`. Defaults to `✓ {latest}`.
+    #[serde(default)]
+    pub latest_hint: Option<String>,
+    /// Disable TLS certificate verification. Only use this for internal mirrors
+    /// which cannot be reached otherwise, and prefer `extraCaCert` where possible.
+    #[serde(default)]
+    pub dangerously_accept_invalid_certs: Option<bool>,
+    /// Path to an additional PEM-encoded root certificate to trust.
+    #[serde(default)]
+    pub extra_ca_cert: Option<PathBuf>,
+    /// Report dependencies on crates which usually shouldn't be used together,
+    /// such as competing async runtimes.
+    #[serde(default)]
+    pub builtin_conflicts: Option<bool>,
+    /// Additional pairs of crates which should be reported when both are dependencies.
+    #[serde(default)]
+    pub conflicting_crates: Option<Vec<(String, String)>>,
+    /// Report dependencies on the given crates whose requirement allows major versions
+    /// below the configured one, e.g. `{ "tokio": 1 }` to report `tokio = "0.2"`.
+    #[serde(default)]
+    pub minimum_major_versions: Option<HashMap<String, u64>>,
+    /// Check the remotes of git dependencies pinned to a tag for newer semver tags.
+    /// Only `https`, `ssh` and `git` remotes are checked.
+    #[serde(default)]
+    pub check_git_tags: Option<bool>,
+    /// Report outdated dependencies of manifests with `publish = false` as hints only.
+    #[serde(default)]
+    pub relax_unpublished: Option<bool>,
+    /// Number of crates kept in memory, after which the least recently used ones
+    /// are only read from the file cache.
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub max_cache_entries: Option<usize>,
+    /// Show an inlay hint summarizing the status of the dependencies next to each
+    /// dependency section header.
+    #[serde(default)]
+    pub section_summary_hints: Option<bool>,
+    /// Number of crates to offer when completing crate names.
+    #[serde(default)]
+    #[schemars(range(min = 1, max = 100))]
+    pub completion_result_limit: Option<u8>,
+    /// Offer the newest prerelease when completing versions, if it is newer than
+    /// the newest stable version.
+    #[serde(default)]
+    pub completion_include_prereleases: Option<bool>,
+    /// Offer yanked versions newer than the newest version when completing versions,
+    /// marked as deprecated.
+    #[serde(default)]
+    pub completion_include_yanked: Option<bool>,
+    /// Kinds of dependency tables in which completions are offered, such as
+    /// `dependencies`, `dev-dependencies` and `build-dependencies`. Tables specific to
+    /// a target count as their kind. Completions are offered in every table by default.
+    #[serde(default)]
+    pub completion_sections: Option<Vec<String>>,
+    /// Point out when the newest version of a crate requires a newer version of Rust
+    /// than the version currently matched by its requirement.
+    #[serde(default)]
+    pub msrv_aware: Option<bool>,
+    /// Compare requirements on pre-releases, e.g. `1.0.0-beta.1`, against newer
+    /// pre-releases as well as stable releases, rather than just the newest stable release.
+    #[serde(default)]
+    pub prerelease_aware: Option<bool>,
+    /// Compare the version locked in the workspace's `Cargo.lock` against the newest
+    /// version, rather than just the version requirement.
+    #[serde(default)]
+    pub use_lockfile: Option<bool>,
+}
+
+impl LspSettings {
+    /// Deserialize the settings field by field, so that a single malformed setting
+    /// is ignored rather than causing all other settings to be discarded.
+    fn lenient(value: serde_json::Value) -> Self {
+        let serde_json::Value::Object(fields) = value else {
+            tracing::warn!("ignoring settings which are not an object");
+            return LspSettings::default();
+        };
+
+        let valid: serde_json::Map<_, _> = fields
+            .into_iter()
+            .filter(|(key, value)| {
+                let field = serde_json::Map::from_iter([(key.clone(), value.clone())]);
+                let valid = serde_json::from_value::<LspSettings>(field.into()).is_ok();
+                if !valid {
+                    tracing::warn!(setting = key, "ignoring invalid setting");
+                }
+                valid
+            })
+            .collect();
+
+        serde_json::from_value(valid.into()).unwrap_or_default()
+    }
 }
 
+/// Order in which diagnostics are published. Editors may apply their own ordering,
+/// but some honor the server's order within their problems panel.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum DiagnosticOrder {
+    /// Leave the diagnostics in the order they were produced, by dependency.
+    #[default]
+    Document,
+    /// Most severe diagnostics first.
+    Severity,
+    /// Outdated crates first, then unknown crates, then everything else, and
+    /// up-to-date crates last.
+    Status,
+}
+
+/// Where the version inlay hint of a dependency is placed on its line.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum InlayHintAnchor {
+    /// Right after the version requirement.
+    #[default]
+    VersionEnd,
+    /// At the end of the line, after any features or comments following the requirement.
+    LineEnd,
+}
+
+/// Operator which version requirements are required to use, as a matter of policy.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RequirementStyle {
+    /// Explicit caret requirements, e.g. `^1.2.3`.
+    Caret,
+    /// Tilde requirements, e.g. `~1.2.3`.
+    Tilde,
+    /// Exact pins, e.g. `=1.2.3`.
+    Exact,
+    /// Any requirement is accepted.
+    #[default]
+    Any,
+}
+
+/// Settings which can be overridden for a directory tree using a `crates-lsp.toml` file.
 #[derive(Default, Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DirectorySettings {
+    #[serde(default)]
+    pub diagnostics: Option<bool>,
+    #[serde(default)]
+    pub inlay_hints: Option<bool>,
+}
+
+/// Settings read from the `[package.metadata.crates-lsp]` or `[workspace.metadata.crates-lsp]`
+/// table of a manifest, which take precedence over both `crates-lsp.toml` files and the
+/// settings provided by the editor.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManifestSettings {
+    #[serde(default)]
+    pub diagnostics: Option<bool>,
+    #[serde(default)]
+    pub inlay_hints: Option<bool>,
+    #[serde(default)]
+    pub needs_update_severity: Option<DiagnosticSeverity>,
+    /// Crates which are neither checked for updates nor given inlay hints.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+impl ManifestSettings {
+    /// Read the settings from the manifest's metadata table, if present. Invalid tables
+    /// are ignored, since they may just be in the middle of being edited.
+    pub fn from_manifest(source: &str) -> Self {
+        let Some(table) = parse::metadata_table(source) else {
+            return ManifestSettings::default();
+        };
+
+        match toml::from_str::<ManifestSettings>(&table) {
+            Ok(settings) => ManifestSettings {
+                needs_update_severity: settings.needs_update_severity.filter(verify_severity),
+                ..settings
+            },
+            Err(err) => {
+                tracing::debug!(%err, "ignoring invalid manifest metadata");
+                ManifestSettings::default()
+            }
+        }
+    }
+
+    pub fn is_ignored(&self, crate_name: &str) -> bool {
+        self.ignore.iter().any(|ignored| ignored == crate_name)
+    }
+}
+
+#[derive(Default, Debug, Clone, Deserialize, JsonSchema)]
 pub struct InnerSettings {
     lsp: LspSettings,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use time::OffsetDateTime;
+    use tower_lsp::lsp_types::DiagnosticSeverity;
+
+    use tower_lsp::lsp_types::Url;
+
+    use indoc::indoc;
+
+    use super::{
+        format_hint, humanize_age, ManifestSettings, Settings, DEFAULT_NEEDS_UPDATE_HINT,
+        DIRECTORY_SETTINGS_FILE,
+    };
+
+    #[tokio::test]
+    async fn populate_from_ignores_invalid_fields() {
+        let settings = Settings::default();
+        settings
+            .populate_from(json!({
+                "lsp": {
+                    "inlayHints": "yes",
+                    "useApi": true,
+                    "needsUpdateSeverity": 2,
+                    "unknownSetting": [1, 2, 3],
+                }
+            }))
+            .await;
+
+        assert!(settings.inlay_hints().await);
+        assert!(settings.use_api().await);
+        assert_eq!(
+            settings.needs_update_severity().await,
+            DiagnosticSeverity::WARNING
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_settings_override() {
+        let root = std::env::temp_dir().join("crates-lsp-test-directory-settings");
+        let member = root.join("member");
+        std::fs::create_dir_all(&member).unwrap();
+        std::fs::write(
+            root.join(DIRECTORY_SETTINGS_FILE),
+            "diagnostics = false\ninlay-hints = true\n",
+        )
+        .unwrap();
+        std::fs::write(
+            member.join(DIRECTORY_SETTINGS_FILE),
+            "inlay-hints = false\n",
+        )
+        .unwrap();
+
+        let settings = Settings::default();
+        let root_manifest = Url::from_file_path(root.join("Cargo.toml")).unwrap();
+        let member_manifest = Url::from_file_path(member.join("Cargo.toml")).unwrap();
+
+        assert!(!settings.diagnostics_for(&root_manifest).await);
+        assert!(settings.inlay_hints_for(&root_manifest).await);
+
+        // Only the nearest file applies, falling back to the LSP settings.
+        assert!(settings.diagnostics_for(&member_manifest).await);
+        assert!(!settings.inlay_hints_for(&member_manifest).await);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn directory_settings_reload() {
+        let root = std::env::temp_dir().join("crates-lsp-test-directory-settings-reload");
+        std::fs::create_dir_all(&root).unwrap();
+        let file = root.join(DIRECTORY_SETTINGS_FILE);
+        std::fs::write(&file, "diagnostics = false\n").unwrap();
+
+        let settings = Settings::default();
+        let manifest = Url::from_file_path(root.join("Cargo.toml")).unwrap();
+        assert!(!settings.diagnostics_for(&manifest).await);
+
+        // Changes only apply once the cached settings are forgotten.
+        std::fs::write(&file, "diagnostics = true\ninlay-hints = false\n").unwrap();
+        assert!(!settings.diagnostics_for(&manifest).await);
+        settings.forget_directory_settings(&file).await;
+        assert!(settings.diagnostics_for(&manifest).await);
+        assert!(!settings.inlay_hints_for(&manifest).await);
+
+        // Deleting the file reverts to the LSP settings.
+        std::fs::remove_file(&file).unwrap();
+        settings.forget_directory_settings(&file).await;
+        assert!(settings.inlay_hints_for(&manifest).await);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn directory_settings_for_non_file_urls() {
+        let settings = Settings::default();
+
+        for url in [
+            "untitled:Untitled-1",
+            "vscode-remote://ssh-remote+host/home/user/project/Cargo.toml",
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert!(settings.diagnostics_for(&url).await);
+            assert!(settings.inlay_hints_for(&url).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn completion_result_limit_range() {
+        let settings = Settings::default();
+        assert_eq!(settings.completion_result_limit().await, 5);
+
+        for (limit, expected) in [(20, 20), (100, 100), (0, 5), (101, 5)] {
+            settings
+                .populate_from(json!({ "lsp": { "completionResultLimit": limit } }))
+                .await;
+            assert_eq!(settings.completion_result_limit().await, expected);
+        }
+    }
+
+    #[test]
+    fn manifest_metadata_settings() {
+        let cargo = indoc! {r#"
+            [package]
+            name = "example"
+
+            [package.metadata.crates-lsp]
+            inlay-hints = false
+            needs-update-severity = 4
+            ignore = ["serde", "tokio"]
+
+            [dependencies]
+            serde = "1"
+        "#};
+
+        assert_eq!(
+            ManifestSettings::from_manifest(cargo),
+            ManifestSettings {
+                diagnostics: None,
+                inlay_hints: Some(false),
+                needs_update_severity: Some(DiagnosticSeverity::HINT),
+                ignore: vec!["serde".to_string(), "tokio".to_string()],
+            }
+        );
+
+        let workspace = indoc! {r#"
+            [workspace.metadata.crates-lsp]
+            diagnostics = false
+            needs-update-severity = 9
+        "#};
+
+        assert_eq!(
+            ManifestSettings::from_manifest(workspace),
+            ManifestSettings {
+                diagnostics: Some(false),
+                ..ManifestSettings::default()
+            }
+        );
+
+        // Tables which are still being edited don't break the rest of the manifest.
+        assert_eq!(
+            ManifestSettings::from_manifest("[package.metadata.crates-lsp]\nignore = [\"ser\n"),
+            ManifestSettings::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn effective_settings_include_defaults() {
+        let settings = Settings::default();
+        settings
+            .populate_from(json!({ "lsp": { "useApi": true } }))
+            .await;
+
+        let effective = settings.effective().await;
+        assert_eq!(effective["useApi"], json!(true));
+        assert_eq!(effective["inlayHints"], json!(true));
+        assert_eq!(effective["diagnosticOrder"], json!("document"));
+        assert_eq!(effective["upToDateHint"], json!("✓"));
+    }
+
+    #[tokio::test]
+    async fn always_show_latest_templates() {
+        let settings = Settings::default();
+        settings
+            .populate_from(json!({
+                "lsp": {
+                    "upToDateHint": "",
+                    "needsUpdateHint": "",
+                }
+            }))
+            .await;
+        assert_eq!(
+            settings.hint_templates().await,
+            (String::new(), String::new())
+        );
+
+        // Every dependency is hinted, even though both templates are disabled.
+        settings
+            .populate_from(json!({
+                "lsp": {
+                    "upToDateHint": "",
+                    "needsUpdateHint": "",
+                    "alwaysShowLatest": true,
+                }
+            }))
+            .await;
+        assert_eq!(
+            settings.hint_templates().await,
+            (
+                "✓ {latest}".to_string(),
+                DEFAULT_NEEDS_UPDATE_HINT.to_string()
+            )
+        );
+
+        settings
+            .populate_from(json!({
+                "lsp": {
+                    "needsUpdateHint": "{current} → {latest}",
+                    "alwaysShowLatest": true,
+                    "latestHint": "(latest: {latest})",
+                }
+            }))
+            .await;
+        assert_eq!(
+            settings.hint_templates().await,
+            (
+                "(latest: {latest})".to_string(),
+                "{current} → {latest}".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn format_hint_placeholders() {
+        assert_eq!(
+            format_hint("{current} → {latest}", "1.2.0", "1.3.0", ""),
+            "1.2.0 → 1.3.0"
+        );
+        assert_eq!(format_hint(" {}", "1.2.0", "1.3.0", ""), " {}");
+        assert_eq!(
+            format_hint("{latest} {age}", "1.2.0", "1.3.0", "2 weeks ago"),
+            "1.3.0 2 weeks ago"
+        );
+    }
+
+    #[test]
+    fn humanized_ages() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let age = |days: i64| humanize_age(Some(now - time::Duration::days(days)), now);
+
+        assert_eq!(humanize_age(None, now), "");
+        assert_eq!(age(0), "today");
+        assert_eq!(age(1), "1 day ago");
+        assert_eq!(age(6), "6 days ago");
+        assert_eq!(age(15), "2 weeks ago");
+        assert_eq!(age(90), "3 months ago");
+        assert_eq!(age(800), "2 years ago");
+        assert_eq!(
+            age(-3),
+            "today",
+            "clock skew shouldn't produce negative ages"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::crates::Release;
+use crate::parse::{DependencySection, DependencyVersion, DependencyWithVersion};
+use crate::requirement::{self, Status};
+
+/// Summarize the status of the dependencies declared within the section, for display
+/// next to its header, e.g. `(3 outdated, 12 up to date)`.
+///
+/// Dependencies whose newest release hasn't been looked up are left out, and sections
+/// without any remaining dependencies aren't summarized at all.
+pub fn section_summary(
+    section: &DependencySection,
+    dependencies: &[DependencyWithVersion],
+    newest_packages: &HashMap<String, Option<Release>>,
+) -> Option<String> {
+    let counts = count(
+        dependencies.iter().filter(|dep| section.contains(dep)),
+        newest_packages,
+    );
+
+    let parts: Vec<_> = [
+        (counts.outdated, "outdated"),
+        (counts.up_to_date, "up to date"),
+        (counts.unknown, "unknown"),
+    ]
+    .into_iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(count, status)| format!("{count} {status}"))
+    .collect();
+
+    (!parts.is_empty()).then(|| format!("({})", parts.join(", ")))
+}
+
+/// Number of dependencies by status, as reported by the `crates-lsp/status` request.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusCounts {
+    /// Every dependency with a version requirement, including those whose newest
+    /// release hasn't been looked up yet.
+    pub dependencies: usize,
+    pub outdated: usize,
+    pub up_to_date: usize,
+    /// Dependencies on crates which don't exist in the registry.
+    pub unknown: usize,
+}
+
+/// Count the dependencies by their status according to the newest releases.
+pub fn count<'a>(
+    dependencies: impl IntoIterator<Item = &'a DependencyWithVersion>,
+    newest_packages: &HashMap<String, Option<Release>>,
+) -> StatusCounts {
+    let mut counts = StatusCounts::default();
+
+    for dependency in dependencies {
+        counts.dependencies += 1;
+
+        let DependencyVersion::Complete { version, .. } = &dependency.version else {
+            continue;
+        };
+
+        match newest_packages.get(&dependency.name) {
+            Some(Some(release)) => {
+                match requirement::status(&version.to_string(), version, release) {
+                    Status::UpToDate => counts.up_to_date += 1,
+                    Status::Outdated { .. } | Status::Yanked { .. } => counts.outdated += 1,
+                }
+            }
+            Some(None) => counts.unknown += 1,
+            None => (),
+        }
+    }
+
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::crates::newest_packages;
+    use crate::parse::{dependency_sections, parse_manifest, versioned};
+
+    use super::{count, section_summary, StatusCounts};
+
+    #[test]
+    fn summarize_sections() {
+        let cargo = indoc! {r#"
+            [dependencies]
+            serde = "1"
+            tokio = "0.2"
+            anyhow = "1"
+            unknown-crate = "1"
+
+            [dev-dependencies]
+            uncached = "1"
+
+            [build-dependencies]
+        "#};
+
+        let newest_packages = newest_packages();
+
+        let dependencies: Vec<_> = versioned(&parse_manifest(cargo))
+            .into_iter()
+            .cloned()
+            .collect();
+
+        let summaries: Vec<_> = dependency_sections(cargo)
+            .iter()
+            .map(|section| section_summary(section, &dependencies, &newest_packages))
+            .collect();
+
+        assert_eq!(
+            summaries,
+            vec![
+                Some("(1 outdated, 2 up to date, 1 unknown)".to_string()),
+                None,
+                None,
+            ]
+        );
+
+        assert_eq!(
+            count(&dependencies, &newest_packages),
+            StatusCounts {
+                dependencies: 5,
+                outdated: 1,
+                up_to_date: 2,
+                unknown: 1,
+            }
+        );
+        assert_eq!(count(&[], &newest_packages), StatusCounts::default());
+    }
+}